@@ -1,46 +1,501 @@
+use std::fmt;
+
+/// Byte range into the source text a node was parsed from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Program {
     pub functions: Vec<Func>,
+    /// File scope declarations other than functions, e.g. `struct point { int x; int y; };`
+    pub declarations: Vec<Statement>,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Func {
     pub return_type: Type,
     pub name: String,
-    pub block_items: Vec<Statement>,
+    /// `None` for a declaration without a body, e.g. `int f(int x);`
+    pub block_items: Option<Vec<Statement>>,
     pub params: Vec<FuncParam>,
+    /// Whether the parameter list ends in `, ...`
+    pub variadic: bool,
+    pub span: Span,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FuncParam {
     pub param_type: Type,
+    /// Empty for an unnamed parameter, e.g. of the prototype `int f(int);`
     pub param_name: String,
+    pub span: Span,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Type {
+    /// Plain `char`, signed as on Apple arm64
+    Char,
+    SignedChar,
+    UnsignedChar,
+    Short,
+    UnsignedShort,
     Int,
+    UnsignedInt,
+    Long,
+    UnsignedLong,
+    LongLong,
+    UnsignedLongLong,
+    Float,
+    Double,
     Void,
+    Pointer(Box<Type>),
+    Array(Box<Type>, usize),
+    /// A `struct` or `union`, its layout is kept in `layout::Layouts`
+    Struct(Box<StructType>),
+    /// An `enum`, which semantic analysis replaces by `int`
+    Enum(Box<EnumType>),
+    /// A name declared by `typedef`, which semantic analysis replaces by the type it stands for
+    Typedef(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StructKind {
+    Struct,
+    Union,
+}
+
+/// A `struct` or `union` type as written, e.g. `struct point` or `union { int i; float f; }`
+#[derive(Debug, Clone, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StructType {
+    pub kind: StructKind,
+    /// `None` for an anonymous type
+    pub tag: Option<String>,
+    /// Present where the type is defined rather than just referred to
+    pub members: Option<Vec<Member>>,
+    /// Index into the layout table, filled in by semantic analysis
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub id: Option<usize>,
 }
 
+// once resolved, the definition and every reference to it are the same type
+impl PartialEq for StructType {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.id, other.id) {
+            (Some(a), Some(b)) => a == b,
+            _ => self.kind == other.kind && self.tag == other.tag && self.members == other.members,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Member {
+    pub member_type: Type,
+    pub name: String,
+    pub span: Span,
+}
+
+/// An `enum` type as written, e.g. `enum color` or `enum { RED, GREEN = 4 }`
 #[derive(Debug, Clone)]
-pub enum Expr {
-    Const(i32),
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EnumType {
+    pub tag: Option<String>,
+    /// Present where the type is defined rather than just referred to
+    pub enumerators: Option<Vec<Enumerator>>,
+}
+
+// enumerator values are expressions, which have no equality, and the type is gone after analysis
+impl PartialEq for EnumType {
+    fn eq(&self, other: &Self) -> bool {
+        self.tag.is_some() && self.tag == other.tag
+    }
+}
+
+impl Eq for EnumType {}
+
+/// `NAME` or `NAME = value` in the body of an `enum`
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Enumerator {
+    pub name: String,
+    pub value: Option<Expr>,
+    pub span: Span,
+}
+
+/// A keyword of a declaration's type, e.g. the `unsigned` and `long` of `unsigned long x`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TypeSpecifier {
+    Void,
+    Char,
+    Short,
+    Int,
+    Long,
+    Float,
+    Double,
+    Signed,
+    Unsigned,
+}
+
+impl Type {
+    pub fn pointer_to(pointee: Type) -> Type {
+        Type::Pointer(Box::new(pointee))
+    }
+
+    pub fn array_of(element: Type, len: usize) -> Type {
+        Type::Array(Box::new(element), len)
+    }
+
+    /// Combines the specifiers of a declaration, which may come in any order, e.g. `long unsigned int`
+    pub fn from_specifiers(specifiers: &[TypeSpecifier]) -> Result<Type, &'static str> {
+        let count = |wanted| specifiers.iter().filter(|s| **s == wanted).count();
+        let (signed, unsigned) = (count(TypeSpecifier::Signed), count(TypeSpecifier::Unsigned));
+        let (short, long) = (count(TypeSpecifier::Short), count(TypeSpecifier::Long));
+        let bases = [
+            TypeSpecifier::Void,
+            TypeSpecifier::Char,
+            TypeSpecifier::Int,
+            TypeSpecifier::Float,
+            TypeSpecifier::Double,
+        ]
+        .into_iter()
+        .filter(|base| count(*base) > 0)
+        .collect::<Vec<_>>();
+
+        if signed + unsigned > 1 || short > 1 || long > 2 || (short > 0 && long > 0) {
+            return Err("invalid combination of type specifiers");
+        }
+        if bases.len() > 1 || bases.iter().any(|base| count(*base) > 1) {
+            return Err("two or more data types in declaration specifiers");
+        }
+        let sign_given = signed + unsigned > 0;
+        let ty = match (bases.first(), short, long) {
+            (Some(TypeSpecifier::Void), 0, 0) if !sign_given => Type::Void,
+            (Some(TypeSpecifier::Float), 0, 0) if !sign_given => Type::Float,
+            // `long double` is treated as `double`
+            (Some(TypeSpecifier::Double), 0, 0 | 1) if !sign_given => Type::Double,
+            (Some(TypeSpecifier::Char), 0, 0) => match (signed, unsigned) {
+                (1, _) => Type::SignedChar,
+                (_, 1) => Type::UnsignedChar,
+                _ => Type::Char,
+            },
+            (None | Some(TypeSpecifier::Int), 1, 0) => Type::Short,
+            (None | Some(TypeSpecifier::Int), 0, 0) => Type::Int,
+            (None | Some(TypeSpecifier::Int), 0, 1) => Type::Long,
+            (None | Some(TypeSpecifier::Int), 0, 2) => Type::LongLong,
+            _ => return Err("invalid combination of type specifiers"),
+        };
+        Ok(if unsigned > 0 { ty.to_unsigned() } else { ty })
+    }
+
+    /// Type of `sizeof` and `_Alignof`, as `size_t` is on arm64
+    pub fn size_t() -> Type {
+        Type::UnsignedLong
+    }
+
+    pub fn is_pointer(&self) -> bool {
+        matches!(self, Type::Pointer(_))
+    }
+
+    pub fn is_array(&self) -> bool {
+        matches!(self, Type::Array(..))
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.rank().is_some()
+    }
+
+    pub fn is_struct(&self) -> bool {
+        matches!(self, Type::Struct(_))
+    }
+
+    pub fn is_floating(&self) -> bool {
+        matches!(self, Type::Float | Type::Double)
+    }
+
+    pub fn is_arithmetic(&self) -> bool {
+        self.is_integer() || self.is_floating()
+    }
+
+    pub fn is_char(&self) -> bool {
+        matches!(self, Type::Char | Type::SignedChar | Type::UnsignedChar)
+    }
+
+    /// Whether values of this integer type are sign-extended when loaded.
+    /// Pointers count as unsigned, so they are compared as addresses.
+    pub fn is_signed(&self) -> bool {
+        matches!(
+            self,
+            Type::Char | Type::SignedChar | Type::Short | Type::Int | Type::Long | Type::LongLong
+        )
+    }
+
+    /// Types that can be used as a condition or compared against zero
+    pub fn is_scalar(&self) -> bool {
+        self.is_arithmetic() || self.is_pointer()
+    }
+
+    /// Integer conversion rank, `None` for non-integer types
+    fn rank(&self) -> Option<u8> {
+        match self {
+            Type::Char | Type::SignedChar | Type::UnsignedChar => Some(1),
+            Type::Short | Type::UnsignedShort => Some(2),
+            Type::Int | Type::UnsignedInt => Some(3),
+            Type::Long | Type::UnsignedLong => Some(4),
+            Type::LongLong | Type::UnsignedLongLong => Some(5),
+            _ => None,
+        }
+    }
+
+    /// The unsigned integer type of the same rank
+    pub fn to_unsigned(&self) -> Type {
+        match self {
+            Type::Char | Type::SignedChar => Type::UnsignedChar,
+            Type::Short => Type::UnsignedShort,
+            Type::Int => Type::UnsignedInt,
+            Type::Long => Type::UnsignedLong,
+            Type::LongLong => Type::UnsignedLongLong,
+            _ => self.clone(),
+        }
+    }
+
+    /// Integer promotion: everything narrower than `int` fits into `int` and becomes one
+    pub fn promote(&self) -> Type {
+        match self.rank() {
+            Some(rank) if rank < 3 => Type::Int,
+            _ => self.clone(),
+        }
+    }
+
+    /// The usual arithmetic conversions, giving the common type of two arithmetic operands
+    pub fn common(a: &Type, b: &Type) -> Type {
+        if a.is_floating() || b.is_floating() {
+            return if *a == Type::Double || *b == Type::Double {
+                Type::Double
+            } else {
+                Type::Float
+            };
+        }
+        let (a, b) = (a.promote(), b.promote());
+        if a == b {
+            return a;
+        }
+        let (high, low) = if a.rank() >= b.rank() { (a, b) } else { (b, a) };
+        if high.is_signed() == low.is_signed() || !high.is_signed() {
+            high
+        } else if high.size() > low.size() {
+            // the signed type can represent every value of the unsigned one
+            high
+        } else {
+            high.to_unsigned()
+        }
+    }
+
+    /// Size in bytes of a value of this type in memory.
+    /// Types containing a `struct` need `Layouts::size_of` instead.
+    pub fn size(&self) -> usize {
+        match self {
+            Type::Char | Type::SignedChar | Type::UnsignedChar => 1,
+            Type::Short | Type::UnsignedShort => 2,
+            Type::Int | Type::UnsignedInt => 4,
+            Type::Long | Type::UnsignedLong | Type::LongLong | Type::UnsignedLongLong => 8,
+            Type::Float => 4,
+            Type::Double => 8,
+            Type::Void => 1, // GNU-style, so `void *` arithmetic steps by bytes
+            Type::Pointer(_) => 8,
+            Type::Array(element, len) => element.size() * len,
+            Type::Struct(_) => unreachable!("struct sizes are kept in the layout table"),
+            Type::Enum(_) | Type::Typedef(_) => unreachable!("resolved by semantic analysis"),
+        }
+    }
+
+    /// Required alignment in bytes of a value of this type in memory
+    pub fn align(&self) -> usize {
+        match self {
+            Type::Array(element, _) => element.align(),
+            _ => self.size(),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Char => write!(f, "char"),
+            Type::SignedChar => write!(f, "signed char"),
+            Type::UnsignedChar => write!(f, "unsigned char"),
+            Type::Short => write!(f, "short"),
+            Type::UnsignedShort => write!(f, "unsigned short"),
+            Type::Int => write!(f, "int"),
+            Type::UnsignedInt => write!(f, "unsigned int"),
+            Type::Long => write!(f, "long"),
+            Type::UnsignedLong => write!(f, "unsigned long"),
+            Type::LongLong => write!(f, "long long"),
+            Type::UnsignedLongLong => write!(f, "unsigned long long"),
+            Type::Float => write!(f, "float"),
+            Type::Double => write!(f, "double"),
+            Type::Void => write!(f, "void"),
+            Type::Pointer(pointee) => match **pointee {
+                Type::Pointer(_) => write!(f, "{}*", pointee),
+                _ => write!(f, "{} *", pointee),
+            },
+            Type::Array(element, len) => {
+                // dimensions are written outermost first, e.g. `int[2][3]`
+                let mut dims = vec![*len];
+                let mut base = &**element;
+                while let Type::Array(inner, len) = base {
+                    dims.push(*len);
+                    base = inner;
+                }
+                write!(f, "{}", base)?;
+                for len in dims {
+                    write!(f, "[{}]", len)?;
+                }
+                Ok(())
+            }
+            Type::Struct(struct_type) => write!(f, "{}", struct_type),
+            Type::Enum(enum_type) => match &enum_type.tag {
+                Some(tag) => write!(f, "enum {}", tag),
+                None => write!(f, "enum <anonymous>"),
+            },
+            Type::Typedef(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl fmt::Display for StructKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StructKind::Struct => write!(f, "struct"),
+            StructKind::Union => write!(f, "union"),
+        }
+    }
+}
+
+impl fmt::Display for StructType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.tag {
+            Some(tag) => write!(f, "{} {}", self.kind, tag),
+            None => write!(f, "{} <anonymous>", self.kind),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+    /// Filled in by semantic analysis
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub ty: Option<Type>,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self {
+            kind,
+            span,
+            ty: None,
+        }
+    }
+
+    /// Type computed during semantic analysis
+    pub fn ty(&self) -> &Type {
+        self.ty
+            .as_ref()
+            .expect("expression type is only known after semantic analysis")
+    }
+
+    /// Whether the expression designates an object that can be assigned to or have its address taken
+    pub fn is_lvalue(&self) -> bool {
+        match &self.kind {
+            ExprKind::Var(_)
+            | ExprKind::Deref(_)
+            | ExprKind::Subscript(..)
+            | ExprKind::String(_) => true,
+            ExprKind::Group(inner) | ExprKind::Member(inner, _) => inner.is_lvalue(),
+            ExprKind::PtrMember(..) => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ExprKind {
+    /// Integer constant and the type C gives it from its value and suffix
+    Const(u64, Type),
+    /// Floating-point constant, a `double` unless it has an `f` suffix
+    FloatConst(f64, Type),
+    /// Character literal such as `'a'`, which has type `int`
+    Char(u8),
+    /// String literal without its terminating NUL; adjacent literals are already concatenated
+    String(Vec<u8>),
     Var(String),
     Unary(UnaryOp, Box<Expr>),
     Binary(BinaryOp, Box<Expr>, Box<Expr>),
     Group(Box<Expr>),
-    Assignment(String, Box<Expr>),
+    Assignment(Box<Expr>, Box<Expr>),
+    /// `a += b`, which unlike `a = a + b` evaluates `a` only once
+    CompoundAssignment(BinaryOp, Box<Expr>, Box<Expr>),
     Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    AddressOf(Box<Expr>),
+    Deref(Box<Expr>),
+    Subscript(Box<Expr>, Box<Expr>),
+    /// Implicit conversion of the inner expression to this node's type, inserted by semantic analysis
+    Convert(Box<Expr>),
+    Call(String, Vec<Expr>),
+    /// Explicit conversion written in the source, e.g. `(long)p`
+    Cast(Type, Box<Expr>),
+    /// `s.member`
+    Member(Box<Expr>, String),
+    /// `p->member`
+    PtrMember(Box<Expr>, String),
+    /// `sizeof x`, whose operand is never evaluated
+    SizeOf(Box<Expr>),
+    /// `sizeof(int)`
+    SizeOfType(Type),
+    /// `_Alignof(int)`
+    AlignOf(Type),
+    /// `a, b` evaluates `a` for its side effects only, the value is that of `b`
+    Comma(Box<Expr>, Box<Expr>),
+    /// `__builtin_va_start(ap, last)`, pointing the `va_list` at the first variadic argument.
+    /// The last named parameter is optional, as in C23.
+    VaStart(Box<Expr>, Option<Box<Expr>>),
+    /// `__builtin_va_arg(ap, int)` takes the next variadic argument
+    VaArg(Box<Expr>, Type),
+    /// `__builtin_va_end(ap)`
+    VaEnd(Box<Expr>),
+    /// `__builtin_va_copy(dest, src)`
+    VaCopy(Box<Expr>, Box<Expr>),
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum UnaryOp {
     Negate,
     BitwiseNegate,
     Negative,
 }
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BinaryOp {
     // Arithmetic operators
     Add,
@@ -59,11 +514,83 @@ pub enum BinaryOp {
     LogicalOr,
 }
 
-#[derive(Debug)]
-pub enum Statement {
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            UnaryOp::Negate => "!",
+            UnaryOp::BitwiseNegate => "~",
+            UnaryOp::Negative => "-",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+            BinaryOp::LogicalAnd => "&&",
+            BinaryOp::LogicalOr => "||",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+/// Initial value of a declared variable
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Initializer {
+    Single(Expr),
+    /// Brace-enclosed list, e.g. `{1, 2, 3}`; missing trailing elements are zeroed
+    List(Vec<Initializer>, Span),
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+impl Statement {
+    pub fn new(kind: StatementKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StatementKind {
     Return(Expr),
     Expr(Expr),
-    Declare(Type, String, Option<Expr>),
+    Declare(Type, String, Option<Initializer>),
+    /// Declaration of just a type, e.g. `struct point { int x; int y; };`
+    DeclareType(Type),
+    /// `typedef unsigned long size_t;`
+    Typedef(Type, String),
     If(Expr, Box<Statement>, Option<Box<Statement>>),
+    /// `switch (x) { ... }`, the `case` and `default` labels are somewhere in the body
+    Switch(Expr, Box<Statement>),
+    /// `case 1: ...`, semantic analysis turns the label into a constant of the switch's type
+    Case(Expr, Box<Statement>),
+    Default(Box<Statement>),
+    /// Leaves the innermost `switch`
+    Break,
+    /// `name: ...`, a target for `goto` anywhere in the function
+    Labeled(String, Box<Statement>),
+    Goto(String),
+    /// The null statement `;`
+    Empty,
     Compound(Vec<Statement>),
+    /// Placeholder for a statement the parser recovered from
+    Error,
 }
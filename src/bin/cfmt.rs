@@ -0,0 +1,140 @@
+//! Formats C files in place.
+//!
+//! ```text
+//! cfmt [--check] [--indent width] [--braces attach | linux | allman] [-I dir]... file...
+//! ```
+//!
+//! `--check` only lists the files that are not formatted, and fails if there are any. `-`
+//! formats stdin to stdout. The headers a file includes are searched in the `-I` directories,
+//! for the typedef names they declare.
+
+use std::io::Read;
+use std::process;
+
+use compiler::error::CompilerError;
+use compiler::format::{self, Style};
+use compiler::preprocess::Preprocessor;
+
+struct Args {
+    files: Vec<String>,
+    check: bool,
+    style: Style,
+    include_dirs: Vec<String>,
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Args, CompilerError> {
+    let mut parsed = Args {
+        files: Vec::new(),
+        check: false,
+        style: Style::default(),
+        include_dirs: Vec::new(),
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value)),
+            _ => (arg.clone(), None),
+        };
+        let mut value = |flag: &str| match value {
+            Some(value) => Ok(value.to_string()),
+            None => args
+                .next()
+                .ok_or_else(|| CompilerError::Usage(format!("missing argument to '{flag}'"))),
+        };
+        match flag.as_str() {
+            "--check" => parsed.check = true,
+            "--indent" => {
+                let width = value(&flag)?;
+                parsed.style.indent_width = width
+                    .parse()
+                    .map_err(|_| CompilerError::Usage(format!("invalid indent width '{width}'")))?;
+            }
+            "--braces" => {
+                parsed.style.braces = value(&flag)?.parse().map_err(CompilerError::Usage)?
+            }
+            "-I" => parsed.include_dirs.push(value(&flag)?),
+            _ if arg.starts_with("-I") => parsed.include_dirs.push(arg[2..].to_string()),
+            "-" => parsed.files.push(arg),
+            _ if arg.starts_with('-') => {
+                return Err(CompilerError::Usage(format!("unknown argument '{arg}'")));
+            }
+            _ => parsed.files.push(arg),
+        }
+    }
+    if parsed.files.is_empty() {
+        return Err(CompilerError::NoInputFile);
+    }
+    Ok(parsed)
+}
+
+// the typedef names of the headers `source` includes, none if it does not preprocess
+fn header_typedefs(args: &Args, path: &str, source: &str) -> Vec<String> {
+    let mut preprocessor = Preprocessor::new();
+    for dir in &args.include_dirs {
+        preprocessor.include_dir(dir);
+    }
+    match preprocessor.preprocess(path, source) {
+        Ok(text) => format::typedef_names(&text),
+        Err(_) => Vec::new(),
+    }
+}
+
+// formats the file at `path`, whether it was formatted already
+fn format_file(args: &Args, path: &str) -> Result<bool, CompilerError> {
+    let source = match path {
+        "-" => {
+            let mut source = String::new();
+            std::io::stdin().read_to_string(&mut source)?;
+            source
+        }
+        _ => std::fs::read_to_string(path)?,
+    };
+    let typedefs = header_typedefs(args, path, &source);
+    let formatted = match format::format(&source, &args.style, &typedefs) {
+        Ok(formatted) => formatted,
+        Err(diagnostics) => {
+            eprint!("{}", diagnostics.render(path, &source));
+            return Err(CompilerError::Aborted(diagnostics.error_count()));
+        }
+    };
+    if path == "-" && !args.check {
+        print!("{formatted}");
+    } else if formatted != source && !args.check {
+        std::fs::write(path, &formatted)?;
+    }
+    Ok(formatted == source)
+}
+
+fn main() {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error[{}]: {}", e.code(), e);
+            process::exit(1);
+        }
+    };
+    let mut failed = false;
+    for path in &args.files {
+        match format_file(&args, path) {
+            Ok(true) => {}
+            Ok(false) => {
+                if args.check {
+                    println!("{path}");
+                    failed = true;
+                }
+            }
+            // the diagnostics were already reported
+            Err(e @ CompilerError::Aborted(_)) => {
+                eprintln!("error: {path}: {e}");
+                failed = true;
+            }
+            Err(e) => {
+                eprintln!("error[{}]: {path}: {e}", e.code());
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
@@ -1,6 +1,8 @@
 use std::process;
 
 use compiler::grammar;
+use compiler::lexer::{Lexer, TypedefNames};
+use compiler::preprocess::Preprocessor;
 use compiler::pretty_print;
 
 fn main() {
@@ -12,18 +14,25 @@ fn main() {
             process::exit(1);
         }
     };
-    let input = std::fs::read_to_string(&path).unwrap_or_else(|err| {
-        eprintln!("Error: Failed to read file '{}': {}", path, err);
-        process::exit(1);
-    });
+    let input = Preprocessor::new()
+        .preprocess_file(&path)
+        .unwrap_or_else(|err| {
+            eprintln!("Error: Failed to preprocess file '{}': {}", path, err);
+            process::exit(1);
+        });
 
     // parse and pretty print
+    let mut errors = Vec::new();
+    let names = TypedefNames::new();
     let program = grammar::ProgramParser::new()
-        .parse(&input)
+        .parse(&mut errors, &names, Lexer::new(&input, &names))
         .unwrap_or_else(|e| {
             eprintln!("Error: Failed to parse input: {:?}", e);
             process::exit(1);
         });
+    for recovered in errors {
+        eprintln!("Error: Failed to parse input: {:?}", recovered.error);
+    }
 
     pretty_print::pretty_print(&program);
 }
@@ -0,0 +1,132 @@
+//! Control flow graphs of generated assembly, as Graphviz `dot` source.
+//!
+//! Each function becomes a cluster of basic blocks, split at labels and after branches.
+//! `bl` calls stay inside their block, as they return to it.
+
+use itertools::Itertools;
+
+struct Block {
+    name: String,
+    lines: Vec<String>,
+    successors: Vec<String>,
+    /// Whether it ends in a branch
+    ended: bool,
+    /// Whether execution can continue into the next block
+    falls_through: bool,
+}
+
+/// The control flow graph of the code sections of `asm`
+pub fn dot(asm: &str) -> String {
+    let mut functions: Vec<(String, Vec<Block>)> = Vec::new();
+    let mut globals = Vec::new();
+    let mut in_code = true;
+    for line in asm.lines() {
+        let trimmed = line.trim();
+        if let Some(symbol) = trimmed.strip_prefix(".global ") {
+            globals.push(symbol.to_string());
+            continue;
+        }
+        if trimmed == ".text" {
+            in_code = true;
+        } else if trimmed.starts_with(".section") || trimmed == ".data" || trimmed == ".bss" {
+            in_code = false;
+        }
+        if !in_code || trimmed.is_empty() || trimmed.starts_with('.') && !trimmed.ends_with(':') {
+            continue;
+        }
+
+        if let Some(label) = trimmed.strip_suffix(':') {
+            if globals.iter().any(|global| global == label) {
+                functions.push((label.to_string(), Vec::new()));
+            }
+            let Some((_, blocks)) = functions.last_mut() else {
+                continue;
+            };
+            if let Some(previous) = blocks.last_mut() {
+                if previous.falls_through {
+                    previous.successors.push(label.to_string());
+                }
+            }
+            blocks.push(Block::new(label.to_string()));
+            continue;
+        }
+
+        let Some((function, blocks)) = functions.last_mut() else {
+            continue;
+        };
+        // a branch ends its block, what follows without a label starts a new one
+        if blocks.last().is_none_or(|block| block.ended) {
+            let name = format!("{function}+{}", blocks.len());
+            if let Some(previous) = blocks.last_mut() {
+                if previous.falls_through {
+                    previous.successors.push(name.clone());
+                }
+            }
+            blocks.push(Block::new(name));
+        }
+        let block = blocks.last_mut().unwrap();
+        block.lines.push(trimmed.to_string());
+        if let Some((target, continues)) = branch(trimmed) {
+            block.successors.extend(target.map(str::to_string));
+            block.ended = true;
+            block.falls_through = continues;
+        }
+    }
+
+    let mut out = String::from("digraph cfg {\n\tnode [shape=box, fontname=monospace];\n");
+    for (function, blocks) in &functions {
+        out.push_str(&format!("\tsubgraph \"cluster_{function}\" {{\n"));
+        out.push_str(&format!("\t\tlabel = \"{function}\";\n"));
+        for block in blocks {
+            // `\l` ends a left-aligned line
+            let text = block.lines.iter().map(|line| escape(line)).join("\\l");
+            let label = escape(&block.name);
+            out.push_str(&format!(
+                "\t\t\"{label}\" [label=\"{label}:\\l{text}\\l\"];\n"
+            ));
+        }
+        out.push_str("\t}\n");
+        for block in blocks {
+            for successor in &block.successors {
+                let (from, to) = (escape(&block.name), escape(successor));
+                out.push_str(&format!("\t\"{from}\" -> \"{to}\";\n"));
+            }
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+impl Block {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            lines: Vec::new(),
+            successors: Vec::new(),
+            ended: false,
+            falls_through: true,
+        }
+    }
+}
+
+// for an instruction ending a block, where it jumps and whether it may continue with the next one
+fn branch(instruction: &str) -> Option<(Option<&str>, bool)> {
+    let (mnemonic, operands) = instruction
+        .split_once(char::is_whitespace)
+        .unwrap_or((instruction, ""));
+    // the label is the last operand
+    let target = operands.rsplit(',').next().map(str::trim);
+    match mnemonic {
+        "b" => Some((target, false)),
+        "ret" | "br" => Some((None, false)),
+        "cbz" | "cbnz" | "tbz" | "tbnz" => Some((target, true)),
+        _ if mnemonic.starts_with("b.") => Some((target, true)),
+        _ => None,
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\t', " ")
+}
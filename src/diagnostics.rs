@@ -0,0 +1,229 @@
+use std::fmt::{self, Write};
+
+use lalrpop_util::ParseError;
+
+use crate::ast::Span;
+use crate::error::CompilerError;
+use crate::lexer::Token;
+use crate::preprocess;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Error raised by a grammar action, e.g. for a malformed literal, reported as a parse error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub message: &'static str,
+    pub span: Span,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// A single error or warning, pointing at the part of the source it is about.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+    /// Secondary locations, e.g. where a conflicting declaration lives
+    pub notes: Vec<(Span, String)>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, error: CompilerError, span: Span) -> Self {
+        Self {
+            severity,
+            code: error.code(),
+            message: error.to_string(),
+            span,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn error(error: CompilerError, span: Span) -> Self {
+        Self::new(Severity::Error, error, span)
+    }
+
+    pub fn warning(error: CompilerError, span: Span) -> Self {
+        Self::new(Severity::Warning, error, span)
+    }
+
+    pub fn with_note(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.notes.push((span, message.into()));
+        self
+    }
+}
+
+impl From<ParseError<usize, Token<'_>, SyntaxError>> for Diagnostic {
+    fn from(error: ParseError<usize, Token<'_>, SyntaxError>) -> Self {
+        let (message, span) = match error {
+            ParseError::InvalidToken { location } => {
+                ("invalid token".to_string(), Span::new(location, location + 1))
+            }
+            ParseError::UnrecognizedEof { location, expected } => (
+                format!("unexpected end of file{}", format_expected(&expected)),
+                Span::new(location, location),
+            ),
+            ParseError::UnrecognizedToken {
+                token: (l, token, r),
+                expected,
+            } => (
+                format!("unexpected `{}`{}", token, format_expected(&expected)),
+                Span::new(l, r),
+            ),
+            ParseError::ExtraToken { token: (l, token, r) } => {
+                (format!("extra token `{}`", token), Span::new(l, r))
+            }
+            ParseError::User { error } => (error.message.to_string(), error.span),
+        };
+        Diagnostic::error(CompilerError::Parse(message), span)
+    }
+}
+
+fn format_expected(expected: &[String]) -> String {
+    match expected {
+        [] => String::new(),
+        [one] => format!(", expected {}", one),
+        many => format!(", expected one of {}", many.join(", ")),
+    }
+}
+
+/// Sink that collects diagnostics from every stage of the pipeline.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    items: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self { items: Vec::new() }
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.items.push(diagnostic);
+    }
+
+    pub fn error(&mut self, error: CompilerError, span: Span) {
+        self.push(Diagnostic::error(error, span));
+    }
+
+    pub fn warning(&mut self, error: CompilerError, span: Span) {
+        self.push(Diagnostic::warning(error, span));
+    }
+
+    pub fn extend(&mut self, other: Diagnostics) {
+        self.items.extend(other.items);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.items.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.items
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+            .count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.error_count() > 0
+    }
+
+    /// Renders every diagnostic rustc-style, with the offending source line underlined.
+    pub fn render(&self, path: &str, source: &str) -> String {
+        let mut out = String::new();
+        for d in self.items.iter() {
+            let _ = writeln!(out, "{}[{}]: {}", d.severity, d.code, d.message);
+            render_snippet(&mut out, path, source, d.span);
+            for (span, note) in d.notes.iter() {
+                let _ = writeln!(out, "note: {}", note);
+                render_snippet(&mut out, path, source, *span);
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// 1-based line and column of a byte offset
+pub fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let offset = clamp(source, offset);
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let col = source[line_start..offset].chars().count() + 1;
+    (line, col)
+}
+
+/// File, 1-based line and column of a byte offset into preprocessed source. The line markers
+/// left by the preprocessor tell which file and line the lines after them come from.
+pub fn locate(source: &str, offset: usize) -> (Option<String>, usize, usize) {
+    let (physical, col) = line_col(source, offset);
+    let (mut file, mut line) = (None, 1);
+    for text in source.lines().take(physical - 1) {
+        match preprocess::parse_line_marker(text) {
+            Some((name, number)) => (file, line) = (Some(name), number),
+            None => line += 1,
+        }
+    }
+    (file, line, col)
+}
+
+// the nearest offset at or before `offset` that `source` can be sliced at
+fn clamp(source: &str, offset: usize) -> usize {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+// spans need not fit the source, e.g. the ones of a program read from JSON
+fn render_snippet(out: &mut String, path: &str, source: &str, span: Span) {
+    if source.is_empty() {
+        return;
+    }
+    let start = clamp(source, span.start);
+    let (physical, _) = line_col(source, start);
+    let (file, line, col) = locate(source, start);
+    let gutter = " ".repeat(line.to_string().len());
+    let path = file.as_deref().unwrap_or(path);
+    let _ = writeln!(out, "{}--> {}:{}:{}", gutter, path, line, col);
+
+    let text = source.lines().nth(physical - 1).unwrap_or("");
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    // underline at most up to the end of the first line
+    let end = clamp(source, span.end).min(line_start + text.len()).max(start);
+    let width = source[start..end].chars().count().max(1);
+    let _ = writeln!(out, "{} |", gutter);
+    let _ = writeln!(out, "{} | {}", line, text);
+    // keep tabs so the carets line up with the echoed source line
+    let padding: String = text
+        .chars()
+        .take(col - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let _ = writeln!(out, "{} | {}{}", gutter, padding, "^".repeat(width));
+}
@@ -0,0 +1,614 @@
+//! The command line driver, which works like `gcc`: it compiles each input and has `cc` run
+//! the system's assembler and linker on the result.
+//!
+//! ```text
+//! compiler [-E | -S | -c | --emit=stage] [-o output] [-I dir]... [-D name[=value]]...
+//!          [-O0 | -O1 | -O2] [--target linux | macos | triple] [-nostdlib] file...
+//! ```
+//!
+//! `-O1` runs the peephole optimizer of [`crate::peephole`], and `-O2` is accepted as another
+//! name for it as there are no further optimizations. `-` reads C source from stdin. Files ending in `.s` are only assembled, and `.o` and `.a`
+//! files only linked. Files ending in `.json` are ASTs as `--emit=ast-json` writes them, which
+//! are compiled from semantic analysis on. `--emit` shows what a stage of the compiler makes of
+//! the input, see [`Emit`].
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::ast::Program;
+use crate::cfg;
+use crate::error::CompilerError;
+use crate::pretty_print;
+use crate::session::{Options, Session};
+use crate::target::Target;
+
+/// The last stage of the pipeline to run, in pipeline order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    /// `-E`, output the preprocessed source
+    Preprocess,
+    /// `-S`, output assembly
+    Compile,
+    /// `-c`, output object files
+    Assemble,
+    /// Build an executable
+    Link,
+}
+
+/// The output of a single stage of the compiler, written to `-o` or stdout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emit {
+    /// The lexer's tokens with their line and column. Typedef names show as identifiers, as
+    /// only the parser declares them.
+    Tokens,
+    /// The parsed program, pretty printed
+    Ast,
+    /// The parsed program as JSON, with the `serde` feature, see [`crate::json`]
+    AstJson,
+    /// The program after semantic analysis, with typedefs and enums resolved and the implicit
+    /// conversions written as casts
+    Ir,
+    /// The control flow graph of the generated code, as Graphviz `dot` source
+    Cfg,
+    /// The generated assembly
+    Asm,
+}
+
+impl FromStr for Emit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tokens" => Ok(Emit::Tokens),
+            "ast" => Ok(Emit::Ast),
+            "ast-json" => Ok(Emit::AstJson),
+            "ir" => Ok(Emit::Ir),
+            "cfg" => Ok(Emit::Cfg),
+            "asm" => Ok(Emit::Asm),
+            _ => Err(format!(
+                "unknown --emit '{s}', expected tokens, ast, ast-json, ir, cfg or asm"
+            )),
+        }
+    }
+}
+
+/// What the command line asks for
+#[derive(Debug, Clone)]
+pub struct CommandLine {
+    pub inputs: Vec<String>,
+    /// `-o`, `-` for stdout
+    pub output: Option<String>,
+    pub stage: Stage,
+    /// `--emit`, which replaces the stages after compiling
+    pub emit: Option<Emit>,
+    /// How to compile each input
+    pub options: Options,
+}
+
+impl CommandLine {
+    /// Parses the command line arguments following the program name
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<CommandLine, CompilerError> {
+        let mut command_line = CommandLine {
+            inputs: Vec::new(),
+            output: None,
+            stage: Stage::Link,
+            emit: None,
+            options: Options::default(),
+        };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let options = &mut command_line.options;
+            match arg.as_str() {
+                // as with gcc, the earliest stage asked for wins
+                "-E" => command_line.stage = command_line.stage.min(Stage::Preprocess),
+                "-S" => command_line.stage = command_line.stage.min(Stage::Compile),
+                "-c" => command_line.stage = command_line.stage.min(Stage::Assemble),
+                "-nostdlib" => options.freestanding = true,
+                "-O" => options.opt_level = 1,
+                "-O0" | "-O1" | "-O2" => options.opt_level = arg.as_bytes()[2] - b'0',
+                "-" => command_line.inputs.push(arg),
+                "-o" | "-I" | "-D" | "--target" | "--emit" => {
+                    let value = args.next().ok_or_else(|| {
+                        CompilerError::Usage(format!("missing argument to '{arg}'"))
+                    })?;
+                    command_line.set(&arg, value)?;
+                }
+                _ if arg.starts_with("--target=") || arg.starts_with("--emit=") => {
+                    let (flag, value) = arg.split_once('=').unwrap();
+                    command_line.set(flag, value.to_string())?
+                }
+                _ if ["-o", "-I", "-D"].iter().any(|flag| arg.starts_with(flag)) => {
+                    command_line.set(&arg[..2], arg[2..].to_string())?
+                }
+                _ if arg.starts_with("-O") => {
+                    let message = format!("unsupported optimization level '{arg}'");
+                    return Err(CompilerError::Usage(message));
+                }
+                _ if arg.starts_with('-') => {
+                    return Err(CompilerError::Usage(format!("unknown argument '{arg}'")));
+                }
+                _ => command_line.inputs.push(arg),
+            }
+        }
+
+        if command_line.inputs.is_empty() {
+            return Err(CompilerError::NoInputFile);
+        }
+        let separate_outputs = matches!(command_line.stage, Stage::Compile | Stage::Assemble);
+        if separate_outputs && command_line.output.is_some() && command_line.inputs.len() > 1 {
+            let message = "cannot specify '-o' with '-c' or '-S' with multiple files";
+            return Err(CompilerError::Usage(message.to_string()));
+        }
+        Ok(command_line)
+    }
+
+    // apply an option taking a value
+    fn set(&mut self, flag: &str, value: String) -> Result<(), CompilerError> {
+        let options = &mut self.options;
+        match flag {
+            "-o" => self.output = Some(value),
+            "-I" => options.include_dirs.push(value),
+            // `-D NAME` defines it as 1
+            "-D" => match value.split_once('=') {
+                Some((name, body)) => options.defines.push((name.to_string(), body.to_string())),
+                None => options.defines.push((value, "1".to_string())),
+            },
+            "--target" => options.target = value.parse().map_err(CompilerError::Usage)?,
+            "--emit" => self.emit = Some(value.parse().map_err(CompilerError::Usage)?),
+            _ => unreachable!("'{flag}' takes no value"),
+        }
+        Ok(())
+    }
+
+    // where the result for `input` goes when stopping before linking
+    fn output_path(&self, input: &str, extension: &str) -> PathBuf {
+        if let Some(output) = &self.output {
+            return PathBuf::from(output);
+        }
+        // like gcc, in the current directory
+        let stem = match input {
+            "-" => "stdin".into(),
+            _ => Path::new(input)
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy(),
+        };
+        PathBuf::from(format!("{stem}.{extension}"))
+    }
+}
+
+/// Runs the stages `command_line` asks for on each input
+pub fn run(command_line: &CommandLine) -> Result<(), CompilerError> {
+    let options = &command_line.options;
+    if command_line.stage == Stage::Preprocess {
+        let mut text = String::new();
+        for input in &command_line.inputs {
+            let mut session = Session::new(options.clone());
+            text.push_str(preprocess(&mut session, input)?);
+        }
+        return write_output(command_line.output.as_deref(), &text);
+    }
+    if let Some(emit) = command_line.emit {
+        let mut text = String::new();
+        for input in &command_line.inputs {
+            text.push_str(&compile(options, input, emit)?);
+        }
+        return write_output(command_line.output.as_deref(), &text);
+    }
+
+    let temp = TempDir::new()?;
+    let mut objects = Vec::new();
+    for (i, input) in command_line.inputs.iter().enumerate() {
+        let object = match command_line.stage {
+            Stage::Assemble => command_line.output_path(input, "o"),
+            _ => temp.file(i, input, "o"),
+        };
+        match Path::new(input).extension().and_then(|ext| ext.to_str()) {
+            // linker inputs are left alone until then
+            Some("o" | "a") => objects.push(PathBuf::from(input)),
+            Some("s") if command_line.stage == Stage::Compile => {}
+            Some("s") => {
+                assemble(options.target, Path::new(input), &object)?;
+                objects.push(object);
+            }
+            _ => {
+                let asm = compile(options, input, Emit::Asm)?;
+                if command_line.stage == Stage::Compile {
+                    let path = command_line.output_path(input, "s");
+                    write_output(path.to_str(), &asm)?;
+                    continue;
+                }
+                let asm_path = temp.file(i, input, "s");
+                std::fs::write(&asm_path, asm)?;
+                assemble(options.target, &asm_path, &object)?;
+                objects.push(object);
+            }
+        }
+    }
+
+    if command_line.stage == Stage::Link {
+        let output = command_line.output.as_deref().unwrap_or("a.out");
+        link(options, &objects, Path::new(output))?;
+    }
+    Ok(())
+}
+
+// the preprocessed `input`, which is stdin for `-`
+fn preprocess<'a>(session: &'a mut Session, input: &str) -> Result<&'a str, CompilerError> {
+    if input == "-" {
+        let mut source = String::new();
+        std::io::stdin().read_to_string(&mut source)?;
+        session.preprocess("<stdin>", &source)
+    } else {
+        session.preprocess_file(input)
+    }
+}
+
+// the output of the compiler stage `emit` for the C source `input`, after reporting any
+// diagnostics
+fn compile(options: &Options, input: &str, emit: Emit) -> Result<String, CompilerError> {
+    let mut session = Session::new(options.clone());
+    // an AST from `--emit=ast-json` is compiled from semantic analysis on
+    let mut program = match input.ends_with(".json") {
+        true if emit == Emit::Tokens => {
+            let message = format!("'{input}' is an AST, which has no tokens");
+            return Err(CompilerError::Usage(message));
+        }
+        true => load_ast_json(&mut session, input)?,
+        false => {
+            preprocess(&mut session, input)?;
+            if emit == Emit::Tokens {
+                let source_map = session.source_map().clone();
+                let mut out = String::new();
+                // one token a line, after where it starts
+                for (span, token) in session.tokens() {
+                    let (file, line, col) = source_map.locate(span.start);
+                    out.push_str(&format!("{file}:{line}:{col}\t{token:?}\n"));
+                }
+                return report(&session).map(|()| out);
+            }
+            let Some(program) = session.parse() else {
+                return Err(report(&session).expect_err("the parser only gives up on an error"));
+            };
+            program
+        }
+    };
+    match emit {
+        Emit::Ast => return report(&session).map(|()| pretty_print::render(&program)),
+        Emit::AstJson => return report(&session).and_then(|()| ast_json(&session, &program)),
+        _ => {}
+    }
+
+    // semantic analysis checks, also run on a partially parsed program to report as much as possible
+    let layouts = session.analyze(&mut program);
+    report(&session)?;
+    if emit == Emit::Ir {
+        return Ok(pretty_print::render(&program));
+    }
+    let asm = session.generate(&program, layouts);
+    Ok(match emit {
+        Emit::Cfg => cfg::dot(&asm),
+        _ => asm,
+    })
+}
+
+// print the diagnostics, failing if there are errors among them
+fn report(session: &Session) -> Result<(), CompilerError> {
+    eprint!("{}", session.source_map().render(session.diagnostics()));
+    match session.diagnostics().error_count() {
+        0 => Ok(()),
+        errors => Err(CompilerError::Aborted(errors)),
+    }
+}
+
+#[cfg(feature = "serde")]
+fn ast_json(session: &Session, program: &Program) -> Result<String, CompilerError> {
+    let mut json = crate::json::to_string(program, session.source_map());
+    json.push('\n');
+    Ok(json)
+}
+
+#[cfg(feature = "serde")]
+fn load_ast_json(session: &mut Session, input: &str) -> Result<Program, CompilerError> {
+    let json = std::fs::read_to_string(input)?;
+    session.load_ast_json(&json)
+}
+
+#[cfg(not(feature = "serde"))]
+fn ast_json(_: &Session, _: &Program) -> Result<String, CompilerError> {
+    Err(CompilerError::Usage(NO_SERDE.to_string()))
+}
+
+#[cfg(not(feature = "serde"))]
+fn load_ast_json(_: &mut Session, _: &str) -> Result<Program, CompilerError> {
+    Err(CompilerError::Usage(NO_SERDE.to_string()))
+}
+
+#[cfg(not(feature = "serde"))]
+const NO_SERDE: &str = "AST JSON needs the compiler built with the `serde` feature";
+
+// write `text` to the file `path`, or stdout for `-` or no path at all
+fn write_output(path: Option<&str>, text: &str) -> Result<(), CompilerError> {
+    match path {
+        None | Some("-") => print!("{}", text),
+        Some(path) => std::fs::write(path, text)?,
+    }
+    Ok(())
+}
+
+// `cc` targeting `target`, which drives the system's assembler and linker
+fn cc(target: Target) -> Command {
+    match target {
+        Target::MacOs => {
+            let mut command = Command::new("cc");
+            command.args(["-arch", "arm64"]);
+            command
+        }
+        Target::Linux if cfg!(all(target_os = "linux", target_arch = "aarch64")) => {
+            Command::new("cc")
+        }
+        // elsewhere the host's `cc` does not produce AArch64 code, a cross compiler does
+        Target::Linux => Command::new("aarch64-linux-gnu-gcc"),
+    }
+}
+
+fn assemble(target: Target, asm: &Path, object: &Path) -> Result<(), CompilerError> {
+    let mut command = cc(target);
+    command.arg("-c").arg(asm).arg("-o").arg(object);
+    run_tool(command)
+}
+
+fn link(options: &Options, objects: &[PathBuf], output: &Path) -> Result<(), CompilerError> {
+    let mut command = cc(options.target);
+    if options.freestanding {
+        match options.target {
+            // nothing but the program and its runtime, starting at `_start`
+            Target::Linux => command.args(["-nostdlib", "-static"]),
+            // macOS only runs dynamically linked programs, so libSystem is linked but not used
+            Target::MacOs => command.arg("-Wl,-e,_start"),
+        };
+    }
+    command.args(objects).arg("-o").arg(output);
+    run_tool(command)
+}
+
+fn run_tool(mut command: Command) -> Result<(), CompilerError> {
+    let tool = command.get_program().to_string_lossy().into_owned();
+    let status = command.status().map_err(|e| CompilerError::ToolFailed {
+        tool: tool.clone(),
+        status: e.to_string(),
+    })?;
+    if !status.success() {
+        let status = status.to_string();
+        return Err(CompilerError::ToolFailed { tool, status });
+    }
+    Ok(())
+}
+
+/// Directory for the intermediate files, removed again when dropped
+struct TempDir(PathBuf);
+
+/// Tells apart the temporary directories of one process, e.g. of threads each running the driver
+static NEXT_TEMP_DIR: AtomicUsize = AtomicUsize::new(0);
+
+impl TempDir {
+    fn new() -> std::io::Result<Self> {
+        let n = NEXT_TEMP_DIR.fetch_add(1, Ordering::Relaxed);
+        let name = format!("compiler-{}-{n}", std::process::id());
+        let path = std::env::temp_dir().join(name);
+        std::fs::create_dir_all(&path)?;
+        Ok(Self(path))
+    }
+
+    // numbered, as inputs in different directories may share a name
+    fn file(&self, index: usize, input: &str, extension: &str) -> PathBuf {
+        let stem = Path::new(input).file_stem().unwrap_or_default();
+        let name = format!("{index}-{}.{extension}", stem.to_string_lossy());
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<CommandLine, CompilerError> {
+        CommandLine::parse(args.split_whitespace().map(String::from))
+    }
+
+    fn usage(args: &str) -> String {
+        match parse(args) {
+            Err(CompilerError::Usage(message)) => message,
+            other => panic!("'{args}' gave {other:?}"),
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let command_line = parse("main.c").unwrap();
+        assert_eq!(command_line.inputs, ["main.c"]);
+        assert_eq!(command_line.output, None);
+        assert_eq!(command_line.stage, Stage::Link);
+        assert_eq!(command_line.emit, None);
+        assert_eq!(command_line.options.opt_level, 0);
+        assert!(!command_line.options.freestanding);
+    }
+
+    #[test]
+    fn optimization_levels() {
+        for (flag, level) in [("-O0", 0), ("-O", 1), ("-O1", 1), ("-O2", 2)] {
+            let command_line = parse(&format!("{flag} main.c")).unwrap();
+            assert_eq!(command_line.options.opt_level, level, "{flag}");
+        }
+        // the last one wins
+        assert_eq!(parse("-O2 -O0 main.c").unwrap().options.opt_level, 0);
+        assert_eq!(usage("-O3 main.c"), "unsupported optimization level '-O3'");
+    }
+
+    #[test]
+    fn values_attached_or_separate() {
+        let command_line = parse("-I inc -Isrc/inc -D N=2 -DDEBUG -o out main.c").unwrap();
+        assert_eq!(command_line.options.include_dirs, ["inc", "src/inc"]);
+        let defines = [("N", "2"), ("DEBUG", "1")].map(|(n, v)| (n.to_string(), v.to_string()));
+        assert_eq!(command_line.options.defines, defines);
+        assert_eq!(command_line.output.as_deref(), Some("out"));
+        assert_eq!(
+            parse("-oout main.c").unwrap().output.as_deref(),
+            Some("out")
+        );
+        // `-o -` writes to stdout, and `-` alone reads stdin
+        let command_line = parse("-S -o - -").unwrap();
+        assert_eq!(command_line.output.as_deref(), Some("-"));
+        assert_eq!(command_line.inputs, ["-"]);
+        assert_eq!(usage("main.c -I"), "missing argument to '-I'");
+    }
+
+    #[test]
+    fn targets_and_emit() {
+        for args in ["--target linux", "--target=aarch64-linux-gnu"] {
+            let command_line = parse(&format!("{args} main.c")).unwrap();
+            assert_eq!(command_line.options.target, Target::Linux, "{args}");
+        }
+        let command_line = parse("--target macos --emit=ir main.c").unwrap();
+        assert_eq!(command_line.options.target, Target::MacOs);
+        assert_eq!(command_line.emit, Some(Emit::Ir));
+        assert_eq!(
+            parse("--emit ast-json a.c").unwrap().emit,
+            Some(Emit::AstJson)
+        );
+        assert!(usage("--emit=bytes main.c").starts_with("unknown --emit 'bytes'"));
+        assert!(matches!(
+            parse("--target riscv64 main.c"),
+            Err(CompilerError::Usage(_))
+        ));
+    }
+
+    #[test]
+    fn several_inputs() {
+        let command_line = parse("a.c b.s c.o -nostdlib -o prog").unwrap();
+        assert_eq!(command_line.inputs, ["a.c", "b.s", "c.o"]);
+        assert!(command_line.options.freestanding);
+        // each input has an output of its own before linking
+        for stage in ["-S", "-c"] {
+            assert_eq!(
+                usage(&format!("{stage} -o out a.c b.c")),
+                "cannot specify '-o' with '-c' or '-S' with multiple files"
+            );
+        }
+        assert!(parse("-E -o out a.c b.c").is_ok());
+        assert!(parse("-S a.c b.c").is_ok());
+        assert!(matches!(parse("-S -O2"), Err(CompilerError::NoInputFile)));
+    }
+
+    #[test]
+    fn earliest_stage_wins() {
+        for (args, stage) in [
+            ("-c -S", Stage::Compile),
+            ("-S -c", Stage::Compile),
+            ("-c -E", Stage::Preprocess),
+            ("-E -S -c", Stage::Preprocess),
+            ("-c", Stage::Assemble),
+        ] {
+            let command_line = parse(&format!("{args} main.c")).unwrap();
+            assert_eq!(command_line.stage, stage, "{args}");
+        }
+    }
+
+    #[test]
+    fn unknown_flags() {
+        assert_eq!(usage("-Wall main.c"), "unknown argument '-Wall'");
+        assert_eq!(usage("--verbose main.c"), "unknown argument '--verbose'");
+    }
+
+    const PROGRAM: &str = "long f(int x) { return x; }\nint main(void) { return f(2) ? 1 : 0; }\n";
+
+    // what running the driver with `args` on `source` writes to `-o`, the arguments naming the
+    // file of the source `main.c`
+    fn output(args: &str, source: &str) -> Result<String, CompilerError> {
+        let dir = TempDir::new().unwrap();
+        let (input, output) = (dir.0.join("main.c"), dir.0.join("out"));
+        std::fs::write(&input, source).unwrap();
+        let args = args.replace("main.c", input.to_str().unwrap());
+        let mut command_line = parse(&format!("--target linux {args}")).unwrap();
+        command_line.output = Some(output.to_str().unwrap().to_string());
+        run(&command_line)?;
+        Ok(std::fs::read_to_string(output).unwrap())
+    }
+
+    #[test]
+    fn output_of_each_stage() {
+        let preprocessed = output("-E -DN=2 main.c", "int n = N;\n").unwrap();
+        assert_eq!(preprocessed.lines().last(), Some("int n = 2;"));
+        let tokens = output("--emit=tokens main.c", PROGRAM).unwrap();
+        let lines: Vec<_> = tokens.lines().collect();
+        assert!(
+            lines[0].ends_with("main.c:1:1\tKeyword(\"long\")"),
+            "{tokens}"
+        );
+        assert!(
+            lines[11].ends_with("main.c:2:1\tKeyword(\"int\")"),
+            "{tokens}"
+        );
+        let ast = output("--emit=ast main.c", PROGRAM).unwrap();
+        assert!(ast.contains("FUNC long f:\n\tparams: (int x)\n\tbody:\n\t  return x;\n"));
+        // the IR has the conversions semantic analysis inserted
+        let ir = output("--emit=ir main.c", PROGRAM).unwrap();
+        assert!(ir.contains("\t  return (long)x;\n"), "{ir}");
+        let cfg = output("--emit=cfg main.c", PROGRAM).unwrap();
+        assert!(cfg.starts_with("digraph cfg {\n"), "{cfg}");
+        assert!(cfg.contains("subgraph \"cluster_main\""), "{cfg}");
+        let asm = output("--emit=asm main.c", PROGRAM).unwrap();
+        assert!(
+            asm.contains("\nmain:\n") && asm.contains("\tbl f\n"),
+            "{asm}"
+        );
+        assert_eq!(output("-S main.c", PROGRAM).unwrap(), asm);
+        // errors stop the pipeline at the stage that found them
+        let error = output("--emit=ir main.c", "int main(void) { return x; }").unwrap_err();
+        assert!(matches!(error, CompilerError::Aborted(_)), "{error:?}");
+        assert!(output("--emit=ast main.c", "int main(void) { return x; }").is_ok());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn ast_json_compiles_like_the_source() {
+        let json = output("--emit=ast-json main.c", PROGRAM).unwrap();
+        assert!(json.starts_with("{\n  \"version\": 1,\n"), "{json}");
+        let dir = TempDir::new().unwrap();
+        let input = dir.0.join("main.json");
+        std::fs::write(&input, json).unwrap();
+        let mut command_line = parse(&format!("--target linux -S {}", input.display())).unwrap();
+        let asm = dir.0.join("main.s");
+        command_line.output = Some(asm.to_str().unwrap().to_string());
+        run(&command_line).unwrap();
+        let expected = output("-S main.c", PROGRAM).unwrap();
+        assert_eq!(std::fs::read_to_string(asm).unwrap(), expected);
+    }
+
+    #[cfg(not(feature = "serde"))]
+    #[test]
+    fn ast_json_needs_serde() {
+        let error = output("--emit=ast-json main.c", PROGRAM).unwrap_err();
+        assert!(matches!(error, CompilerError::Usage(_)), "{error:?}");
+    }
+
+    #[test]
+    fn temp_dirs_are_distinct() {
+        let (a, b) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        assert_ne!(a.0, b.0);
+        let path = a.0.clone();
+        assert!(path.is_dir());
+        drop(a);
+        assert!(!path.exists());
+        assert_eq!(b.file(1, "src/main.c", "s"), b.0.join("1-main.s"));
+    }
+}
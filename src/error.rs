@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::ast::Type;
+
 #[derive(Error, Debug)]
 pub enum CompilerError {
     #[error("IO error: {0}")]
@@ -18,4 +20,185 @@ pub enum CompilerError {
 
     #[error("Invalid input: No input file specified")]
     NoInputFile,
+
+    #[error("Variable '{0}' redeclared in the same scope")]
+    VariableRedeclared(String),
+
+    #[error("Variable '{0}' may be used before it is initialized")]
+    UseBeforeInit(String),
+
+    #[error("Incompatible types: expected '{expected}', found '{found}'")]
+    IncompatibleTypes { expected: Type, found: Type },
+
+    #[error("Invalid operands to binary '{0}' ('{1}' and '{2}')")]
+    InvalidOperands(String, Type, Type),
+
+    #[error("Invalid operand to unary '{0}' ('{1}')")]
+    InvalidOperand(String, Type),
+
+    #[error("Cannot dereference a value of type '{0}'")]
+    InvalidDereference(Type),
+
+    #[error("Expression is not assignable")]
+    NotAssignable,
+
+    #[error("Cannot take the address of a temporary value")]
+    NotAddressable,
+
+    #[error("Cannot subscript a value of type '{0}' with '{1}'")]
+    InvalidSubscript(Type, Type),
+
+    #[error("Invalid initializer for a variable of type '{0}'")]
+    InvalidInitializer(Type),
+
+    #[error("Too many elements in initializer for type '{0}'")]
+    TooManyInitializers(Type),
+
+    #[error("Conflicting types for function '{0}'")]
+    ConflictingTypes(String),
+
+    #[error("Function '{0}' called but not declared")]
+    UndeclaredFunction(String),
+
+    #[error("Function '{name}' expects {expected} argument(s), but {found} were given")]
+    WrongArgumentCount {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+
+    #[error("Function '{name}' expects at least {expected} argument(s), but {found} were given")]
+    TooFewArguments {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+
+    #[error("Cannot cast a value of type '{0}' to '{1}'")]
+    InvalidCast(Type, Type),
+
+    #[error("Variable or member has incomplete type '{0}'")]
+    IncompleteType(Type),
+
+    #[error("No member named '{1}' in '{0}'")]
+    NoSuchMember(Type, String),
+
+    #[error("Redefinition of '{0}'")]
+    TagRedefined(Type),
+
+    #[error("Duplicate member '{0}'")]
+    DuplicateMember(String),
+
+    #[error("Invalid member access on a value of type '{0}'")]
+    NotAStruct(Type),
+
+    #[error("Expression is not an integer constant")]
+    NotConstant,
+
+    #[error("Unknown type name '{0}'")]
+    UnknownTypeName(String),
+
+    #[error("Conflicting declaration of '{0}'")]
+    ConflictingDeclaration(String),
+
+    #[error("Switch on a value of type '{0}', which is not an integer")]
+    SwitchNotInteger(Type),
+
+    #[error("'{0}' label not within a switch statement")]
+    LabelOutsideSwitch(&'static str),
+
+    #[error("Duplicate case value '{0}'")]
+    DuplicateCase(String),
+
+    #[error("Multiple default labels in one switch")]
+    DuplicateDefault,
+
+    #[error("'break' statement not within a switch")]
+    BreakOutsideSwitch,
+
+    #[error("Label '{0}' used but not defined")]
+    UndefinedLabel(String),
+
+    #[error("Redefinition of label '{0}'")]
+    DuplicateLabel(String),
+
+    #[error("'va_start' used in a function with a fixed number of arguments")]
+    VaStartOutsideVariadic,
+
+    #[error("Expected a 'va_list', found '{0}'")]
+    NotAVaList(Type),
+
+    #[error("Cannot take an argument of type '{0}' from a va_list")]
+    InvalidVaArg(Type),
+
+    #[error("{0}")]
+    Usage(String),
+
+    #[error("'{tool}' failed ({status})")]
+    ToolFailed { tool: String, status: String },
+
+    #[error("aborting due to {0} previous error{plural}", plural = if *.0 == 1 { "" } else { "s" })]
+    Aborted(usize),
+
+    #[error("{file}:{line}: {message}")]
+    Preprocess {
+        file: String,
+        line: usize,
+        message: String,
+    },
+
+    #[error("Invalid AST JSON: {0}")]
+    InvalidAstJson(String),
+}
+
+impl CompilerError {
+    /// Stable identifier printed alongside diagnostics, e.g. `error[E0002]`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            CompilerError::Io(_) => "E0000",
+            CompilerError::Parse(_) => "E0001",
+            CompilerError::UndeclaredVariable(_) => "E0002",
+            CompilerError::FunctionRedefined(_) => "E0003",
+            CompilerError::NoInputFile => "E0004",
+            CompilerError::VariableRedeclared(_) => "E0005",
+            CompilerError::UseBeforeInit(_) => "W0001",
+            CompilerError::IncompatibleTypes { .. } => "E0006",
+            CompilerError::InvalidOperands(..) => "E0007",
+            CompilerError::InvalidOperand(..) => "E0008",
+            CompilerError::InvalidDereference(_) => "E0009",
+            CompilerError::NotAssignable => "E0010",
+            CompilerError::NotAddressable => "E0011",
+            CompilerError::InvalidSubscript(..) => "E0012",
+            CompilerError::InvalidInitializer(_) => "E0013",
+            CompilerError::TooManyInitializers(_) => "E0014",
+            CompilerError::ConflictingTypes(_) => "E0015",
+            CompilerError::UndeclaredFunction(_) => "E0016",
+            CompilerError::WrongArgumentCount { .. } => "E0017",
+            CompilerError::InvalidCast(..) => "E0018",
+            CompilerError::IncompleteType(_) => "E0019",
+            CompilerError::NoSuchMember(..) => "E0020",
+            CompilerError::TagRedefined(_) => "E0021",
+            CompilerError::DuplicateMember(_) => "E0022",
+            CompilerError::NotAStruct(_) => "E0023",
+            CompilerError::NotConstant => "E0024",
+            CompilerError::UnknownTypeName(_) => "E0025",
+            CompilerError::ConflictingDeclaration(_) => "E0026",
+            CompilerError::SwitchNotInteger(_) => "E0027",
+            CompilerError::LabelOutsideSwitch(_) => "E0028",
+            CompilerError::DuplicateCase(_) => "E0029",
+            CompilerError::DuplicateDefault => "E0030",
+            CompilerError::BreakOutsideSwitch => "E0031",
+            CompilerError::UndefinedLabel(_) => "E0032",
+            CompilerError::DuplicateLabel(_) => "E0033",
+            CompilerError::Preprocess { .. } => "E0034",
+            CompilerError::TooFewArguments { .. } => "E0035",
+            CompilerError::VaStartOutsideVariadic => "E0036",
+            CompilerError::NotAVaList(_) => "E0037",
+            CompilerError::InvalidVaArg(_) => "E0038",
+            CompilerError::Usage(_) => "E0039",
+            CompilerError::ToolFailed { .. } => "E0040",
+            CompilerError::Aborted(_) => "E0041",
+            CompilerError::InvalidAstJson(_) => "E0042",
+        }
+    }
 }
@@ -0,0 +1,741 @@
+//! Reformats C source, for `cfmt`.
+//!
+//! Unlike [`crate::printer`], which prints an AST, this lays out the tokens of the source
+//! itself: constants, `+=` and the other things the AST does not keep stay as written, and so
+//! do comments and preprocessor directives. The layout follows the structure of the grammar,
+//! as far as the tokens show it. Formatting is idempotent, formatting the output again changes
+//! nothing.
+//!
+//! Statements go on lines of their own, indented by their nesting, with at most one blank line
+//! kept between them. Comments stay on their own line or after the code they follow, and
+//! directives start at column 0. Long lines are not wrapped.
+
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use lalrpop_util::ParseError;
+
+use crate::ast::Span;
+use crate::diagnostics::{Diagnostics, SyntaxError};
+use crate::lexer::{Lexer, Token, TypedefNames};
+
+/// Where opening braces go
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BraceStyle {
+    /// On the line of the `if`, function or struct they belong to
+    Attach,
+    /// Like `Attach`, but on a line of their own for function bodies
+    Linux,
+    /// Always on a line of their own
+    Allman,
+}
+
+impl FromStr for BraceStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "attach" => Ok(BraceStyle::Attach),
+            "linux" => Ok(BraceStyle::Linux),
+            "allman" => Ok(BraceStyle::Allman),
+            _ => Err(format!(
+                "unknown brace style '{s}', expected attach, linux or allman"
+            )),
+        }
+    }
+}
+
+/// How to lay out the source
+#[derive(Debug, Clone)]
+pub struct Style {
+    /// Spaces per level of nesting
+    pub indent_width: usize,
+    pub braces: BraceStyle,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            indent_width: 4,
+            braces: BraceStyle::Attach,
+        }
+    }
+}
+
+/// Formats `source`. `typedefs` are the typedef names it uses without declaring them, e.g.
+/// those of its headers, see [`typedef_names`]. The source need not parse, as e.g. a macro
+/// may take a type as its argument, but it has to lex: that fails with the lexer's error.
+pub fn format(source: &str, style: &Style, typedefs: &[String]) -> Result<String, Diagnostics> {
+    let (code, trivia) = scan(source);
+    let tokens = tokens(&code).map_err(|(_, error)| {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.push(ParseError::User { error }.into());
+        diagnostics
+    })?;
+    let mut known: HashSet<&str> = typedefs.iter().map(String::as_str).collect();
+    known.extend(declared_typedefs(&tokens));
+    let mut formatter = Formatter {
+        source,
+        tokens,
+        pos: 0,
+        trivia,
+        next_trivia: 0,
+        style,
+        typedefs: known,
+        out: String::new(),
+        line_open: false,
+        pending: None,
+        statement_indent: 0,
+        block_start: false,
+        after_comment: false,
+        last_end: 0,
+        prev: Class::Operator,
+        parens: Vec::new(),
+    };
+    formatter.program();
+    Ok(formatter.out)
+}
+
+/// The names `source` declares with `typedef`, in any scope, e.g. in its preprocessed form
+/// to know those of its headers
+pub fn typedef_names(source: &str) -> Vec<String> {
+    let (code, _) = scan(source);
+    let tokens = tokens(&code).unwrap_or_else(|(tokens, _)| tokens);
+    let names = declared_typedefs(&tokens);
+    names.into_iter().map(str::to_string).collect()
+}
+
+// a comment or preprocessor directive, which the lexer skips
+struct Trivia {
+    span: Span,
+    directive: bool,
+}
+
+// `source` with comments and directives blanked out, keeping the offsets of the tokens, and
+// where they were
+fn scan(source: &str) -> (String, Vec<Trivia>) {
+    let bytes = source.as_bytes();
+    let mut code = bytes.to_vec();
+    let mut trivia = Vec::new();
+    let mut line_start = true;
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let directive = line_start && bytes[i] == b'#';
+        match (bytes[i], bytes.get(i + 1)) {
+            // a directive runs to the end of the line, lines ending in a backslash continuing it
+            (b'#', _) if directive => {
+                while i < bytes.len() && !(bytes[i] == b'\n' && bytes[i - 1] != b'\\') {
+                    i += 1;
+                }
+                // what `#if 0` leaves out need not even lex, it stays as it is
+                if directive_words(&source[start..i]) == ["if", "0"] {
+                    i = disabled_end(source, i);
+                }
+            }
+            (b'/', Some(b'/')) => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            (b'/', Some(b'*')) => match source[i + 2..].find("*/") {
+                Some(end) => i += 2 + end + 2,
+                // the lexer reports it
+                None => break,
+            },
+            (quote @ (b'"' | b'\''), _) => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote && bytes[i] != b'\n' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                i = (i + 1).min(bytes.len());
+                line_start = false;
+                continue;
+            }
+            (byte, _) => {
+                if !byte.is_ascii_whitespace() {
+                    line_start = false;
+                } else if byte == b'\n' {
+                    line_start = true;
+                }
+                i += 1;
+                continue;
+            }
+        }
+        for byte in &mut code[start..i] {
+            if *byte != b'\n' {
+                *byte = b' ';
+            }
+        }
+        trivia.push(Trivia {
+            span: Span::new(start, i),
+            directive,
+        });
+    }
+    // only whole characters were blanked, so this is still UTF-8
+    (String::from_utf8(code).unwrap(), trivia)
+}
+
+fn directive_words(directive: &str) -> Vec<&str> {
+    directive[1..].split_whitespace().collect()
+}
+
+// the start of the line with the `#else`, `#elif` or `#endif` ending the group of lines at
+// `start`, at the end of its line if there is none
+fn disabled_end(source: &str, start: usize) -> usize {
+    let mut depth = 0;
+    let mut offset = start;
+    for line in source[start..].split_inclusive('\n') {
+        let directive = line.trim_start();
+        if directive.starts_with('#') {
+            match directive_words(directive)
+                .first()
+                .copied()
+                .unwrap_or_default()
+            {
+                "if" | "ifdef" | "ifndef" => depth += 1,
+                "else" | "elif" if depth == 0 => return offset,
+                "endif" if depth == 0 => return offset,
+                "endif" => depth -= 1,
+                _ => {}
+            }
+        }
+        offset += line.len();
+    }
+    source.trim_end().len().max(start)
+}
+
+type Tokens<'a> = Vec<(Span, Token<'a>)>;
+
+// the tokens of `code`, or the ones before the first the lexer rejects and why it does
+fn tokens(code: &str) -> Result<Tokens<'_>, (Tokens<'_>, SyntaxError)> {
+    let names = TypedefNames::new();
+    let mut tokens = Vec::new();
+    for token in Lexer::new(code, &names) {
+        match token {
+            Ok((start, token, end)) => tokens.push((Span::new(start, end), token)),
+            Err(error) => return Err((tokens, error)),
+        }
+    }
+    Ok(tokens)
+}
+
+// the last name before the `;` ending each `typedef` declaration, outside of any braces
+fn declared_typedefs<'a>(tokens: &[(Span, Token<'a>)]) -> Vec<&'a str> {
+    let mut names = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        if tokens[i].1 != Token::Keyword("typedef") {
+            i += 1;
+            continue;
+        }
+        let (mut depth, mut name) = (0, None);
+        while let Some((_, token)) = tokens.get(i) {
+            match token {
+                Token::Punct("{") => depth += 1,
+                Token::Punct("}") => depth -= 1,
+                Token::Punct(";") if depth == 0 => break,
+                Token::Identifier(text) if depth == 0 => name = Some(*text),
+                _ => {}
+            }
+            i += 1;
+        }
+        names.extend(name);
+    }
+    names
+}
+
+// what the last token was, which decides the spacing around the next one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Class {
+    /// Ends an operand, e.g. a name or a `)`, so a `-` after it subtracts
+    Operand,
+    /// A type, after which `*` declares a pointer
+    Type,
+    /// A unary operator or cast, which the next token sticks to
+    Prefix,
+    /// Anything else, e.g. a binary operator, `,` or `return`, after which `-` negates
+    Operator,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Paren {
+    /// Of a call, function declarator, `sizeof` or builtin
+    Call,
+    Cast,
+    /// Around an expression, or the condition of an `if` or `switch`
+    Group,
+}
+
+// where the next line starts, once something is written to it
+#[derive(Debug, Clone, Copy)]
+struct Break {
+    indent: usize,
+    /// Whether a blank line of the source before it is kept
+    blank: bool,
+}
+
+struct Formatter<'a, 's> {
+    source: &'a str,
+    tokens: Vec<(Span, Token<'s>)>,
+    pos: usize,
+    trivia: Vec<Trivia>,
+    next_trivia: usize,
+    style: &'a Style,
+    typedefs: HashSet<&'s str>,
+    out: String,
+    /// Whether the last line of `out` is unfinished
+    line_open: bool,
+    /// Set when the next token goes on a new line
+    pending: Option<Break>,
+    /// Indentation of the statement being written, its continuation lines go one deeper
+    statement_indent: usize,
+    /// Nothing was written since the `{` of a block, no blank line goes here
+    block_start: bool,
+    /// The line ends in a comment, so the next token needs a space
+    after_comment: bool,
+    /// End of the last token or trivia written, in the source
+    last_end: usize,
+    prev: Class,
+    parens: Vec<Paren>,
+}
+
+impl<'a> Formatter<'a, '_> {
+    fn peek(&self, offset: usize) -> Option<&'a str> {
+        let (span, _) = self.tokens.get(self.pos + offset)?;
+        Some(&self.source[span.start..span.end])
+    }
+
+    fn text(&self, index: usize) -> &'a str {
+        let span = self.tokens[index].0;
+        &self.source[span.start..span.end]
+    }
+
+    fn program(&mut self) {
+        while self.pos < self.tokens.len() {
+            self.newline(0, true);
+            self.phrase(0);
+        }
+        // comments and directives after the last token
+        self.newline(0, true);
+        self.flush_trivia(self.source.len());
+        if self.line_open {
+            self.out.push('\n');
+        }
+    }
+
+    /// Starts a new line at `indent` before the next token
+    fn newline(&mut self, indent: usize, blank: bool) {
+        self.pending = Some(Break { indent, blank });
+        self.statement_indent = indent;
+        self.prev = Class::Operator;
+    }
+
+    fn end_line(&mut self) {
+        if self.line_open {
+            self.out.push('\n');
+            self.line_open = false;
+        }
+        self.after_comment = false;
+    }
+
+    // starts the pending line, keeping a blank line of the source before `start`
+    fn start_line(&mut self, start: usize) {
+        let Some(pending) = self.pending.take() else {
+            return;
+        };
+        self.end_line();
+        let blank = self.source[self.last_end..start].matches('\n').count() > 1;
+        if blank && pending.blank && !self.block_start && !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        let indent = pending.indent * self.style.indent_width;
+        self.out.push_str(&" ".repeat(indent));
+        self.line_open = true;
+    }
+
+    // writes the comments and directives before `offset`
+    fn flush_trivia(&mut self, offset: usize) {
+        while let Some(trivia) = self.trivia.get(self.next_trivia) {
+            let span = trivia.span;
+            if span.start >= offset {
+                break;
+            }
+            self.next_trivia += 1;
+            let text = self.source[span.start..span.end].trim_end();
+            let own_line = !self.line_open || self.source[self.last_end..span.start].contains('\n');
+            if trivia.directive {
+                if self.pending.is_none() {
+                    self.pending = Some(self.continuation());
+                }
+                let pending = self.pending;
+                self.start_line(span.start);
+                // directives are not indented
+                self.out.truncate(self.out.trim_end_matches(' ').len());
+                self.out.push_str(text);
+                self.end_line();
+                self.pending = pending;
+            } else if own_line {
+                if self.pending.is_none() {
+                    self.pending = Some(self.continuation());
+                }
+                let pending = self.pending;
+                self.start_line(span.start);
+                self.out.push_str(&trim_lines(text));
+                self.end_line();
+                self.pending = pending;
+            } else {
+                if !self.out.ends_with(['(', '[']) {
+                    self.out.push(' ');
+                }
+                self.out.push_str(text);
+                self.after_comment = true;
+                // nothing can follow a line comment on its line
+                if text.starts_with("//") && self.pending.is_none() {
+                    self.pending = Some(self.continuation());
+                }
+                self.last_end = span.end;
+                continue;
+            }
+            self.block_start = false;
+            self.last_end = span.end;
+        }
+    }
+
+    fn continuation(&self) -> Break {
+        Break {
+            indent: self.statement_indent + 1,
+            blank: false,
+        }
+    }
+
+    /// Writes the next token
+    fn token(&mut self) {
+        self.token_spaced(None);
+    }
+
+    // the next token, with or without a space before it rather than what the tokens around
+    // it call for
+    fn token_spaced(&mut self, space: Option<bool>) {
+        // source that does not parse may end early
+        let Some(&(span, token)) = self.tokens.get(self.pos) else {
+            return;
+        };
+        self.flush_trivia(span.start);
+        let text = self.text(self.pos);
+        if self.pending.is_some() {
+            self.start_line(span.start);
+        } else if self.line_open {
+            let space = self.after_comment || space.unwrap_or_else(|| self.space_before(text));
+            if space {
+                self.out.push(' ');
+            }
+        } else {
+            self.line_open = true;
+        }
+        self.out.push_str(text);
+        self.after_comment = false;
+        self.block_start = false;
+        self.last_end = span.end;
+        self.prev = self.classify(token);
+        self.pos += 1;
+    }
+
+    fn space_before(&self, next: &str) -> bool {
+        let prev = self.text(self.pos - 1);
+        match next {
+            ")" | "]" | "}" | "," | ";" | "." | "->" | "[" => return false,
+            "(" => {
+                return !self.follows_callee()
+                    && self.prev != Class::Prefix
+                    && !matches!(prev, "(" | "[");
+            }
+            "++" | "--" if self.prev == Class::Operand => return false,
+            _ => {}
+        }
+        // `- -x` is not `--x`
+        if self.prev == Class::Prefix && matches!(prev, "-" | "+" | "&") && next.starts_with(prev) {
+            return true;
+        }
+        !matches!(prev, "(" | "[" | "{" | "." | "->") && self.prev != Class::Prefix
+    }
+
+    // the class of `token`, the next one, keeping track of the parentheses
+    fn classify(&mut self, token: Token) -> Class {
+        let before = self.pos.checked_sub(1).map(|i| self.text(i));
+        match token {
+            Token::Identifier(name) | Token::TypedefName(name) => {
+                let member = matches!(before, Some("." | "->"));
+                let tag = matches!(before, Some("struct" | "union" | "enum"));
+                match tag || (!member && self.typedefs.contains(name)) {
+                    true => Class::Type,
+                    false => Class::Operand,
+                }
+            }
+            Token::Integer(_) | Token::Float(_) | Token::Char(_) | Token::String(_) => {
+                Class::Operand
+            }
+            Token::Keyword(keyword) if is_type_keyword(keyword) => Class::Type,
+            Token::Keyword(_) => Class::Operator,
+            Token::Punct("(") => {
+                let paren = if self.follows_callee() {
+                    Paren::Call
+                } else if self.starts_type(self.pos + 1) {
+                    Paren::Cast
+                } else {
+                    Paren::Group
+                };
+                self.parens.push(paren);
+                Class::Operator
+            }
+            Token::Punct(")") => match self.parens.pop() {
+                Some(Paren::Cast) => Class::Prefix,
+                _ => Class::Operand,
+            },
+            Token::Punct("]") => Class::Operand,
+            Token::Punct("!" | "~" | "." | "->") => Class::Prefix,
+            Token::Punct("-" | "+" | "&" | "++" | "--") if self.prev != Class::Operand => {
+                Class::Prefix
+            }
+            Token::Punct("++" | "--") => Class::Operand,
+            Token::Punct("*") if self.prev != Class::Operand => Class::Prefix,
+            _ => Class::Operator,
+        }
+    }
+
+    // whether the next token follows a name that `(` makes a call of
+    fn follows_callee(&self) -> bool {
+        match self.pos.checked_sub(1) {
+            Some(i) => matches!(self.tokens[i].1, Token::Identifier(_)) || is_callee(self.text(i)),
+            None => false,
+        }
+    }
+
+    // whether the token at `index` begins a type name, making a `(` before it a cast
+    fn starts_type(&self, index: usize) -> bool {
+        match self.tokens.get(index) {
+            Some((_, Token::Keyword(keyword))) => {
+                is_type_keyword(keyword) || matches!(*keyword, "struct" | "union" | "enum")
+            }
+            Some((_, Token::Identifier(name))) => self.typedefs.contains(name),
+            _ => false,
+        }
+    }
+
+    // writes a balanced `(...)`
+    fn parenthesized(&mut self) {
+        let depth = self.parens.len();
+        self.token();
+        while self.pos < self.tokens.len() && self.parens.len() > depth {
+            self.token();
+        }
+    }
+
+    /// A declaration, expression statement or function definition, up to its `;` or body
+    fn phrase(&mut self, indent: usize) {
+        while let Some(next) = self.peek(0) {
+            match next {
+                ";" if self.parens.is_empty() => return self.token(),
+                "{" if self.parens.is_empty() => {
+                    let before = |n: usize| self.pos.checked_sub(n).map(|i| self.text(i));
+                    let aggregate =
+                        |text: Option<&str>| matches!(text, Some("struct" | "union" | "enum"));
+                    if aggregate(before(1)) || aggregate(before(2)) {
+                        let members = before(1) == Some("enum") || before(2) == Some("enum");
+                        self.aggregate_body(indent, members);
+                    } else if before(1) == Some("=") {
+                        self.initializer();
+                    } else {
+                        // a function's body
+                        if self.style.braces != BraceStyle::Attach {
+                            self.newline(indent, false);
+                        }
+                        return self.block(indent);
+                    }
+                }
+                _ => self.token(),
+            }
+        }
+    }
+
+    // `{1, {2, 3}}`, on one line
+    fn initializer(&mut self) {
+        self.token();
+        let mut depth = 1;
+        while depth > 0 && self.pos < self.tokens.len() {
+            match self.peek(0) {
+                Some("{") => depth += 1,
+                Some("}") => depth -= 1,
+                _ => {}
+            }
+            self.token();
+        }
+    }
+
+    // the members of a struct or union, or the enumerators of an enum, one per line
+    fn aggregate_body(&mut self, indent: usize, enumerators: bool) {
+        if self.style.braces == BraceStyle::Allman {
+            self.newline(indent, false);
+        }
+        self.open_brace();
+        while self.pos < self.tokens.len() && self.peek(0) != Some("}") {
+            self.newline(indent + 1, true);
+            if !enumerators {
+                self.phrase(indent + 1);
+                continue;
+            }
+            while let Some(next) = self.peek(0) {
+                match next {
+                    "}" => break,
+                    "," if self.parens.is_empty() => {
+                        self.token();
+                        break;
+                    }
+                    _ => self.token(),
+                }
+            }
+        }
+        self.close_brace(indent);
+        // `struct point {...} *p;` declares a pointer
+        self.prev = Class::Type;
+    }
+
+    fn open_brace(&mut self) {
+        self.token();
+        self.block_start = true;
+    }
+
+    // the `}` closing a block at `indent`, after the comments at the end of the block
+    fn close_brace(&mut self, indent: usize) {
+        if let Some(&(span, _)) = self.tokens.get(self.pos) {
+            self.newline(indent + 1, true);
+            self.flush_trivia(span.start);
+            self.newline(indent, false);
+            self.token();
+        }
+    }
+
+    /// A `{` block whose braces are at `indent`, the `{` going where the caller left off
+    fn block(&mut self, indent: usize) {
+        self.open_brace();
+        while self.pos < self.tokens.len() && self.peek(0) != Some("}") {
+            self.newline(indent + 1, true);
+            self.statement(indent + 1);
+        }
+        self.close_brace(indent);
+    }
+
+    fn statement(&mut self, indent: usize) {
+        let label = matches!(self.tokens.get(self.pos), Some((_, Token::Identifier(_))))
+            && self.peek(1) == Some(":");
+        match self.peek(0) {
+            Some("{") => self.block(indent),
+            Some("if") => self.if_statement(indent),
+            Some("switch") => {
+                self.token();
+                self.parenthesized();
+                self.body(indent);
+            }
+            // labels stand out one level from the statements around them
+            Some("case" | "default") => {
+                self.newline(indent.saturating_sub(1), true);
+                let mut conditionals = 0;
+                while let Some(next) = self.peek(0) {
+                    match next {
+                        ":" if self.parens.is_empty() && conditionals == 0 => break,
+                        ":" => conditionals -= 1,
+                        "?" => conditionals += 1,
+                        _ => {}
+                    }
+                    self.token();
+                }
+                self.token_spaced(Some(false));
+                self.labeled(indent);
+            }
+            _ if label => {
+                self.newline(indent.saturating_sub(1), true);
+                self.token();
+                self.token_spaced(Some(false));
+                self.labeled(indent);
+            }
+            _ => self.phrase(indent),
+        }
+    }
+
+    // the statement after a label, a block going with the label as in `case 1: {`
+    fn labeled(&mut self, indent: usize) {
+        if self.peek(0) == Some("{") {
+            if self.style.braces == BraceStyle::Allman {
+                self.newline(indent.saturating_sub(1), false);
+            }
+            return self.block(indent.saturating_sub(1));
+        }
+        self.newline(indent, false);
+        self.statement(indent);
+    }
+
+    // `if` and its `else if` chain
+    fn if_statement(&mut self, indent: usize) {
+        self.token();
+        self.parenthesized();
+        let braced = self.body(indent);
+        if self.peek(0) != Some("else") {
+            return;
+        }
+        if !braced || self.style.braces == BraceStyle::Allman {
+            self.newline(indent, false);
+        }
+        self.token();
+        match self.peek(0) {
+            Some("if") => self.if_statement(indent),
+            _ => {
+                self.body(indent);
+            }
+        }
+    }
+
+    // the statement controlled by an `if`, `else` or `switch`, whether it is a block
+    fn body(&mut self, indent: usize) -> bool {
+        if self.peek(0) == Some("{") {
+            if self.style.braces == BraceStyle::Allman {
+                self.newline(indent, false);
+            }
+            self.block(indent);
+            return true;
+        }
+        self.newline(indent + 1, false);
+        self.statement(indent + 1);
+        false
+    }
+}
+
+fn is_type_keyword(keyword: &str) -> bool {
+    matches!(
+        keyword,
+        "void"
+            | "char"
+            | "short"
+            | "int"
+            | "long"
+            | "float"
+            | "double"
+            | "signed"
+            | "unsigned"
+            | "const"
+            | "volatile"
+            | "restrict"
+    )
+}
+
+// keywords taking their argument in parentheses like a function
+fn is_callee(text: &str) -> bool {
+    matches!(text, "sizeof" | "_Alignof") || text.starts_with("__builtin_")
+}
+
+// a comment without trailing whitespace on its lines, which are otherwise left as they are
+fn trim_lines(text: &str) -> String {
+    text.lines()
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -1,33 +1,95 @@
-use crate::ast::{BinaryOp, Expr, Program, Statement, Type, UnaryOp};
+use crate::ast::{
+    BinaryOp, Expr, ExprKind, FuncParam, Initializer, Program, Statement, StatementKind, Type,
+    UnaryOp,
+};
+use crate::layout::Layouts;
+use crate::literal;
+use crate::runtime;
+use crate::target::{Syscall, Target};
+use itertools::Itertools;
 use std::collections::HashMap;
 
 #[derive(Clone)]
 struct VariableDef {
     address: usize,
-    id: String,
     var_type: Type,
 }
 
-// TODO: handle scope
-#[derive(Default)]
+/// Labels of the `case` and `default` statements of a switch body, in source order
+struct SwitchLabels {
+    labels: Vec<String>,
+    next: usize,
+}
+
+/// A switch with at least this many cases may use a jump table
+const JUMP_TABLE_MIN_CASES: usize = 4;
+
+/// Where `va_start` finds the variadic arguments of the function being generated
+struct Varargs {
+    /// What the named parameters take, the variadic arguments follow them
+    named: ArgUsage,
+    /// Frame offsets of the saved x0-x7 and q0-q7, on targets passing variadic arguments in
+    /// registers
+    save_areas: Option<(usize, usize)>,
+}
+
 pub struct CodeGenerator {
+    target: Target,
+    freestanding: bool, // no C library calls `main`, the program starts at `_start` instead
     buffer: String,
     variables: HashMap<String, VariableDef>,
     stack_offset: usize,
-    frame_offset: usize,  // offset from frame pointer for next variable
+    frame_offset: usize, // bytes of the current function's frame used by variables so far
     label_counter: usize, // for generating unique labels
+    strings: Vec<(String, Vec<u8>)>, // string literals and their labels, emitted after the code
+    return_type: Type,   // of the function being generated
+    result_address: Option<usize>, // frame slot holding x8, where a large struct result goes
+    layouts: Layouts,
+    switches: Vec<SwitchLabels>,                // innermost switch last
+    break_labels: Vec<String>,                  // where `break` jumps to, innermost last
+    labels: HashMap<String, String>,            // assembly labels of the current function's labels
+    variadic_functions: HashMap<String, usize>, // number of named parameters of each
+    varargs: Option<Varargs>,                   // if the current function is variadic
+}
+
+impl Default for CodeGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CodeGenerator {
     pub fn new() -> Self {
+        Self::with_target(Target::host())
+    }
+
+    pub fn with_target(target: Target) -> Self {
         Self {
+            target,
+            freestanding: false,
             buffer: String::new(),
             variables: HashMap::new(),
             stack_offset: 0,
             frame_offset: 0,
             label_counter: 0,
+            strings: Vec::new(),
+            return_type: Type::Int,
+            result_address: None,
+            layouts: Layouts::new(),
+            switches: Vec::new(),
+            break_labels: Vec::new(),
+            labels: HashMap::new(),
+            variadic_functions: HashMap::new(),
+            varargs: None,
         }
     }
+    /// Generates a program that runs without a C library, whose `_start` calls `main` and
+    /// exits with its result. The file defining `main` brings the runtime along as well.
+    /// Otherwise the C library's startup code calls `main`.
+    pub fn freestanding(&mut self, freestanding: bool) {
+        self.freestanding = freestanding;
+    }
+
     pub fn emit_line(&mut self, code: &str) {
         self.buffer.push_str(code);
         self.buffer.push('\n');
@@ -49,58 +111,566 @@ impl CodeGenerator {
         self.emit_line(&format!("\tb {}", label));
     }
 
+    // push a register onto the value stack
+    fn emit_push(&mut self, reg: &str) {
+        self.stack_offset += 0x10;
+        self.emit_line("\tsub sp, sp, #0x10");
+        self.emit_line(&format!("\tstr {reg}, [sp]"));
+    }
+
+    // pop the top of the value stack into a register
+    fn emit_pop(&mut self, reg: &str) {
+        self.stack_offset -= 0x10;
+        self.emit_line(&format!("\tldr {reg}, [sp]"));
+        self.emit_line("\tadd sp, sp, #0x10");
+    }
+
+    // drop the value on top of the value stack
+    fn emit_drop(&mut self) {
+        self.stack_offset -= 0x10;
+        self.emit_line("\tadd sp, sp, #0x10");
+    }
+
+    // load a value of type `ty` from `address` into x{reg}, extended to 64 bits
+    fn emit_load(&mut self, ty: &Type, reg: u8, address: &str) {
+        match ty.size() {
+            1 if ty.is_signed() => self.emit_line(&format!("\tldrsb x{reg}, {address}")),
+            1 => self.emit_line(&format!("\tldrb w{reg}, {address}")),
+            2 if ty.is_signed() => self.emit_line(&format!("\tldrsh x{reg}, {address}")),
+            2 => self.emit_line(&format!("\tldrh w{reg}, {address}")),
+            4 if ty.is_signed() => self.emit_line(&format!("\tldrsw x{reg}, {address}")),
+            // writing a w register clears the upper half
+            4 => self.emit_line(&format!("\tldr w{reg}, {address}")),
+            8 => self.emit_line(&format!("\tldr x{reg}, {address}")),
+            size => unreachable!("no load for values of size {size}"),
+        }
+    }
+
+    // store the low bytes of x{reg} matching the size of `ty` to `address`
+    fn emit_store(&mut self, ty: &Type, reg: u8, address: &str) {
+        match ty.size() {
+            1 => self.emit_line(&format!("\tstrb w{reg}, {address}")),
+            2 => self.emit_line(&format!("\tstrh w{reg}, {address}")),
+            4 => self.emit_line(&format!("\tstr w{reg}, {address}")),
+            8 => self.emit_line(&format!("\tstr x{reg}, {address}")),
+            size => unreachable!("no store for values of size {size}"),
+        }
+    }
+
+    // truncate x{reg} to a value of type `ty`, as if it was stored and loaded again,
+    // so values narrower than 64 bits are always kept sign- or zero-extended in registers
+    fn emit_extend(&mut self, ty: &Type, reg: u8) {
+        match ty.size() {
+            1 if ty.is_signed() => self.emit_line(&format!("\tsxtb x{reg}, w{reg}")),
+            1 => self.emit_line(&format!("\tuxtb w{reg}, w{reg}")),
+            2 if ty.is_signed() => self.emit_line(&format!("\tsxth x{reg}, w{reg}")),
+            2 => self.emit_line(&format!("\tuxth w{reg}, w{reg}")),
+            4 if ty.is_signed() => self.emit_line(&format!("\tsxtw x{reg}, w{reg}")),
+            // uxtw, a 32-bit move zeroes the upper half
+            4 => self.emit_line(&format!("\tmov w{reg}, w{reg}")),
+            _ => {}
+        }
+    }
+
+    // set the flags for comparing x{reg}, holding a value of type `ty`, against zero
+    fn emit_compare_zero(&mut self, ty: &Type, reg: u8) {
+        if ty.is_floating() {
+            // -0.0 is false as well, so compare the value rather than its bits
+            self.emit_move_to_fp(ty, 0, reg);
+            self.emit_line(&format!("\tfcmp {}, #0.0", fp_reg(ty, 0)));
+        } else {
+            self.emit_line(&format!("\tcmp x{reg}, #0"));
+        }
+    }
+
+    // floating-point values live in general purpose registers on the value stack,
+    // these move their bits to and from the FP registers
+    fn emit_move_to_fp(&mut self, ty: &Type, fp: u8, reg: u8) {
+        let (fp, reg) = (fp_reg(ty, fp), bits_reg(ty, reg));
+        self.emit_line(&format!("\tfmov {fp}, {reg}"));
+    }
+
+    fn emit_move_from_fp(&mut self, ty: &Type, reg: u8, fp: u8) {
+        let (reg, fp) = (bits_reg(ty, reg), fp_reg(ty, fp));
+        self.emit_line(&format!("\tfmov {reg}, {fp}"));
+    }
+
+    // convert x0 from a value of type `from` to one of type `to`
+    fn emit_convert(&mut self, from: &Type, to: &Type) {
+        match (from.is_floating(), to.is_floating()) {
+            (false, false) => {
+                if to.is_integer() {
+                    self.emit_extend(to, 0);
+                }
+            }
+            (false, true) => {
+                let op = if from.is_signed() { "scvtf" } else { "ucvtf" };
+                self.emit_line(&format!("\t{op} {}, x0", fp_reg(to, 0)));
+                self.emit_move_from_fp(to, 0, 0);
+            }
+            (true, false) => {
+                self.emit_move_to_fp(from, 0, 0);
+                let op = if to.is_signed() { "fcvtzs" } else { "fcvtzu" };
+                self.emit_line(&format!("\t{op} x0, {}", fp_reg(from, 0)));
+                self.emit_extend(to, 0);
+            }
+            (true, true) => {
+                if from != to {
+                    self.emit_move_to_fp(from, 0, 0);
+                    self.emit_line(&format!("\tfcvt {}, {}", fp_reg(to, 0), fp_reg(from, 0)));
+                    self.emit_move_from_fp(to, 0, 0);
+                }
+            }
+        }
+    }
+
+    // apply `op` to the floating-point values of type `ty` in x0 and x1, leaving the result in x0
+    fn emit_float_op(&mut self, op: &BinaryOp, ty: &Type) {
+        self.emit_move_to_fp(ty, 0, 0);
+        self.emit_move_to_fp(ty, 1, 1);
+        let (l, r) = (fp_reg(ty, 0), fp_reg(ty, 1));
+        let instruction = match op {
+            BinaryOp::Add => "fadd",
+            BinaryOp::Subtract => "fsub",
+            BinaryOp::Multiply => "fmul",
+            BinaryOp::Divide => "fdiv",
+            _ => {
+                // MI and LS are false for unordered operands, so comparisons with NaN fail
+                let condition = match op {
+                    BinaryOp::Equal => "EQ",
+                    BinaryOp::NotEqual => "NE",
+                    BinaryOp::Greater => "GT",
+                    BinaryOp::Less => "MI",
+                    BinaryOp::GreaterEqual => "GE",
+                    BinaryOp::LessEqual => "LS",
+                    _ => unreachable!("logical operators are short-circuited"),
+                };
+                self.emit_line(&format!("\tfcmp {l}, {r}"));
+                self.emit_line(&format!("\tcset x0, {condition}"));
+                return;
+            }
+        };
+        self.emit_line(&format!("\t{instruction} {l}, {l}, {r}"));
+        self.emit_move_from_fp(ty, 0, 0);
+    }
+
+    // apply `op` to the operands of types `l_type` and `r_type` in x0 and x1, leaving the
+    // result in x0 and clobbering x2
+    fn emit_binary_op(&mut self, op: &BinaryOp, l_type: &Type, r_type: &Type) {
+        match op {
+            _ if l_type.is_floating() => self.emit_float_op(op, l_type),
+            BinaryOp::Add => {
+                // pointer + int advances by whole elements
+                if l_type.is_pointer() {
+                    self.emit_scale(1, l_type);
+                } else if r_type.is_pointer() {
+                    self.emit_scale(0, r_type);
+                }
+                self.emit_line("\tadd x0, x0, x1");
+            }
+            BinaryOp::Subtract => {
+                if l_type.is_pointer() && r_type.is_pointer() {
+                    // pointer difference counts elements, not bytes
+                    let Type::Pointer(pointee) = l_type else {
+                        unreachable!()
+                    };
+                    self.emit_line("\tsub x0, x0, x1");
+                    let size = self.layouts.size_of(pointee);
+                    self.emit_line(&format!("\tmov x1, #{size}"));
+                    self.emit_line("\tsdiv x0, x0, x1");
+                } else {
+                    if l_type.is_pointer() {
+                        self.emit_scale(1, l_type);
+                    }
+                    self.emit_line("\tsub x0, x0, x1");
+                }
+            }
+            BinaryOp::Multiply => self.emit_line("\tmul x0, x0, x1"),
+            // both operands are extended to 64 bits, so the full-width divide is exact
+            BinaryOp::Divide if l_type.is_signed() => self.emit_line("\tsdiv x0, x0, x1"),
+            BinaryOp::Divide => self.emit_line("\tudiv x0, x0, x1"),
+            _ => {
+                // operands share a type, pointers compare as unsigned addresses
+                let signed = l_type.is_signed();
+                let condition = match op {
+                    BinaryOp::Equal => "EQ",
+                    BinaryOp::NotEqual => "NE",
+                    BinaryOp::Greater if signed => "GT",
+                    BinaryOp::Greater => "HI",
+                    BinaryOp::Less if signed => "LT",
+                    BinaryOp::Less => "LO",
+                    BinaryOp::GreaterEqual if signed => "GE",
+                    BinaryOp::GreaterEqual => "HS",
+                    BinaryOp::LessEqual if signed => "LE",
+                    BinaryOp::LessEqual => "LS",
+                    _ => unreachable!(),
+                };
+                self.emit_line("\tcmp x0, x1");
+                self.emit_line("\tmov x0, #0");
+                self.emit_line(&format!("\tcset x0, {}", condition));
+            }
+        }
+    }
+
+    // load an arbitrary 64-bit constant into x{reg}
+    fn emit_mov_imm(&mut self, reg: u8, value: u64) {
+        if value <= 0xffff {
+            self.emit_line(&format!("\tmov x{reg}, #{value}"));
+            return;
+        }
+        self.emit_line(&format!("\tmovz x{reg}, #{}", value & 0xffff));
+        for shift in [16, 32, 48] {
+            let chunk = (value >> shift) & 0xffff;
+            if chunk != 0 {
+                self.emit_line(&format!("\tmovk x{reg}, #{chunk}, lsl #{shift}"));
+            }
+        }
+    }
+
+    // compute the address of the variable at `[fp - offset]` into x{reg}
+    fn emit_frame_address(&mut self, reg: u8, offset: usize) {
+        if offset < 4096 {
+            self.emit_line(&format!("\tsub x{reg}, fp, #{offset}"));
+        } else {
+            self.emit_mov_imm(reg, offset as u64);
+            self.emit_line(&format!("\tsub x{reg}, fp, x{reg}"));
+        }
+    }
+
+    // memory operand for `[fp - offset]`, going through x9 when the offset is too large to encode
+    fn frame_operand(&mut self, offset: usize) -> String {
+        if offset <= 256 {
+            format!("[fp, #-{offset}]")
+        } else {
+            self.emit_frame_address(9, offset);
+            "[x9]".to_string()
+        }
+    }
+
+    // multiply x{reg} by the size of the pointee when doing pointer arithmetic
+    fn emit_scale(&mut self, reg: u8, pointer: &Type) {
+        let Type::Pointer(pointee) = pointer else {
+            unreachable!("only pointers are scaled")
+        };
+        let size = self.layouts.size_of(pointee);
+        if size != 1 {
+            self.emit_line(&format!("\tmov x2, #{size}"));
+            self.emit_line(&format!("\tmul x{reg}, x{reg}, x2"));
+        }
+    }
+
+    // reserve a slot for an object of type `ty` in the frame and return its offset
+    fn allocate(&mut self, ty: &Type) -> usize {
+        let (size, align) = (self.layouts.size_of(ty), self.layouts.align_of(ty));
+        self.reserve(size, align)
+    }
+
+    // reserve `size` bytes aligned to `align` in the frame and return their offset
+    fn reserve(&mut self, size: usize, align: usize) -> usize {
+        self.frame_offset = (self.frame_offset + size).next_multiple_of(align);
+        self.frame_offset
+    }
+
+    // copy `size` bytes from [x{src}] to [x{dst}], advancing both registers and clobbering x10 and x12
+    fn emit_copy(&mut self, src: u8, dst: u8, size: usize) {
+        let words = size / 8;
+        if words > 8 {
+            let loop_label = self.get_unique_label("copy_loop");
+            self.emit_mov_imm(10, words as u64);
+            self.emit_label(&loop_label);
+            self.emit_line(&format!("\tldr x12, [x{src}], #8"));
+            self.emit_line(&format!("\tstr x12, [x{dst}], #8"));
+            self.emit_line("\tsubs x10, x10, #1");
+            self.emit_line(&format!("\tb.ne {}", loop_label));
+        } else {
+            for _ in 0..words {
+                self.emit_line(&format!("\tldr x12, [x{src}], #8"));
+                self.emit_line(&format!("\tstr x12, [x{dst}], #8"));
+            }
+        }
+        for (chunk, load, store, reg) in [
+            (4, "ldr", "str", "w12"),
+            (2, "ldrh", "strh", "w12"),
+            (1, "ldrb", "strb", "w12"),
+        ] {
+            if (size % 8) & chunk != 0 {
+                self.emit_line(&format!("\t{load} {reg}, [x{src}], #{chunk}"));
+                self.emit_line(&format!("\t{store} {reg}, [x{dst}], #{chunk}"));
+            }
+        }
+    }
+
+    // load the `len` <= 8 bytes at [x{base}, #offset] into x{reg}, without reading past them
+    fn emit_load_bytes(&mut self, reg: u8, base: u8, offset: usize, len: usize) {
+        match len {
+            8 => self.emit_line(&format!("\tldr x{reg}, [x{base}, #{offset}]")),
+            4 => self.emit_line(&format!("\tldr w{reg}, [x{base}, #{offset}]")),
+            2 => self.emit_line(&format!("\tldrh w{reg}, [x{base}, #{offset}]")),
+            1 => self.emit_line(&format!("\tldrb w{reg}, [x{base}, #{offset}]")),
+            // assembled from single bytes, little-endian so the last one ends up highest
+            _ => {
+                self.emit_line(&format!("\tmov x{reg}, #0"));
+                for byte in (0..len).rev() {
+                    self.emit_line(&format!("\tldrb w11, [x{base}, #{}]", offset + byte));
+                    self.emit_line(&format!("\torr x{reg}, x11, x{reg}, lsl #8"));
+                }
+            }
+        }
+    }
+
+    // store the low `len` <= 8 bytes of x{reg} to [x{base}, #offset]
+    fn emit_store_bytes(&mut self, reg: u8, base: u8, offset: usize, len: usize) {
+        match len {
+            8 => self.emit_line(&format!("\tstr x{reg}, [x{base}, #{offset}]")),
+            4 => self.emit_line(&format!("\tstr w{reg}, [x{base}, #{offset}]")),
+            2 => self.emit_line(&format!("\tstrh w{reg}, [x{base}, #{offset}]")),
+            1 => self.emit_line(&format!("\tstrb w{reg}, [x{base}, #{offset}]")),
+            _ => {
+                self.emit_line(&format!("\tmov x11, x{reg}"));
+                for byte in 0..len {
+                    self.emit_line(&format!("\tstrb w11, [x{base}, #{}]", offset + byte));
+                    self.emit_line("\tlsr x11, x11, #8");
+                }
+            }
+        }
+    }
+
+    /// Pushes the address of an lvalue onto the value stack
+    fn generate_address(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Var(id) => {
+                let var_offset = self.variables[id].address;
+                self.emit_frame_address(0, var_offset);
+                self.emit_push("x0");
+            }
+            // the address is the pointer value itself
+            ExprKind::Deref(pointer) => self.generate_expr(pointer),
+            ExprKind::Subscript(array, index) => {
+                // either operand may be the pointer, as in `i[a]`
+                let (pointer, index) = if array.ty().is_pointer() {
+                    (array, index)
+                } else {
+                    (index, array)
+                };
+                self.generate_expr(pointer);
+                self.generate_expr(index);
+                self.emit_pop("x1");
+                self.emit_pop("x0");
+                self.emit_scale(1, pointer.ty());
+                self.emit_line("\tadd x0, x0, x1");
+                self.emit_push("x0");
+            }
+            ExprKind::String(bytes) => {
+                let label = self.target.local_label(&self.get_unique_label("str"));
+                self.strings.push((label.clone(), bytes.clone()));
+                for line in self.target.load_address(0, &label) {
+                    self.emit_line(&line);
+                }
+                self.emit_push("x0");
+            }
+            ExprKind::Group(inner) => self.generate_address(inner),
+            // a struct value is its address already, even when it is not an lvalue like `f().x`
+            ExprKind::Member(base, name) | ExprKind::PtrMember(base, name) => {
+                let struct_type = match base.ty() {
+                    Type::Pointer(pointee) => pointee,
+                    ty => ty,
+                };
+                let offset = self
+                    .layouts
+                    .member(struct_type, name)
+                    .expect("members are checked")
+                    .offset;
+                self.generate_expr(base);
+                if offset > 0 {
+                    self.emit_pop("x0");
+                    self.emit_mov_imm(1, offset as u64);
+                    self.emit_line("\tadd x0, x0, x1");
+                    self.emit_push("x0");
+                }
+            }
+            _ => unreachable!("semantic analysis only lets lvalues through"),
+        }
+    }
+
     pub fn generate_expr(&mut self, expr: &Expr) {
         // for now we assume all previous expr-values live on the stack
         // we then use x0, and x1 to pop them
-        match expr {
-            Expr::Const(val) => {
+        match &expr.kind {
+            ExprKind::Unary(UnaryOp::Negative, operand) if expr.ty().is_floating() => {
+                self.generate_expr(operand);
+                self.emit_pop("x0");
+                self.emit_move_to_fp(expr.ty(), 0, 0);
+                let reg = fp_reg(expr.ty(), 0);
+                self.emit_line(&format!("\tfneg {reg}, {reg}"));
+                self.emit_move_from_fp(expr.ty(), 0, 0);
+                self.emit_push("x0");
+            }
+            ExprKind::Const(val, _) => {
                 self.stack_offset += 0x10;
-                self.emit_line(&format!("\tmov x0, #{val}"));
+                self.emit_mov_imm(0, *val);
                 self.emit_line("\tsub sp, sp, #0x10");
                 self.emit_line("\tstr x0, [sp]");
             }
-            Expr::Var(id) => {
+            ExprKind::FloatConst(value, ty) => {
+                // materialize the bits, the value stack holds floats in general purpose registers
+                let bits = match ty {
+                    Type::Float => (*value as f32).to_bits() as u64,
+                    _ => value.to_bits(),
+                };
+                self.emit_mov_imm(0, bits);
+                self.emit_push("x0");
+            }
+            ExprKind::Char(c) => {
+                // plain `char` is signed, so e.g. '\xff' is -1
+                self.emit_mov_imm(0, *c as i8 as u64);
+                self.emit_push("x0");
+            }
+            // arrays and structs evaluate to their address
+            ExprKind::Var(_) if by_address(expr.ty()) => self.generate_address(expr),
+            ExprKind::String(_) => self.generate_address(expr),
+            ExprKind::Var(id) => {
                 // load value from var-address to x0 reg
-                match self.variables.get(id) {
+                match self.variables.get(id).cloned() {
                     Some(def) => {
-                        let var_offset = def.address;
-                        self.emit_line(&format!("\tldr x0, [fp, #-{var_offset}]"));
+                        let address = self.frame_operand(def.address);
+                        self.emit_load(&def.var_type, 0, &address);
                         self.emit_line("\tsub sp, sp, #0x10");
                         self.emit_line("\tstr x0, [sp]");
                         self.stack_offset += 0x10;
                     }
-                    None => panic!(),
+                    None => unreachable!("variables are resolved by semantic analysis"),
                 }
             }
-            Expr::Assignment(id, expr) => {
+            ExprKind::Assignment(target, value) if target.ty().is_struct() => {
+                self.generate_expr(value);
+                self.generate_address(target);
+                // copy the members over, the target is the result
+                self.emit_pop("x1");
+                self.emit_line("\tldr x0, [sp]");
+                self.emit_line("\tstr x1, [sp]");
+                let size = self.layouts.size_of(target.ty());
+                self.emit_copy(0, 1, size);
+            }
+            ExprKind::Assignment(target, expr) => {
                 self.generate_expr(expr);
-                // save value to var-address and return the assigned value
-                match self.variables.get(id) {
-                    Some(def) => {
-                        // load value from stack but keep it there for return
-                        let var_offset = def.address;
-                        self.emit_line("\tldr x0, [sp]"); // load value into x0
-                        self.emit_line(&format!("\tstr x0, [fp, #-{var_offset}]"));
-                        // store to variable
-                        // value remains on stack as the result of the assignment expression
-                    }
-                    None => panic!(),
+                self.generate_address(target);
+                // save value to the target address and return the assigned value
+                self.emit_pop("x1");
+                // load value from stack but keep it there for return, as the target type holds it
+                self.emit_line("\tldr x0, [sp]");
+                self.emit_extend(target.ty(), 0);
+                self.emit_line("\tstr x0, [sp]");
+                self.emit_store(target.ty(), 0, "[x1]");
+            }
+            ExprKind::CompoundAssignment(op, target, value) => {
+                // the address is computed once, and stays on the stack until the store
+                self.generate_address(target);
+                self.generate_expr(value);
+                self.emit_pop("x1");
+                self.emit_line("\tldr x2, [sp]");
+                let ty = target.ty();
+                self.emit_load(ty, 0, "[x2]");
+                // pointers step by elements, anything else is computed in the value's type
+                let op_ty = match ty.is_pointer() {
+                    true => ty.clone(),
+                    false => value.ty().clone(),
+                };
+                self.emit_convert(ty, &op_ty);
+                self.emit_binary_op(op, &op_ty, value.ty());
+                if op_ty.is_integer() {
+                    self.emit_extend(&op_ty, 0);
                 }
+                self.emit_convert(&op_ty, ty);
+                // the assigned value replaces the address as the result
+                self.emit_line("\tldr x2, [sp]");
+                self.emit_line("\tstr x0, [sp]");
+                self.emit_store(ty, 0, "[x2]");
             }
-            Expr::Group(expr) => {
-                self.generate_expr(expr);
+            ExprKind::AddressOf(inner) => {
+                self.generate_address(inner);
+            }
+            ExprKind::Deref(_)
+            | ExprKind::Subscript(..)
+            | ExprKind::Member(..)
+            | ExprKind::PtrMember(..) => {
+                self.generate_address(expr);
+                // an element or member that is itself an array or struct is used by address
+                if !by_address(expr.ty()) {
+                    self.emit_pop("x0");
+                    self.emit_load(expr.ty(), 0, "[x0]");
+                    self.emit_push("x0");
+                }
+            }
+            ExprKind::Convert(inner) => {
+                // arrays are already represented by their address, so decaying is free
+                self.generate_expr(inner);
+                if !inner.ty().is_array() {
+                    self.emit_pop("x0");
+                    self.emit_convert(inner.ty(), expr.ty());
+                    self.emit_push("x0");
+                }
+            }
+            ExprKind::Call(name, args) => self.generate_call(name, args, expr.ty()),
+            ExprKind::SizeOf(_) | ExprKind::SizeOfType(_) | ExprKind::AlignOf(_) => {
+                unreachable!("folded into constants by semantic analysis")
+            }
+            ExprKind::Cast(target, inner) => {
+                self.generate_expr(inner);
+                // narrowing truncates, a pointer keeps its bits, and a cast to `void` drops the value
+                if *target != Type::Void {
+                    self.emit_pop("x0");
+                    self.emit_convert(inner.ty(), target);
+                    self.emit_push("x0");
+                }
             }
-            Expr::Unary(op, expr) => {
+            ExprKind::Group(expr) => {
                 self.generate_expr(expr);
+            }
+            ExprKind::Comma(first, second) => {
+                self.generate_expr(first);
+                self.emit_drop();
+                self.generate_expr(second);
+            }
+            // the builtins have no value of their own, they leave zero like a `void` call would
+            ExprKind::VaStart(ap, _) => {
+                self.generate_address(ap);
+                self.emit_pop("x0");
+                self.emit_va_start();
+                self.emit_push("xzr");
+            }
+            ExprKind::VaArg(ap, ty) => {
+                self.generate_address(ap);
+                self.emit_pop("x1");
+                self.emit_va_arg(ty);
+                self.emit_push("x0");
+            }
+            ExprKind::VaEnd(_) => self.emit_push("xzr"),
+            ExprKind::VaCopy(dest, src) => {
+                self.generate_address(dest);
+                self.generate_address(src);
+                self.emit_pop("x0");
+                self.emit_pop("x1");
+                let size = self.layouts.size_of(dest.ty());
+                self.emit_copy(0, 1, size);
+                self.emit_push("xzr");
+            }
+            ExprKind::Unary(op, operand) => {
+                self.generate_expr(operand);
                 // pop previous result into x0
                 self.stack_offset -= 0x10;
                 self.emit_line("\tldr x0, [sp]");
                 self.emit_line("\tadd sp, sp, #0x10");
                 match op {
-                    UnaryOp::Negative => self.emit_line("\tneg\tx0, x0"),
-                    UnaryOp::BitwiseNegate => self.emit_line("\tmvn\tx0, x0"),
+                    UnaryOp::Negative => {
+                        self.emit_line("\tneg\tx0, x0");
+                        self.emit_extend(expr.ty(), 0);
+                    }
+                    UnaryOp::BitwiseNegate => {
+                        self.emit_line("\tmvn\tx0, x0");
+                        self.emit_extend(expr.ty(), 0);
+                    }
                     UnaryOp::Negate => {
-                        self.emit_line("\tcmp\tx0, #0");
+                        self.emit_compare_zero(operand.ty(), 0);
                         self.emit_line("\tcset\tx0, EQ");
                     }
                 }
@@ -109,7 +679,7 @@ impl CodeGenerator {
                 self.emit_line("\tsub sp, sp, #0x10");
                 self.emit_line("\tstr x0, [sp]");
             }
-            Expr::Binary(op, l_expr, r_expr) => {
+            ExprKind::Binary(op, l_expr, r_expr) => {
                 match op {
                     BinaryOp::LogicalOr => {
                         // short-circuit OR: if left is true, skip right
@@ -119,7 +689,7 @@ impl CodeGenerator {
                         // Evaluate left expression
                         self.generate_expr(l_expr);
                         self.emit_line("\tldr x0, [sp]"); // peek at result
-                        self.emit_line("\tcmp x0, #0"); // test if true
+                        self.emit_compare_zero(l_expr.ty(), 0); // test if true
                         self.emit_line(&format!("\tbeq {}", right_label)); // if false, evaluate right
 
                         // Left was true, set result to 1 and skip right
@@ -135,7 +705,7 @@ impl CodeGenerator {
 
                         // Convert right result to 0 or 1
                         self.emit_line("\tldr x0, [sp]");
-                        self.emit_line("\tcmp x0, #0");
+                        self.emit_compare_zero(r_expr.ty(), 0);
                         self.emit_line("\tmov x0, #0");
                         self.emit_line("\tcset x0, NE"); // set to 1 if not equal to 0
                         self.emit_line("\tstr x0, [sp]");
@@ -150,7 +720,7 @@ impl CodeGenerator {
                         // Evaluate left expression
                         self.generate_expr(l_expr);
                         self.emit_line("\tldr x0, [sp]"); // peek at result
-                        self.emit_line("\tcmp x0, #0"); // test if false
+                        self.emit_compare_zero(l_expr.ty(), 0); // test if false
                         self.emit_line(&format!("\tbne {}", right_label)); // if true, evaluate right
 
                         // Left was false, set result to 0 and skip right
@@ -166,7 +736,7 @@ impl CodeGenerator {
 
                         // Convert right result to 0 or 1
                         self.emit_line("\tldr x0, [sp]");
-                        self.emit_line("\tcmp x0, #0");
+                        self.emit_compare_zero(r_expr.ty(), 0);
                         self.emit_line("\tmov x0, #0");
                         self.emit_line("\tcset x0, NE"); // set to 1 if not equal to 0
                         self.emit_line("\tstr x0, [sp]");
@@ -184,27 +754,12 @@ impl CodeGenerator {
                         self.emit_line("\tldr x0, [sp, #0x10]");
                         self.emit_line("\tadd sp, sp, #32"); //  x0 is l_expr, x1 is r_expr
 
-                        // do op on x0 x1
-                        match op {
-                            BinaryOp::Add => self.emit_line("\tadd x0, x0, x1"),
-                            BinaryOp::Subtract => self.emit_line("\tsub x0, x0, x1"),
-                            BinaryOp::Multiply => self.emit_line("\tmul x0, x0, x1"),
-                            BinaryOp::Divide => self.emit_line("\tsdiv x0, x0, x1"),
-                            _ => {
-                                let condition = match op {
-                                    BinaryOp::Equal => "EQ",
-                                    BinaryOp::NotEqual => "NE",
-                                    BinaryOp::Greater => "GT",
-                                    BinaryOp::Less => "LT",
-                                    BinaryOp::GreaterEqual => "GE",
-                                    BinaryOp::LessEqual => "LE",
-                                    _ => unreachable!(),
-                                };
-                                self.emit_line("\tcmp x0, x1");
-                                self.emit_line("\tmov x0, #0");
-                                self.emit_line(&format!("\tcset x0, {}", condition));
-                            }
+                        self.emit_binary_op(op, l_expr.ty(), r_expr.ty());
+                        // wrap around results that don't fit the type, e.g. of unsigned arithmetic
+                        if expr.ty().is_integer() {
+                            self.emit_extend(expr.ty(), 0);
                         }
+
                         // push res
                         self.stack_offset += 0x10;
                         self.emit_line("\tsub sp, sp, #0x10");
@@ -212,7 +767,7 @@ impl CodeGenerator {
                     }
                 }
             }
-            Expr::Conditional(cond, if_expr, else_expr) => {
+            ExprKind::Conditional(cond, if_expr, else_expr) => {
                 //evaluate cond
                 self.generate_expr(cond);
                 // pop cond result
@@ -225,7 +780,7 @@ impl CodeGenerator {
                 let else_lbl = self.get_unique_label("branch_else");
                 let end_lbl = self.get_unique_label("branch_end");
 
-                self.emit_line("\tsubs x0, x0, #0"); // doesnt change x0 just updates flags
+                self.emit_compare_zero(cond.ty(), 0); // doesnt change x0 just updates flags
                 self.emit_line("\tcset x0, eq"); // updates based on flags
                 self.emit_line(&format!("\ttbnz w0, #0, {}", else_lbl));
                 // branch to if and run
@@ -244,24 +799,34 @@ impl CodeGenerator {
     }
 
     fn generate_statement(&mut self, statement: &Statement) {
-        match statement {
-            Statement::Compound(statements) => {
+        match &statement.kind {
+            StatementKind::Compound(statements) => {
+                // declarations inside the block may shadow outer ones until it ends
+                let outer_scope = self.variables.clone();
                 for s in statements {
                     self.generate_statement(s);
                 }
+                self.variables = outer_scope;
             }
-            Statement::Return(expr) => {
+            StatementKind::Return(expr) => {
                 self.generate_expr(expr);
                 self.stack_offset -= 0x10;
                 self.emit_line("\tldr x0, [sp]");
                 self.emit_line("\tadd sp, sp, #0x10");
+                let return_type = self.return_type.clone();
+                // floating-point results are returned in v0
+                if return_type.is_floating() {
+                    self.emit_move_to_fp(&return_type, 0, 0);
+                } else if return_type.is_struct() {
+                    self.emit_return_struct(&return_type);
+                }
 
                 // function epilogue: restore stack and frame pointer
                 self.emit_line("\tmov sp, fp"); // restore stack pointer
                 self.emit_line("\tldp fp, lr, [sp], #16"); // restore fp and lr, post-increment sp
                 self.emit_line("\tret");
             }
-            Statement::If(cond, branch, else_branch) => {
+            StatementKind::If(cond, branch, else_branch) => {
                 self.generate_expr(cond);
                 // pop cond result
                 self.stack_offset -= 0x10;
@@ -273,7 +838,7 @@ impl CodeGenerator {
                 let else_lbl = self.get_unique_label("branch_else");
                 let end_lbl = self.get_unique_label("branch_end");
 
-                self.emit_line("\tsubs x0, x0, #0"); // doesnt change x0 just updates flags
+                self.emit_compare_zero(cond.ty(), 0); // doesnt change x0 just updates flags
                 self.emit_line("\tcset x0, eq"); // updates based on flags
 
                 // if we have an else, branch to there, else just branch to end
@@ -299,77 +864,841 @@ impl CodeGenerator {
                 }
                 self.emit_label(&end_lbl);
             }
-            Statement::Declare(var_type, id, value) => {
-                // check if already declared
-                if self.variables.contains_key(id) {
-                    panic!(); // TODO: proper error handling
+            StatementKind::Switch(value, body) => {
+                let mut cases = Vec::new();
+                collect_cases(body, &mut cases);
+                let labels: Vec<String> = cases
+                    .iter()
+                    .map(|case| match case {
+                        Some(_) => self.get_unique_label("switch_case"),
+                        None => self.get_unique_label("switch_default"),
+                    })
+                    .collect();
+                let end_lbl = self.get_unique_label("switch_end");
+                // without a `default`, values matching no case skip the whole body
+                let default_lbl = match cases.iter().position(Option::is_none) {
+                    Some(i) => labels[i].clone(),
+                    None => end_lbl.clone(),
+                };
+                let targets: Vec<(i64, String)> = cases
+                    .iter()
+                    .zip(&labels)
+                    .filter_map(|(case, label)| Some(((*case)?, label.clone())))
+                    .collect();
+
+                self.generate_expr(value);
+                self.emit_pop("x0");
+                if is_dense(&targets) {
+                    self.emit_jump_table(&targets, &default_lbl);
+                } else {
+                    self.emit_compare_chain(&targets, &default_lbl);
                 }
 
-                // allocate space for the variable
-                self.frame_offset += 0x10;
-                self.emit_line("\tsub sp, sp, #0x10");
-                self.stack_offset += 0x10;
+                self.switches.push(SwitchLabels { labels, next: 0 });
+                self.break_labels.push(end_lbl.clone());
+                self.generate_statement(body);
+                self.break_labels.pop();
+                self.switches.pop();
+                self.emit_label(&end_lbl);
+            }
+            StatementKind::Case(_, stmt) | StatementKind::Default(stmt) => {
+                let switch = self
+                    .switches
+                    .last_mut()
+                    .expect("labels outside a switch are rejected by semantic analysis");
+                let label = switch.labels[switch.next].clone();
+                switch.next += 1;
+                // falling through from the previous case just runs into the label
+                self.emit_label(&label);
+                self.generate_statement(stmt);
+            }
+            StatementKind::Break => {
+                let label = self
+                    .break_labels
+                    .last()
+                    .expect("a misplaced break is rejected by semantic analysis")
+                    .clone();
+                self.emit_jump(&label);
+            }
+            StatementKind::Labeled(name, stmt) => {
+                let label = self.user_label(name);
+                self.emit_label(&label);
+                self.generate_statement(stmt);
+            }
+            StatementKind::Goto(name) => {
+                let label = self.user_label(name);
+                self.emit_jump(&label);
+            }
+            StatementKind::Declare(var_type, id, value) => {
+                // reserve space in the frame, the prologue allocates all of it at once
+                // (the initializer may reserve more, e.g. for a call's struct result)
+                let offset = self.allocate(var_type);
 
                 match value {
                     None => {
                         // variable allocated but not initialized
                     }
-                    Some(expr) => {
-                        // generate the initialization expression
-                        self.generate_expr(expr);
-                        // pop the result and store it in the variable location
-                        self.emit_line("\tldr x0, [sp]");
-                        self.stack_offset -= 0x10;
-                        self.emit_line("\tadd sp, sp, #0x10");
-                        self.emit_line(&format!("\tstr x0, [fp, #-{}]", self.frame_offset));
+                    Some(init) if var_type.is_array() || matches!(init, Initializer::List(..)) => {
+                        // elements and members without an initializer are zero
+                        let size = self.layouts.size_of(var_type);
+                        self.emit_zero(offset, size);
+                        self.generate_initializer(var_type, init, offset);
                     }
+                    Some(init) => self.generate_initializer(var_type, init, offset),
                 }
 
                 self.variables.insert(
                     id.clone(),
                     VariableDef {
-                        id: id.clone(),
                         var_type: var_type.clone(),
-                        address: self.frame_offset,
+                        address: offset,
                     },
                 );
             }
-            Statement::Expr(e) => {
+            StatementKind::Expr(e) => {
+                // evaluated for its side effects only
                 self.generate_expr(e);
+                self.emit_drop();
             }
+            StatementKind::Empty => {}
+            // only matters to semantic analysis
+            StatementKind::DeclareType(_) | StatementKind::Typedef(..) => {}
+            StatementKind::Error => unreachable!("programs with syntax errors are never generated"),
         }
     }
 
-    pub fn generate(&mut self, program: &Program) {
-        self.emit_line(".global _start");
+    // the assembly label for the label `name` of the current function, which may be
+    // jumped to before it is defined
+    fn user_label(&mut self, name: &str) -> String {
+        if let Some(label) = self.labels.get(name) {
+            return label.clone();
+        }
+        let label = self.get_unique_label(&format!("label_{}", name));
+        self.labels.insert(name.to_string(), label.clone());
+        label
+    }
+
+    // branch to the label of the case matching x0 by comparing against each in turn
+    fn emit_compare_chain(&mut self, targets: &[(i64, String)], default_lbl: &str) {
+        for (value, label) in targets {
+            self.emit_mov_imm(1, *value as u64);
+            self.emit_line("\tcmp x0, x1");
+            self.emit_line(&format!("\tb.eq {}", label));
+        }
+        self.emit_jump(default_lbl);
+    }
+
+    // branch to the label of the case matching x0 through a table of offsets, with one
+    // entry for every value from the smallest case to the largest
+    fn emit_jump_table(&mut self, targets: &[(i64, String)], default_lbl: &str) {
+        let (min, max) = targets
+            .iter()
+            .map(|(value, _)| *value)
+            .minmax()
+            .into_option()
+            .expect("a jump table has cases");
+        let table_lbl = self.get_unique_label("switch_table");
+
+        // values below `min` wrap around to large indices, so one unsigned compare catches both ends
+        self.emit_mov_imm(1, min as u64);
+        self.emit_line("\tsub x0, x0, x1");
+        self.emit_mov_imm(1, max.wrapping_sub(min) as u64);
+        self.emit_line("\tcmp x0, x1");
+        self.emit_line(&format!("\tb.hi {}", default_lbl));
+        self.emit_line(&format!("\tadr x1, {}", table_lbl));
+        self.emit_line("\tldrsw x2, [x1, x0, lsl #2]");
+        self.emit_line("\tadd x1, x1, x2");
+        self.emit_line("\tbr x1");
+
+        let labels: HashMap<i64, &String> = targets
+            .iter()
+            .map(|(value, label)| (*value, label))
+            .collect();
+        self.emit_label(&table_lbl);
+        for value in min..=max {
+            let label = labels
+                .get(&value)
+                .map_or(default_lbl, |label| label.as_str());
+            self.emit_line(&format!("\t.word {} - {}", label, table_lbl));
+        }
+    }
+
+    // store the value(s) of `init` into the object of type `ty` at `[fp - offset]`
+    fn generate_initializer(&mut self, ty: &Type, init: &Initializer, offset: usize) {
+        match init {
+            // a string literal filling a `char` array, the rest of which is already zeroed
+            Initializer::Single(Expr {
+                kind: ExprKind::String(bytes),
+                ..
+            }) if ty.is_array() => {
+                for (i, byte) in bytes.iter().take(self.layouts.size_of(ty)).enumerate() {
+                    self.emit_mov_imm(0, *byte as u64);
+                    let address = self.frame_operand(offset - i);
+                    self.emit_line(&format!("\tstrb w0, {address}"));
+                }
+            }
+            Initializer::Single(expr) if ty.is_struct() => {
+                self.generate_expr(expr);
+                self.emit_pop("x0");
+                self.emit_frame_address(1, offset);
+                let size = self.layouts.size_of(ty);
+                self.emit_copy(0, 1, size);
+            }
+            Initializer::Single(expr) => {
+                // generate the initialization expression
+                self.generate_expr(expr);
+                // pop the result and store it in the variable location
+                self.emit_pop("x0");
+                let address = self.frame_operand(offset);
+                self.emit_store(ty, 0, &address);
+            }
+            Initializer::List(items, _) => match ty {
+                Type::Array(element, _) => {
+                    // element i lives i * size bytes above the start of the array
+                    let size = self.layouts.size_of(element);
+                    for (i, item) in items.iter().enumerate() {
+                        self.generate_initializer(element, item, offset - i * size);
+                    }
+                }
+                Type::Struct(_) => {
+                    let members = self
+                        .layouts
+                        .of(ty)
+                        .expect("struct types are resolved")
+                        .members
+                        .clone();
+                    for (member, item) in members.iter().zip(items) {
+                        self.generate_initializer(
+                            &member.member_type,
+                            item,
+                            offset - member.offset,
+                        );
+                    }
+                }
+                _ => self.generate_initializer(ty, &items[0], offset),
+            },
+        }
+    }
+
+    // zero `size` bytes starting at `[fp - offset]`
+    fn emit_zero(&mut self, offset: usize, size: usize) {
+        if size == 0 {
+            return;
+        }
+        let loop_label = self.get_unique_label("zero_loop");
+        self.emit_frame_address(9, offset);
+        self.emit_mov_imm(10, size as u64);
+        self.emit_label(&loop_label);
+        self.emit_line("\tstrb wzr, [x9], #1");
+        self.emit_line("\tsubs x10, x10, #1");
+        self.emit_line(&format!("\tb.ne {}", loop_label));
+    }
+
+    // how a value of type `ty` is passed to or returned from a function
+    fn pass_as(&self, ty: &Type) -> PassAs {
+        if ty.is_floating() {
+            return PassAs::Fp(ty.clone(), 1);
+        }
+        if !ty.is_struct() {
+            return PassAs::Gp(1);
+        }
+        match self.layouts.hfa(ty) {
+            Some((element, count)) => PassAs::Fp(element, count as u8),
+            None => match self.layouts.size_of(ty) {
+                size if size > 16 => PassAs::Indirect,
+                size => PassAs::Gp(size.div_ceil(8) as u8),
+            },
+        }
+    }
+
+    // where AAPCS64 puts arguments of the given types, the first `named` of which match declared
+    // parameters, and what they take altogether
+    fn classify(&self, types: &[&Type], named: usize) -> (Vec<ArgLocation>, ArgUsage) {
+        let (mut next_gp, mut next_fp, mut stack) = (0, 0, 0);
+        let mut locations = Vec::with_capacity(types.len());
+        for (i, ty) in types.iter().enumerate() {
+            // Apple platforms pass the variadic arguments on the stack, the others like any
+            let registers = i < named || self.target != Target::MacOs;
+            let location = match self.pass_as(ty) {
+                PassAs::Fp(_, count) if registers && next_fp + count <= 8 => {
+                    next_fp += count;
+                    ArgLocation::Fp(next_fp - count)
+                }
+                PassAs::Gp(count) if registers && next_gp + count <= 8 => {
+                    next_gp += count;
+                    ArgLocation::Gp(next_gp - count)
+                }
+                PassAs::Indirect if registers && next_gp < 8 => {
+                    next_gp += 1;
+                    ArgLocation::Gp(next_gp - 1)
+                }
+                pass => {
+                    // an aggregate that does not fit uses up the rest of the registers
+                    match pass {
+                        _ if !registers => {}
+                        PassAs::Fp(..) => next_fp = 8,
+                        _ => next_gp = 8,
+                    }
+                    // each argument takes 8 bytes, a struct passed by value as many as it needs
+                    let size = match pass {
+                        PassAs::Indirect => 8,
+                        _ if ty.is_struct() => self.layouts.size_of(ty).next_multiple_of(8),
+                        _ => 8,
+                    };
+                    stack += size;
+                    ArgLocation::Stack(stack - size)
+                }
+            };
+            locations.push(location);
+        }
+        let usage = ArgUsage {
+            gp: next_gp,
+            fp: next_fp,
+            stack,
+        };
+        (locations, usage)
+    }
+
+    // evaluate the arguments, move them to where AAPCS64 expects them and call `name`
+    fn generate_call(&mut self, name: &str, args: &[Expr], return_type: &Type) {
+        for arg in args {
+            self.generate_expr(arg);
+        }
+
+        // integers, pointers and small structs go in x0-x7, floating-point values in v0-v7,
+        // the rest on the stack below the evaluated values
+        let types: Vec<&Type> = args.iter().map(|arg| arg.ty()).collect();
+        let named = self.variadic_functions.get(name).copied();
+        let (locations, usage) = self.classify(&types, named.unwrap_or(args.len()));
+        let stack_area = usage.stack.next_multiple_of(16);
+        if stack_area > 0 {
+            self.emit_line(&format!("\tsub sp, sp, #{stack_area}"));
+        }
+        let slot = |i: usize| stack_area + (args.len() - 1 - i) * 0x10;
+
+        // copying clobbers registers, so large structs and stacked arguments are handled first
+        for (i, (arg, location)) in args.iter().zip(&locations).enumerate() {
+            let ty = arg.ty();
+            let size = if ty.is_struct() {
+                self.layouts.size_of(ty)
+            } else {
+                8
+            };
+            let indirect = matches!(self.pass_as(ty), PassAs::Indirect);
+            if indirect {
+                // the callee may modify its argument, so it gets the address of a copy
+                let copy = self.allocate(ty);
+                self.emit_line(&format!("\tldr x0, [sp, #{}]", slot(i)));
+                self.emit_frame_address(1, copy);
+                self.emit_copy(0, 1, size);
+                self.emit_frame_address(1, copy);
+                self.emit_line(&format!("\tstr x1, [sp, #{}]", slot(i)));
+            }
+            if let ArgLocation::Stack(offset) = location {
+                if ty.is_struct() && !indirect {
+                    self.emit_line(&format!("\tldr x0, [sp, #{}]", slot(i)));
+                    self.emit_line(&format!("\tadd x1, sp, #{offset}"));
+                    self.emit_copy(0, 1, size);
+                } else {
+                    self.emit_line(&format!("\tldr x9, [sp, #{}]", slot(i)));
+                    self.emit_line(&format!("\tstr x9, [sp, #{offset}]"));
+                }
+            }
+        }
+        for (i, (arg, location)) in args.iter().zip(&locations).enumerate() {
+            let ty = arg.ty();
+            match (location, self.pass_as(ty)) {
+                // a struct is split into 8 byte pieces, one per register
+                (ArgLocation::Gp(first), PassAs::Gp(count)) if ty.is_struct() => {
+                    let size = self.layouts.size_of(ty);
+                    self.emit_line(&format!("\tldr x9, [sp, #{}]", slot(i)));
+                    for k in 0..count {
+                        let offset = k as usize * 8;
+                        self.emit_load_bytes(first + k, 9, offset, (size - offset).min(8));
+                    }
+                }
+                (ArgLocation::Gp(reg), _) => {
+                    self.emit_line(&format!("\tldr x{reg}, [sp, #{}]", slot(i)))
+                }
+                // one register per member of a homogeneous floating-point aggregate
+                (ArgLocation::Fp(first), PassAs::Fp(element, count)) if ty.is_struct() => {
+                    self.emit_line(&format!("\tldr x9, [sp, #{}]", slot(i)));
+                    for k in 0..count {
+                        let reg = fp_reg(&element, first + k);
+                        self.emit_line(&format!(
+                            "\tldr {reg}, [x9, #{}]",
+                            k as usize * element.size()
+                        ));
+                    }
+                }
+                (ArgLocation::Fp(reg), _) => {
+                    let reg = fp_reg(ty, *reg);
+                    self.emit_line(&format!("\tldr {reg}, [sp, #{}]", slot(i)));
+                }
+                (ArgLocation::Stack(_), _) => {}
+            }
+        }
+
+        // a struct result gets a slot in the caller's frame, a large one is written there
+        // by the callee through x8
+        let result = return_type.is_struct().then(|| self.allocate(return_type));
+        let return_as = self.pass_as(return_type);
+        if let (Some(result), PassAs::Indirect) = (result, &return_as) {
+            self.emit_frame_address(8, result);
+        }
+
+        // AAPCS64 wants sp 16-byte aligned at every call, which holds as the frame and each slot
+        // of the value stack and the stack arguments are multiples of 16 bytes
+        debug_assert_eq!(self.stack_offset % 16, 0, "misaligned stack at a call");
+        self.emit_line(&format!("\tbl {}", self.target.symbol(name)));
+
+        // drop the arguments and push the result instead
+        let used = stack_area + args.len() * 0x10;
+        if used > 0 {
+            self.stack_offset -= args.len() * 0x10;
+            self.emit_line(&format!("\tadd sp, sp, #{used}"));
+        }
+        if let Some(result) = result {
+            let size = self.layouts.size_of(return_type);
+            self.emit_frame_address(9, result);
+            match return_as {
+                PassAs::Gp(count) => {
+                    for k in 0..count {
+                        let offset = k as usize * 8;
+                        self.emit_store_bytes(k, 9, offset, (size - offset).min(8));
+                    }
+                }
+                PassAs::Fp(element, count) => {
+                    for k in 0..count {
+                        let reg = fp_reg(&element, k);
+                        self.emit_line(&format!(
+                            "\tstr {reg}, [x9, #{}]",
+                            k as usize * element.size()
+                        ));
+                    }
+                }
+                PassAs::Indirect => {}
+            }
+            self.emit_push("x9");
+        } else if return_type.is_floating() {
+            self.emit_move_from_fp(return_type, 0, 0);
+            self.emit_push("x0");
+        } else {
+            // the callee may leave the upper bits of narrow results undefined
+            self.emit_extend(return_type, 0);
+            self.emit_push("x0");
+        }
+    }
+
+    // move the struct at [x0] to where the caller expects the result of the function
+    fn emit_return_struct(&mut self, ty: &Type) {
+        let size = self.layouts.size_of(ty);
+        match self.pass_as(ty) {
+            PassAs::Indirect => {
+                let result = self.result_address.expect("x8 is saved in the prologue");
+                let address = self.frame_operand(result);
+                self.emit_line(&format!("\tldr x1, {address}"));
+                self.emit_copy(0, 1, size);
+            }
+            PassAs::Gp(count) => {
+                self.emit_line("\tmov x9, x0");
+                for k in 0..count {
+                    let offset = k as usize * 8;
+                    self.emit_load_bytes(k, 9, offset, (size - offset).min(8));
+                }
+            }
+            PassAs::Fp(element, count) => {
+                for k in 0..count {
+                    let reg = fp_reg(&element, k);
+                    self.emit_line(&format!(
+                        "\tldr {reg}, [x0, #{}]",
+                        k as usize * element.size()
+                    ));
+                }
+            }
+        }
+    }
+
+    // copy the parameters from where the caller put them into the frame, so they act like locals
+    fn generate_params(&mut self, params: &[FuncParam]) {
+        let types: Vec<&Type> = params.iter().map(|param| &param.param_type).collect();
+        let (locations, _) = self.classify(&types, types.len());
+        for (param, location) in params.iter().zip(locations) {
+            let ty = &param.param_type;
+            let offset = self.allocate(ty);
+            let size = self.layouts.size_of(ty);
+            match (location, self.pass_as(ty)) {
+                // the caller passed the address of its own copy
+                (ArgLocation::Gp(reg), PassAs::Indirect) => {
+                    self.emit_frame_address(9, offset);
+                    self.emit_copy(reg, 9, size);
+                }
+                (ArgLocation::Stack(stacked), PassAs::Indirect) => {
+                    self.emit_line(&format!("\tldr x11, [fp, #{}]", 16 + stacked));
+                    self.emit_frame_address(9, offset);
+                    self.emit_copy(11, 9, size);
+                }
+                (ArgLocation::Gp(first), PassAs::Gp(count)) if ty.is_struct() => {
+                    self.emit_frame_address(9, offset);
+                    for k in 0..count {
+                        let piece = k as usize * 8;
+                        self.emit_store_bytes(first + k, 9, piece, (size - piece).min(8));
+                    }
+                }
+                (ArgLocation::Fp(first), PassAs::Fp(element, count)) if ty.is_struct() => {
+                    self.emit_frame_address(9, offset);
+                    for k in 0..count {
+                        let reg = fp_reg(&element, first + k);
+                        self.emit_line(&format!(
+                            "\tstr {reg}, [x9, #{}]",
+                            k as usize * element.size()
+                        ));
+                    }
+                }
+                (ArgLocation::Stack(stacked), _) if ty.is_struct() => {
+                    self.emit_line(&format!("\tadd x11, fp, #{}", 16 + stacked));
+                    self.emit_frame_address(9, offset);
+                    self.emit_copy(11, 9, size);
+                }
+                (ArgLocation::Fp(reg), _) => {
+                    let address = self.frame_operand(offset);
+                    self.emit_line(&format!("\tstr {}, {address}", fp_reg(ty, reg)));
+                }
+                (ArgLocation::Gp(reg), _) => {
+                    let address = self.frame_operand(offset);
+                    self.emit_store(ty, reg, &address);
+                }
+                (ArgLocation::Stack(stacked), _) => {
+                    // the caller's stack arguments start right above the saved fp and lr
+                    self.emit_line(&format!("\tldr x10, [fp, #{}]", 16 + stacked));
+                    let address = self.frame_operand(offset);
+                    self.emit_store(ty, 10, &address);
+                }
+            }
+            self.variables.insert(
+                param.param_name.clone(),
+                VariableDef {
+                    var_type: ty.clone(),
+                    address: offset,
+                },
+            );
+        }
+    }
+
+    // AAPCS64 passes variadic arguments like any other, so `va_arg` may have to take them from
+    // the registers. All of x0-x7 and q0-q7 are saved in the frame, where it can index them.
+    fn save_argument_registers(&mut self, params: &[FuncParam]) -> Varargs {
+        let types: Vec<&Type> = params.iter().map(|param| &param.param_type).collect();
+        let (_, named) = self.classify(&types, types.len());
+        let save_areas = match self.target {
+            Target::MacOs => None,
+            Target::Linux => {
+                let gp_area = self.reserve(8 * 8, 16);
+                self.emit_frame_address(9, gp_area);
+                for k in (0..8).step_by(2) {
+                    self.emit_line(&format!("\tstp x{}, x{}, [x9, #{}]", k, k + 1, k * 8));
+                }
+                let fp_area = self.reserve(8 * 16, 16);
+                self.emit_frame_address(9, fp_area);
+                for k in (0..8).step_by(2) {
+                    self.emit_line(&format!("\tstp q{}, q{}, [x9, #{}]", k, k + 1, k * 16));
+                }
+                Some((gp_area, fp_area))
+            }
+        };
+        Varargs { named, save_areas }
+    }
+
+    // point the va_list at [x0] at the first variadic argument
+    fn emit_va_start(&mut self) {
+        let varargs = self
+            .varargs
+            .as_ref()
+            .expect("semantic analysis only allows va_start in variadic functions");
+        let (named, save_areas) = (varargs.named, varargs.save_areas);
+        // the caller's stack arguments start right above the saved fp and lr
+        self.emit_line(&format!("\tadd x1, fp, #{}", 16 + named.stack));
+        self.emit_line("\tstr x1, [x0]");
+        let Some((gp_area, fp_area)) = save_areas else {
+            return;
+        };
+        // `__gr_top` and `__vr_top` point just past the save areas, `__gr_offs` and `__vr_offs`
+        // are the negative offsets from there to the first register no named parameter took
+        self.emit_frame_address(1, gp_area - 8 * 8);
+        self.emit_line("\tstr x1, [x0, #8]");
+        self.emit_frame_address(1, fp_area - 8 * 16);
+        self.emit_line("\tstr x1, [x0, #16]");
+        let gp_offs = -8 * (8 - named.gp as i64);
+        self.emit_line(&format!("\tmov w1, #{gp_offs}"));
+        self.emit_line("\tstr w1, [x0, #24]");
+        let fp_offs = -16 * (8 - named.fp as i64);
+        self.emit_line(&format!("\tmov w1, #{fp_offs}"));
+        self.emit_line("\tstr w1, [x0, #28]");
+    }
+
+    // load the next variadic argument of type `ty` from the va_list at [x1] into x0, which for
+    // a struct is its address
+    fn emit_va_arg(&mut self, ty: &Type) {
+        let pass = self.pass_as(ty);
+        // what the argument takes on the stack, a large struct is passed by the address of a copy
+        let stack_size = match pass {
+            PassAs::Indirect => 8,
+            _ if ty.is_struct() => self.layouts.size_of(ty).next_multiple_of(8),
+            _ => 8,
+        };
+        if self.target == Target::Linux {
+            // take it from the saved registers until the ones of its kind are used up
+            let (offs, top, slot, count) = match &pass {
+                PassAs::Fp(_, count) => (28, 16, 16, *count),
+                PassAs::Gp(count) => (24, 8, 8, *count),
+                PassAs::Indirect => (24, 8, 8, 1),
+            };
+            let stack_lbl = self.get_unique_label("va_stack");
+            let done_lbl = self.get_unique_label("va_done");
+            self.emit_line(&format!("\tldrsw x2, [x1, #{offs}]"));
+            self.emit_line("\tcmp x2, #0");
+            self.emit_line(&format!("\tb.ge {}", stack_lbl));
+            self.emit_line(&format!("\tadd w3, w2, #{}", slot * count as usize));
+            self.emit_line(&format!("\tstr w3, [x1, #{offs}]"));
+            // an aggregate that does not fit in the registers left goes on the stack as a whole
+            if count > 1 {
+                self.emit_line("\tcmp w3, #0");
+                self.emit_line(&format!("\tb.gt {}", stack_lbl));
+            }
+            self.emit_line(&format!("\tldr x0, [x1, #{top}]"));
+            self.emit_line("\tadd x0, x0, x2");
+            if let (PassAs::Fp(element, count), true) = (&pass, ty.is_struct()) {
+                // the members are saved 16 bytes apart, so they are gathered into a copy
+                let copy = self.allocate(ty);
+                self.emit_frame_address(4, copy);
+                for k in 0..*count as usize {
+                    self.emit_load(element, 3, &format!("[x0, #{}]", k * 16));
+                    self.emit_store(element, 3, &format!("[x4, #{}]", k * element.size()));
+                }
+                self.emit_line("\tmov x0, x4");
+            }
+            self.emit_jump(&done_lbl);
+            self.emit_label(&stack_lbl);
+            self.emit_stack_va_arg(stack_size);
+            self.emit_label(&done_lbl);
+        } else {
+            self.emit_stack_va_arg(stack_size);
+        }
+        match pass {
+            PassAs::Indirect => self.emit_line("\tldr x0, [x0]"),
+            _ if ty.is_struct() => {}
+            _ => self.emit_load(ty, 0, "[x0]"),
+        }
+    }
+
+    // advance `__stack`, the first member of the va_list at [x1], past an argument taking
+    // `size` bytes, leaving its address in x0
+    fn emit_stack_va_arg(&mut self, size: usize) {
+        self.emit_line("\tldr x0, [x1]");
+        self.emit_line(&format!("\tadd x3, x0, #{size}"));
+        self.emit_line("\tstr x3, [x1]");
+    }
+
+    /// Generates assembly for `program`, using the struct layouts semantic analysis determined
+    pub fn generate(&mut self, program: &Program, layouts: Layouts) {
+        self.layouts = layouts;
         self.emit_line(".align 2");
 
+        // calls need to know which arguments are variadic, as Apple platforms pass them differently
+        self.variadic_functions = program
+            .functions
+            .iter()
+            .filter(|f| f.variadic)
+            .map(|f| (f.name.clone(), f.params.len()))
+            .collect();
+
         for f in program.functions.iter() {
+            // declarations without a body are defined elsewhere
+            let Some(block_items) = &f.block_items else {
+                continue;
+            };
+
             // function definition
-            self.emit_line("_start:");
+            let symbol = self.target.symbol(&f.name);
+            self.emit_line(&format!(".global {}", symbol));
+            self.emit_label(&symbol);
 
             // function prologue: save old frame pointer and set up new one
             self.emit_line("\tstp fp, lr, [sp, #-16]!"); // save fp and lr, pre-decrement sp
             self.emit_line("\tmov fp, sp"); // set up frame pointer
+            let prologue_end = self.buffer.len();
 
             // content
-            let mut had_return = false;
-            for s in f.block_items.iter() {
-                if let Statement::Return(..) = s {
-                    had_return = true;
-                }
+            self.variables.clear();
+            self.labels.clear();
+            self.frame_offset = 0;
+            self.return_type = f.return_type.clone();
+            // x8 is free to be reused, so keep the address a large struct result goes to
+            self.result_address = None;
+            if f.return_type.is_struct() && matches!(self.pass_as(&f.return_type), PassAs::Indirect)
+            {
+                let slot = self.allocate(&Type::pointer_to(Type::Void));
+                let address = self.frame_operand(slot);
+                self.emit_line(&format!("\tstr x8, {address}"));
+                self.result_address = Some(slot);
+            }
+            self.varargs = f.variadic.then(|| self.save_argument_registers(&f.params));
+            self.generate_params(&f.params);
+            for s in block_items.iter() {
                 self.generate_statement(s);
             }
-            if !had_return {
+
+            // now that all variables are known, reserve the frame for them (keeping sp 16-byte aligned)
+            let frame_size = self.frame_offset.next_multiple_of(16);
+            if frame_size > 0 {
+                let mut reserve = String::new();
+                for chunk in [frame_size & !0xfff, frame_size & 0xfff] {
+                    if chunk != 0 {
+                        reserve.push_str(&format!("\tsub sp, sp, #{chunk}\n"));
+                    }
+                }
+                self.buffer.insert_str(prologue_end, &reserve);
+            }
+            // falling off the end returns, unless the body ends in a `return` already; one
+            // earlier on may be jumped over, e.g. to a label after it
+            let ends_in_return = matches!(
+                block_items.last().map(|s| &s.kind),
+                Some(StatementKind::Return(_))
+            );
+            if !ends_in_return {
                 self.emit_line("\tmov x0, #0");
                 self.emit_line("\tmov sp, fp"); // restore stack pointer
                 self.emit_line("\tldp fp, lr, [sp], #16"); // restore fp and lr, post-increment sp
                 self.emit_line("\tret");
             }
         }
+
+        let has_main = program
+            .functions
+            .iter()
+            .any(|f| f.name == "main" && f.block_items.is_some());
+        if self.freestanding && has_main {
+            self.emit_start();
+        }
+
+        if !self.strings.is_empty() {
+            self.emit_line(self.target.rodata_section());
+            for (label, bytes) in std::mem::take(&mut self.strings) {
+                self.emit_label(&label);
+                self.emit_line(&format!("\t.asciz \"{}\"", literal::escape(&bytes, b'"')));
+            }
+        }
+        if self.freestanding && has_main {
+            self.buffer.push_str(&runtime::generate(self.target));
+        }
+    }
+
+    // the entry point of a freestanding program, which exits with the result of `main`
+    fn emit_start(&mut self) {
+        self.emit_line(".global _start");
+        self.emit_label("_start");
+        self.emit_line(&format!("\tbl {}", self.target.symbol("main")));
+        for line in self.target.syscall(Syscall::Exit) {
+            self.emit_line(&line);
+        }
     }
 
     pub fn output(self) -> String {
         self.buffer
     }
 }
+
+/// Where an argument is passed according to the calling convention
+enum ArgLocation {
+    /// the first of the general purpose registers it takes
+    Gp(u8),
+    /// the first of the FP registers it takes
+    Fp(u8),
+    /// offset into the stack arguments
+    Stack(usize),
+}
+
+/// Registers and bytes of stack the arguments of a call take
+#[derive(Clone, Copy)]
+struct ArgUsage {
+    gp: u8,
+    fp: u8,
+    stack: usize,
+}
+
+/// How AAPCS64 passes a value of some type
+enum PassAs {
+    /// integers, pointers and structs of up to 16 bytes, in this many general purpose registers
+    Gp(u8),
+    /// floating-point values and homogeneous floating-point aggregates, one FP register per element
+    Fp(Type, u8),
+    /// larger structs, by the address of a copy
+    Indirect,
+}
+
+/// Values of the `case` labels of a switch body in source order, `None` for `default`.
+/// Labels of nested switches belong to those.
+fn collect_cases(statement: &Statement, cases: &mut Vec<Option<i64>>) {
+    match &statement.kind {
+        StatementKind::Case(value, stmt) => {
+            let ExprKind::Const(value, _) = value.kind else {
+                unreachable!("case labels are folded into constants by semantic analysis")
+            };
+            cases.push(Some(value as i64));
+            collect_cases(stmt, cases);
+        }
+        StatementKind::Default(stmt) => {
+            cases.push(None);
+            collect_cases(stmt, cases);
+        }
+        StatementKind::Labeled(_, stmt) => collect_cases(stmt, cases),
+        StatementKind::If(_, then_stmt, else_stmt) => {
+            collect_cases(then_stmt, cases);
+            if let Some(else_stmt) = else_stmt {
+                collect_cases(else_stmt, cases);
+            }
+        }
+        StatementKind::Compound(statements) => {
+            for stmt in statements {
+                collect_cases(stmt, cases);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Whether the cases fill enough of the range between the smallest and largest to be worth
+/// a jump table, i.e. at least half of its entries
+fn is_dense(targets: &[(i64, String)]) -> bool {
+    let Some((min, max)) = targets
+        .iter()
+        .map(|(value, _)| *value)
+        .minmax()
+        .into_option()
+    else {
+        return false;
+    };
+    let entries = max as i128 - min as i128 + 1;
+    targets.len() >= JUMP_TABLE_MIN_CASES && entries <= 2 * targets.len() as i128
+}
+
+/// Arrays and structs are represented by their address on the value stack
+fn by_address(ty: &Type) -> bool {
+    ty.is_array() || ty.is_struct()
+}
+
+/// Name of FP register `n` in the width of the floating-point type `ty`
+fn fp_reg(ty: &Type, n: u8) -> String {
+    match ty {
+        Type::Float => format!("s{n}"),
+        _ => format!("d{n}"),
+    }
+}
+
+/// General purpose register `n` wide enough for the bits of the floating-point type `ty`
+fn bits_reg(ty: &Type, n: u8) -> String {
+    match ty {
+        Type::Float => format!("w{n}"),
+        _ => format!("x{n}"),
+    }
+}
@@ -0,0 +1,24 @@
+#ifndef __STDARG_H
+#define __STDARG_H
+
+/* `va_list` is whatever the platform's C library expects to be handed, e.g. by `vprintf`:
+   a pointer to the arguments on the stack on Apple platforms, and the AAPCS64 structure
+   tracking the saved argument registers elsewhere */
+#ifdef __APPLE__
+typedef char *va_list;
+#else
+typedef struct __va_list {
+    void *__stack;
+    void *__gr_top;
+    void *__vr_top;
+    int __gr_offs;
+    int __vr_offs;
+} va_list;
+#endif
+
+#define va_start(ap, ...) __builtin_va_start(ap, ##__VA_ARGS__)
+#define va_arg(ap, type) __builtin_va_arg(ap, type)
+#define va_end(ap) __builtin_va_end(ap)
+#define va_copy(dest, src) __builtin_va_copy(dest, src)
+
+#endif
@@ -0,0 +1,9 @@
+#ifndef __STDDEF_H
+#define __STDDEF_H
+
+typedef unsigned long size_t;
+typedef long ptrdiff_t;
+
+#define NULL ((void *)0)
+
+#endif
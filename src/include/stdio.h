@@ -0,0 +1,17 @@
+#ifndef __STDIO_H
+#define __STDIO_H
+
+/* The parts of the C library's <stdio.h> that need no `FILE` */
+#include <stdarg.h>
+#include <stddef.h>
+
+#define EOF (-1)
+
+int printf(const char *format, ...);
+int vprintf(const char *format, va_list ap);
+int sprintf(char *buffer, const char *format, ...);
+int vsprintf(char *buffer, const char *format, va_list ap);
+int putchar(int c);
+int puts(const char *s);
+
+#endif
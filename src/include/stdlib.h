@@ -0,0 +1,10 @@
+#ifndef __STDLIB_H
+#define __STDLIB_H
+
+#include <stddef.h>
+
+void *malloc(size_t size);
+void free(void *p);
+void exit(int status);
+
+#endif
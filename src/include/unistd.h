@@ -0,0 +1,8 @@
+#ifndef __UNISTD_H
+#define __UNISTD_H
+
+#include <stddef.h>
+
+long write(int fd, const void *buffer, size_t count);
+
+#endif
@@ -0,0 +1,213 @@
+//! The AST as JSON, for tools outside the compiler. Needs the `serde` feature.
+//!
+//! A document is an object holding the version of its format, the source the spans point
+//! into and the program, as `--emit=ast-json` writes it:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "source": { "name": "main.c", "text": "# 1 \"main.c\"\nint main(void) { return -1; }\n" },
+//!   "program": {
+//!     "functions": [{
+//!       "return_type": "Int",
+//!       "name": "main",
+//!       "block_items": [{
+//!         "kind": { "Return": {
+//!           "kind": { "Unary": ["Negative", {
+//!             "kind": { "Const": [1, "Int"] }, "span": { "start": 38, "end": 39 }, "ty": null
+//!           }] },
+//!           "span": { "start": 37, "end": 39 },
+//!           "ty": null
+//!         } },
+//!         "span": { "start": 30, "end": 40 }
+//!       }],
+//!       "params": [],
+//!       "variadic": false,
+//!       "span": { "start": 13, "end": 42 }
+//!     }],
+//!     "declarations": []
+//!   }
+//! }
+//! ```
+//!
+//! The program follows the types of [`crate::ast`]:
+//!
+//! - A struct is an object with its fields, e.g. `{ "name": "x", "member_type": "Int", ... }`.
+//! - A variant without fields is its name, e.g. `"Int"` or `"Break"`. Any other variant is an
+//!   object with the name as its one key, mapping to its field or an array of its fields,
+//!   e.g. `{ "Pointer": "Char" }` or `{ "Binary": ["Add", left, right] }`.
+//! - `Option`s are `null` when absent, string literals are arrays of bytes and character
+//!   constants a byte.
+//! - Every span is the byte range `{ "start": .., "end": .. }` of `source.text`, the
+//!   preprocessed source with its line markers. Spans are not checked against it: a
+//!   diagnostic past its end points at the end, and without a `source` it has no snippet.
+//! - The expression types `ty` and struct `id`s are `null` before semantic analysis, and may
+//!   be left out.
+//! - There are no `Convert` expressions nor `Error` statements, which only semantic analysis
+//!   and syntax errors make.
+//!
+//! The version changes whenever a document of the old format would no longer be read the
+//! same, e.g. when a variant is renamed or gets another field.
+
+use serde::{Deserialize, Serialize};
+
+use crate::ast::{Expr, ExprKind, Initializer, Program, Span, Statement, StatementKind, Type};
+use crate::error::CompilerError;
+use crate::session::SourceMap;
+
+/// Version of the format written, the only one read
+pub const VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct Document<'a> {
+    version: u32,
+    source: &'a SourceMap,
+    program: &'a Program,
+}
+
+#[derive(Deserialize)]
+struct OwnedDocument {
+    version: u32,
+    /// Without it, diagnostics show no source line
+    #[serde(default)]
+    source: SourceMap,
+    program: Program,
+}
+
+/// `program`, with the source its spans point into, as a pretty printed JSON document
+pub fn to_string(program: &Program, source: &SourceMap) -> String {
+    let document = Document {
+        version: VERSION,
+        source,
+        program,
+    };
+    serde_json::to_string_pretty(&document).expect("the AST is valid JSON")
+}
+
+/// Reads a JSON document as [`to_string`] writes it
+pub fn from_str(json: &str) -> Result<(Program, SourceMap), CompilerError> {
+    let document: OwnedDocument =
+        serde_json::from_str(json).map_err(|e| CompilerError::InvalidAstJson(e.to_string()))?;
+    if document.version != VERSION {
+        return Err(CompilerError::InvalidAstJson(format!(
+            "version {} is not the supported version {VERSION}",
+            document.version
+        )));
+    }
+    check_program(&document.program)?;
+    Ok((document.program, document.source))
+}
+
+// the compiler's own nodes, which it does not expect in a program it is to check
+fn check_program(program: &Program) -> Result<(), CompilerError> {
+    for function in &program.functions {
+        check_type(&function.return_type)?;
+        for param in &function.params {
+            check_type(&param.param_type)?;
+        }
+        for statement in function.block_items.iter().flatten() {
+            check_statement(statement)?;
+        }
+    }
+    program.declarations.iter().try_for_each(check_statement)
+}
+
+fn check_statement(statement: &Statement) -> Result<(), CompilerError> {
+    match &statement.kind {
+        StatementKind::Return(expr) | StatementKind::Expr(expr) => check_expr(expr),
+        StatementKind::Declare(ty, _, init) => {
+            check_type(ty)?;
+            init.iter().try_for_each(check_initializer)
+        }
+        StatementKind::DeclareType(ty) | StatementKind::Typedef(ty, _) => check_type(ty),
+        StatementKind::If(condition, then_stmt, else_stmt) => {
+            check_expr(condition)?;
+            check_statement(then_stmt)?;
+            else_stmt.iter().try_for_each(|stmt| check_statement(stmt))
+        }
+        StatementKind::Switch(expr, stmt) | StatementKind::Case(expr, stmt) => {
+            check_expr(expr)?;
+            check_statement(stmt)
+        }
+        StatementKind::Default(stmt) | StatementKind::Labeled(_, stmt) => check_statement(stmt),
+        StatementKind::Compound(statements) => statements.iter().try_for_each(check_statement),
+        StatementKind::Break | StatementKind::Goto(_) | StatementKind::Empty => Ok(()),
+        StatementKind::Error => Err(unexpected("an `Error` statement", statement.span)),
+    }
+}
+
+fn check_initializer(init: &Initializer) -> Result<(), CompilerError> {
+    match init {
+        Initializer::Single(expr) => check_expr(expr),
+        Initializer::List(items, _) => items.iter().try_for_each(check_initializer),
+    }
+}
+
+fn check_expr(expr: &Expr) -> Result<(), CompilerError> {
+    match &expr.kind {
+        ExprKind::Const(..)
+        | ExprKind::FloatConst(..)
+        | ExprKind::Char(_)
+        | ExprKind::String(_)
+        | ExprKind::Var(_) => Ok(()),
+        ExprKind::Unary(_, operand)
+        | ExprKind::Group(operand)
+        | ExprKind::AddressOf(operand)
+        | ExprKind::Deref(operand)
+        | ExprKind::Member(operand, _)
+        | ExprKind::PtrMember(operand, _)
+        | ExprKind::SizeOf(operand)
+        | ExprKind::VaEnd(operand) => check_expr(operand),
+        ExprKind::Binary(_, left, right)
+        | ExprKind::Assignment(left, right)
+        | ExprKind::CompoundAssignment(_, left, right)
+        | ExprKind::Subscript(left, right)
+        | ExprKind::Comma(left, right)
+        | ExprKind::VaCopy(left, right) => {
+            check_expr(left)?;
+            check_expr(right)
+        }
+        ExprKind::Conditional(cond, then_expr, else_expr) => {
+            check_expr(cond)?;
+            check_expr(then_expr)?;
+            check_expr(else_expr)
+        }
+        ExprKind::Call(_, args) => args.iter().try_for_each(check_expr),
+        ExprKind::Cast(ty, operand) | ExprKind::VaArg(operand, ty) => {
+            check_type(ty)?;
+            check_expr(operand)
+        }
+        ExprKind::SizeOfType(ty) | ExprKind::AlignOf(ty) => check_type(ty),
+        ExprKind::VaStart(ap, last) => {
+            check_expr(ap)?;
+            last.iter().try_for_each(|last| check_expr(last))
+        }
+        ExprKind::Convert(_) => Err(unexpected("a `Convert` expression", expr.span)),
+    }
+}
+
+// types hold expressions too, the values of enumerators
+fn check_type(ty: &Type) -> Result<(), CompilerError> {
+    match ty {
+        Type::Pointer(inner) | Type::Array(inner, _) => check_type(inner),
+        Type::Struct(struct_type) => struct_type
+            .members
+            .iter()
+            .flatten()
+            .try_for_each(|member| check_type(&member.member_type)),
+        Type::Enum(enum_type) => enum_type
+            .enumerators
+            .iter()
+            .flatten()
+            .filter_map(|enumerator| enumerator.value.as_ref())
+            .try_for_each(check_expr),
+        _ => Ok(()),
+    }
+}
+
+fn unexpected(node: &str, span: Span) -> CompilerError {
+    CompilerError::InvalidAstJson(format!(
+        "{node} at {}..{}, which only the compiler makes",
+        span.start, span.end
+    ))
+}
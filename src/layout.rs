@@ -0,0 +1,156 @@
+//! Sizes, alignments and member offsets of types, including `struct` and `union` types
+
+use crate::ast::{StructKind, Type};
+
+/// A member placed at its offset from the start of the enclosing `struct` or `union`
+#[derive(Debug, Clone)]
+pub struct MemberLayout {
+    pub name: String,
+    pub member_type: Type,
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct StructLayout {
+    pub kind: StructKind,
+    pub tag: Option<String>,
+    pub members: Vec<MemberLayout>,
+    pub size: usize,
+    pub align: usize,
+    /// Only declared so far, e.g. by `struct node;` or a `struct node *` member
+    pub complete: bool,
+}
+
+/// Layout of every `struct` and `union` in the program, indexed by `StructType::id`
+#[derive(Debug, Clone, Default)]
+pub struct Layouts {
+    structs: Vec<StructLayout>,
+}
+
+impl Layouts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an incomplete type and returns its id
+    pub fn declare(&mut self, kind: StructKind, tag: Option<String>) -> usize {
+        self.structs.push(StructLayout {
+            kind,
+            tag,
+            members: Vec::new(),
+            size: 0,
+            align: 1,
+            complete: false,
+        });
+        self.structs.len() - 1
+    }
+
+    /// Completes the type `id` by placing its members. Struct members follow each other,
+    /// each at the next offset matching its alignment, union members all start at 0.
+    /// The size is padded to a multiple of the largest alignment, so arrays stay aligned.
+    pub fn define(&mut self, id: usize, members: Vec<(String, Type)>) {
+        let kind = self.structs[id].kind;
+        let mut placed = Vec::with_capacity(members.len());
+        let (mut size, mut align) = (0usize, 1);
+        for (name, member_type) in members {
+            let (member_size, member_align) =
+                (self.size_of(&member_type), self.align_of(&member_type));
+            let offset = match kind {
+                StructKind::Struct => size.next_multiple_of(member_align),
+                StructKind::Union => 0,
+            };
+            size = size.max(offset + member_size);
+            align = align.max(member_align);
+            placed.push(MemberLayout {
+                name,
+                member_type,
+                offset,
+            });
+        }
+
+        let layout = &mut self.structs[id];
+        layout.members = placed;
+        layout.size = size.next_multiple_of(align);
+        layout.align = align;
+        layout.complete = true;
+    }
+
+    pub fn get(&self, id: usize) -> &StructLayout {
+        &self.structs[id]
+    }
+
+    /// The layout behind a `struct` or `union` type
+    pub fn of(&self, ty: &Type) -> Option<&StructLayout> {
+        match ty {
+            Type::Struct(struct_type) => {
+                let id = struct_type
+                    .id
+                    .expect("struct types are resolved by semantic analysis");
+                Some(&self.structs[id])
+            }
+            _ => None,
+        }
+    }
+
+    pub fn member(&self, ty: &Type, name: &str) -> Option<&MemberLayout> {
+        self.of(ty)?
+            .members
+            .iter()
+            .find(|member| member.name == name)
+    }
+
+    /// Whether the size of `ty` is known, `void` and only declared structs have none
+    pub fn is_complete(&self, ty: &Type) -> bool {
+        match ty {
+            Type::Void => false,
+            Type::Array(element, _) => self.is_complete(element),
+            Type::Struct(_) => self.of(ty).is_some_and(|layout| layout.complete),
+            _ => true,
+        }
+    }
+
+    pub fn size_of(&self, ty: &Type) -> usize {
+        match ty {
+            Type::Array(element, len) => self.size_of(element) * len,
+            Type::Struct(_) => self.of(ty).map_or(0, |layout| layout.size),
+            _ => ty.size(),
+        }
+    }
+
+    pub fn align_of(&self, ty: &Type) -> usize {
+        match ty {
+            Type::Array(element, _) => self.align_of(element),
+            Type::Struct(_) => self.of(ty).map_or(1, |layout| layout.align),
+            _ => ty.align(),
+        }
+    }
+
+    /// A homogeneous floating-point aggregate: a struct made of one to four members of the
+    /// same floating-point type, possibly nested, which AAPCS64 passes in FP registers
+    pub fn hfa(&self, ty: &Type) -> Option<(Type, usize)> {
+        let mut elements = Vec::new();
+        self.flatten(ty, &mut elements).then_some(())?;
+        let first = elements.first()?.clone();
+        (first.is_floating() && elements.len() <= 4 && elements.iter().all(|e| *e == first))
+            .then_some((first, elements.len()))
+    }
+
+    // collects the scalar members of `ty` in order, false if they overlap as in a union
+    fn flatten(&self, ty: &Type, out: &mut Vec<Type>) -> bool {
+        match ty {
+            Type::Array(element, len) => (0..*len).all(|_| self.flatten(element, out)),
+            Type::Struct(_) => {
+                let layout = self.of(ty).expect("struct types are resolved");
+                layout.kind == StructKind::Struct
+                    && layout
+                        .members
+                        .iter()
+                        .all(|m| self.flatten(&m.member_type, out))
+            }
+            _ => {
+                out.push(ty.clone());
+                true
+            }
+        }
+    }
+}
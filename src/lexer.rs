@@ -0,0 +1,309 @@
+//! Splits the source into tokens for the parser.
+//!
+//! C cannot be parsed without knowing which identifiers name types: `T * x;` declares a
+//! pointer if `T` is a typedef name and multiplies otherwise. The parser records each
+//! declaration in `TypedefNames` as soon as it has seen it, and the lexer consults that
+//! to hand out `TypedefName` instead of `Identifier` tokens (the "lexer hack").
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::Span;
+use crate::diagnostics::SyntaxError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token<'input> {
+    Identifier(&'input str),
+    /// An identifier declared by `typedef` in a scope that is still open
+    TypedefName(&'input str),
+    Integer(&'input str),
+    Float(&'input str),
+    Char(&'input str),
+    String(&'input str),
+    Keyword(&'static str),
+    Punct(&'static str),
+}
+
+impl fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Identifier(text)
+            | Token::TypedefName(text)
+            | Token::Integer(text)
+            | Token::Float(text)
+            | Token::Char(text)
+            | Token::String(text) => write!(f, "{}", text),
+            Token::Keyword(text) | Token::Punct(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+const KEYWORDS: &[&str] = &[
+    "_Alignof", "auto", "break", "case", "char", "const", "continue", "default", "do", "double",
+    "else", "enum", "extern", "float", "for", "goto", "if", "inline", "int", "long", "register",
+    "restrict", "return", "short", "signed", "sizeof", "static", "struct", "switch", "typedef",
+    "union", "unsigned", "void", "volatile", "while",
+];
+
+// builtins taking a type or an lvalue as an argument, which a function call cannot
+const BUILTINS: &[&str] = &[
+    "__builtin_va_arg",
+    "__builtin_va_copy",
+    "__builtin_va_end",
+    "__builtin_va_start",
+];
+
+// longest first, so e.g. `->` is not lexed as `-` followed by `>`
+pub(crate) const PUNCTUATORS: &[&str] = &[
+    "...", "<<=", ">>=", "->", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "*=",
+    "/=", "%=", "+=", "-=", "&=", "^=", "|=", "##", "[", "]", "(", ")", "{", "}", ".", "&", "*",
+    "+", "-", "~", "!", "/", "%", "<", ">", "^", "|", "?", ":", ";", "=", ",", "#",
+];
+
+/// Typedef names of every open scope, shared between the lexer and the parser's actions.
+/// Each scope maps a name to whether it is a typedef name there, so a variable can hide
+/// a typedef name of an outer scope.
+#[derive(Debug)]
+pub struct TypedefNames {
+    scopes: RefCell<Vec<HashMap<String, bool>>>,
+}
+
+impl Default for TypedefNames {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TypedefNames {
+    pub fn new() -> Self {
+        Self {
+            scopes: RefCell::new(vec![HashMap::new()]),
+        }
+    }
+
+    pub fn declare_typedef(&self, name: &str) {
+        self.declare(name, true);
+    }
+
+    /// Records a variable, which hides a typedef name of the same name from outer scopes
+    pub fn declare_ordinary(&self, name: &str) {
+        self.declare(name, false);
+    }
+
+    pub fn is_typedef(&self, name: &str) -> bool {
+        self.scopes
+            .borrow()
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+            .unwrap_or(false)
+    }
+
+    fn declare(&self, name: &str, is_typedef: bool) {
+        let mut scopes = self.scopes.borrow_mut();
+        let scope = scopes.last_mut().expect("file scope is never closed");
+        scope.insert(name.to_string(), is_typedef);
+    }
+
+    fn open_scope(&self) {
+        self.scopes.borrow_mut().push(HashMap::new());
+    }
+
+    fn close_scope(&self) {
+        let mut scopes = self.scopes.borrow_mut();
+        if scopes.len() > 1 {
+            scopes.pop();
+        }
+    }
+}
+
+/// Iterator over the tokens of `input` and their byte ranges, as the parser expects them
+pub struct Lexer<'input, 'names> {
+    input: &'input str,
+    pos: usize,
+    names: &'names TypedefNames,
+    /// Whether a `{` would start the body of an enum, as after `enum` or `enum color`
+    enum_head: bool,
+    /// For each open brace, whether it opened a scope
+    braces: Vec<bool>,
+}
+
+impl<'input, 'names> Lexer<'input, 'names> {
+    pub fn new(input: &'input str, names: &'names TypedefNames) -> Self {
+        Self {
+            input,
+            pos: 0,
+            names,
+            enum_head: false,
+            braces: Vec::new(),
+        }
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<u8> {
+        self.input.as_bytes().get(self.pos + offset).copied()
+    }
+
+    fn eat_while(&mut self, wanted: impl Fn(u8) -> bool) {
+        while self.peek_at(0).is_some_and(&wanted) {
+            self.pos += 1;
+        }
+    }
+
+    fn error(&self, message: &'static str, start: usize) -> SyntaxError {
+        SyntaxError {
+            message,
+            span: Span::new(start, self.pos.max(start + 1)),
+        }
+    }
+
+    // whitespace and comments separate tokens but are otherwise ignored, and so are the
+    // line markers the preprocessor leaves at the start of a line
+    fn skip_trivia(&mut self) -> Result<(), SyntaxError> {
+        loop {
+            self.eat_while(|c| c.is_ascii_whitespace());
+            let line_start = self.pos == 0 || self.input.as_bytes()[self.pos - 1] == b'\n';
+            match (self.peek_at(0), self.peek_at(1)) {
+                (Some(b'/'), Some(b'/')) => self.eat_while(|c| c != b'\n'),
+                (Some(b'#'), _) if line_start => self.eat_while(|c| c != b'\n'),
+                (Some(b'/'), Some(b'*')) => {
+                    let start = self.pos;
+                    match self.input[self.pos + 2..].find("*/") {
+                        Some(end) => self.pos += 2 + end + 2,
+                        None => {
+                            self.pos = self.input.len();
+                            return Err(self.error("unterminated comment", start));
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    // integer and floating-point constants, e.g. `0x2aUL`, `1.5e3f` or `.5`
+    fn number(&mut self) -> Token<'input> {
+        let start = self.pos;
+        if self.peek_at(0) == Some(b'0') && matches!(self.peek_at(1), Some(b'x' | b'X')) {
+            self.pos += 2;
+            self.eat_while(|c| c.is_ascii_hexdigit());
+            self.eat_while(|c| matches!(c, b'u' | b'U' | b'l' | b'L'));
+            return Token::Integer(&self.input[start..self.pos]);
+        }
+
+        self.eat_while(|c| c.is_ascii_digit());
+        let mut floating = false;
+        if self.peek_at(0) == Some(b'.') {
+            floating = true;
+            self.pos += 1;
+            self.eat_while(|c| c.is_ascii_digit());
+        }
+        let exponent_digits = match self.peek_at(1) {
+            Some(b'+' | b'-') => 2,
+            _ => 1,
+        };
+        if matches!(self.peek_at(0), Some(b'e' | b'E'))
+            && self
+                .peek_at(exponent_digits)
+                .is_some_and(|c| c.is_ascii_digit())
+        {
+            floating = true;
+            self.pos += exponent_digits;
+            self.eat_while(|c| c.is_ascii_digit());
+        }
+
+        if floating {
+            if matches!(self.peek_at(0), Some(b'f' | b'F' | b'l' | b'L')) {
+                self.pos += 1;
+            }
+            Token::Float(&self.input[start..self.pos])
+        } else {
+            self.eat_while(|c| matches!(c, b'u' | b'U' | b'l' | b'L'));
+            Token::Integer(&self.input[start..self.pos])
+        }
+    }
+
+    // the body of a character or string literal up to the closing `quote`, escapes included
+    fn quoted(&mut self, quote: u8) -> Result<&'input str, SyntaxError> {
+        let start = self.pos;
+        self.pos += 1;
+        loop {
+            match self.peek_at(0) {
+                Some(c) if c == quote => {
+                    self.pos += 1;
+                    return Ok(&self.input[start..self.pos]);
+                }
+                Some(b'\\') if self.peek_at(1).is_some_and(|c| c != b'\n') => self.pos += 2,
+                Some(b'\n') | None => {
+                    return Err(self.error("missing terminating quote", start));
+                }
+                Some(_) => self.pos += 1,
+            }
+        }
+    }
+}
+
+impl<'input> Iterator for Lexer<'input, '_> {
+    type Item = Result<(usize, Token<'input>, usize), SyntaxError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(error) = self.skip_trivia() {
+            return Some(Err(error));
+        }
+        let start = self.pos;
+        let c = self.peek_at(0)?;
+        let token = match c {
+            b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
+                self.eat_while(|c| c.is_ascii_alphanumeric() || c == b'_');
+                let text = &self.input[start..self.pos];
+                match KEYWORDS
+                    .iter()
+                    .chain(BUILTINS)
+                    .find(|keyword| **keyword == text)
+                {
+                    Some(keyword) => Token::Keyword(keyword),
+                    None if self.names.is_typedef(text) => Token::TypedefName(text),
+                    None => Token::Identifier(text),
+                }
+            }
+            b'0'..=b'9' => self.number(),
+            b'.' if self.peek_at(1).is_some_and(|c| c.is_ascii_digit()) => self.number(),
+            b'\'' => match self.quoted(b'\'') {
+                Ok(text) => Token::Char(text),
+                Err(error) => return Some(Err(error)),
+            },
+            b'"' => match self.quoted(b'"') {
+                Ok(text) => Token::String(text),
+                Err(error) => return Some(Err(error)),
+            },
+            _ => {
+                let rest = &self.input[self.pos..];
+                let Some(punct) = PUNCTUATORS.iter().find(|p| rest.starts_with(**p)) else {
+                    // skip the whole character, which may take several bytes
+                    self.pos += rest.chars().next().map_or(1, char::len_utf8);
+                    return Some(Err(self.error("invalid token", start)));
+                };
+                self.pos += punct.len();
+                // typedef names declared inside a block go out of scope at its end, while
+                // the constants of an enum belong to the scope the enum is declared in
+                match *punct {
+                    "{" => {
+                        self.braces.push(!self.enum_head);
+                        if !self.enum_head {
+                            self.names.open_scope();
+                        }
+                    }
+                    "}" if self.braces.pop().unwrap_or(true) => self.names.close_scope(),
+                    _ => {}
+                }
+                Token::Punct(punct)
+            }
+        };
+        self.enum_head = match token {
+            Token::Keyword("enum") => true,
+            Token::Identifier(_) | Token::TypedefName(_) => self.enum_head,
+            _ => false,
+        };
+        Some(Ok((start, token, self.pos)))
+    }
+}
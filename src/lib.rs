@@ -1,8 +1,29 @@
 use lalrpop_util::lalrpop_mod;
 
 pub mod ast;
+pub mod cfg;
+pub mod diagnostics;
+pub mod driver;
 pub mod error;
+pub mod format;
 pub mod generate;
+#[cfg(feature = "serde")]
+pub mod json;
+pub mod layout;
+pub mod lexer;
+pub mod literal;
+pub mod peephole;
+pub mod preprocess;
 pub mod pretty_print;
+pub mod printer;
+pub mod runtime;
 pub mod semantics;
-lalrpop_mod!(pub grammar);
+pub mod session;
+pub mod target;
+
+pub use session::{compile, Options, Output, Session};
+
+lalrpop_mod!(
+    #[allow(clippy::ptr_arg)] // generated actions take the `errors` grammar parameter by `&mut Vec`
+    pub grammar
+);
@@ -0,0 +1,153 @@
+//! Decoding and encoding of integer, floating-point, character and string literals
+
+use crate::ast::Type;
+
+/// Decodes an integer literal such as `42`, `0x2aUL` or `052`, giving it the first type of
+/// `int`, `long`, `long long` its suffix allows that can hold the value. Octal and hex
+/// literals may also become the unsigned variants, just like in C.
+pub fn parse_integer(text: &str) -> Result<(u64, Type), &'static str> {
+    let digits_end = text
+        .rfind(|c: char| !matches!(c, 'u' | 'U' | 'l' | 'L'))
+        .map_or(0, |i| i + 1);
+    let (digits, suffix) = text.split_at(digits_end);
+    let (radix, digits) = match digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        Some(hex) => (16, hex),
+        None if digits.len() > 1 && digits.starts_with('0') => (8, &digits[1..]),
+        None => (10, digits),
+    };
+    let value = u64::from_str_radix(digits, radix)
+        .map_err(|_| "integer literal is too large or malformed")?;
+
+    let unsigned = suffix.contains(['u', 'U']);
+    let min_rank = match suffix.to_ascii_lowercase().replace('u', "").as_str() {
+        "" => 0,
+        "l" => 1,
+        "ll" => 2,
+        _ => return Err("invalid suffix on integer literal"),
+    };
+    if suffix.matches(['u', 'U']).count() > 1 || suffix.contains("lL") || suffix.contains("Ll") {
+        return Err("invalid suffix on integer literal");
+    }
+
+    let candidates = [
+        (Type::Int, Type::UnsignedInt),
+        (Type::Long, Type::UnsignedLong),
+        (Type::LongLong, Type::UnsignedLongLong),
+    ];
+    for (signed, unsigned_ty) in candidates.into_iter().skip(min_rank) {
+        let signed_max = (1u64 << (signed.size() * 8 - 1)) - 1;
+        let unsigned_max = u64::MAX >> (64 - unsigned_ty.size() * 8);
+        if !unsigned && value <= signed_max {
+            return Ok((value, signed));
+        }
+        if (unsigned || radix != 10) && value <= unsigned_max {
+            return Ok((value, unsigned_ty));
+        }
+    }
+    Err("integer literal is too large for any integer type")
+}
+
+/// Decodes a floating-point literal such as `1.5e3`, `.5` or `2.0f`
+pub fn parse_float(text: &str) -> Result<(f64, Type), &'static str> {
+    let (digits, ty) = match text.strip_suffix(['f', 'F']) {
+        Some(digits) => (digits, Type::Float),
+        // `long double` is the same as `double` here
+        None => (text.trim_end_matches(['l', 'L']), Type::Double),
+    };
+    let value = digits
+        .parse::<f64>()
+        .map_err(|_| "malformed floating-point literal")?;
+    Ok((value, ty))
+}
+
+/// Decodes a character literal including its quotes, e.g. `'\n'`
+pub fn parse_char(text: &str) -> Result<u8, &'static str> {
+    let bytes = unescape(&text[1..text.len() - 1])?;
+    match bytes.as_slice() {
+        [byte] => Ok(*byte),
+        _ => Err("character literal must contain exactly one character"),
+    }
+}
+
+/// Decodes a string literal including its quotes, e.g. `"hi\n"`, without the terminating NUL
+pub fn parse_string(text: &str) -> Result<Vec<u8>, &'static str> {
+    unescape(&text[1..text.len() - 1])
+}
+
+/// Replaces C escape sequences in the body of a literal with the bytes they stand for
+fn unescape(body: &str) -> Result<Vec<u8>, &'static str> {
+    let mut bytes = Vec::with_capacity(body.len());
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        let byte = match chars.next().ok_or("unterminated escape sequence")? {
+            'n' => b'\n',
+            't' => b'\t',
+            'r' => b'\r',
+            'a' => 0x07,
+            'b' => 0x08,
+            'f' => 0x0c,
+            'v' => 0x0b,
+            '\\' => b'\\',
+            '\'' => b'\'',
+            '"' => b'"',
+            '?' => b'?',
+            // up to three octal digits, e.g. `\0` or `\177`
+            digit @ '0'..='7' => {
+                let mut value = digit.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(d) => {
+                            value = value * 8 + d;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                u8::try_from(value).map_err(|_| "octal escape sequence out of range")?
+            }
+            // any number of hex digits, e.g. `\x7f`
+            'x' => {
+                let mut value: u32 = 0;
+                let mut digits = 0;
+                while let Some(d) = chars.peek().and_then(|c| c.to_digit(16)) {
+                    value = value.saturating_mul(16).saturating_add(d);
+                    digits += 1;
+                    chars.next();
+                }
+                if digits == 0 {
+                    return Err("\\x used with no following hex digits");
+                }
+                u8::try_from(value).map_err(|_| "hex escape sequence out of range")?
+            }
+            _ => return Err("unknown escape sequence"),
+        };
+        bytes.push(byte);
+    }
+    Ok(bytes)
+}
+
+/// Writes `bytes` as the body of a C (or assembler) literal delimited by `quote`.
+/// Unprintable bytes become three digit octal escapes, so a following digit is never absorbed.
+pub fn escape(bytes: &[u8], quote: u8) -> String {
+    let mut out = String::with_capacity(bytes.len());
+    for &byte in bytes {
+        match byte {
+            b'\n' => out.push_str("\\n"),
+            b'\t' => out.push_str("\\t"),
+            b'\r' => out.push_str("\\r"),
+            b'\\' => out.push_str("\\\\"),
+            _ if byte == quote => {
+                out.push('\\');
+                out.push(byte as char);
+            }
+            0x20..=0x7e => out.push(byte as char),
+            _ => out.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    out
+}
@@ -0,0 +1,43 @@
+//! Optimizations on the generated assembly, for `-O1` and up. They are all there is, so `-O2`
+//! optimizes just as `-O1` does.
+//!
+//! The code generator keeps every intermediate value on the stack, so most values are pushed
+//! only to be popped again right away. Those pairs become a register move, or nothing.
+
+/// `asm` with each push directly followed by a pop of a general purpose register replaced
+pub fn optimize(asm: &str) -> String {
+    let lines: Vec<&str> = asm.lines().collect();
+    let mut output = String::with_capacity(asm.len());
+    let mut i = 0;
+    while i < lines.len() {
+        if let Some((from, to)) = push_pop(&lines[i..]) {
+            if from != to {
+                output.push_str(&format!("\tmov {to}, {from}\n"));
+            }
+            i += 4;
+            continue;
+        }
+        output.push_str(lines[i]);
+        output.push('\n');
+        i += 1;
+    }
+    output
+}
+
+// the registers pushed and popped if `lines` starts with a push and a pop of x registers
+fn push_pop<'a>(lines: &[&'a str]) -> Option<(&'a str, &'a str)> {
+    let [sub, store, load, add, ..] = lines else {
+        return None;
+    };
+    if *sub != "\tsub sp, sp, #0x10" || *add != "\tadd sp, sp, #0x10" {
+        return None;
+    }
+    let from = store.strip_prefix("\tstr ")?.strip_suffix(", [sp]")?;
+    let to = load.strip_prefix("\tldr ")?.strip_suffix(", [sp]")?;
+    (is_x_register(from) && is_x_register(to)).then_some((from, to))
+}
+
+fn is_x_register(name: &str) -> bool {
+    let number = name.strip_prefix('x').map(str::parse::<u8>);
+    name == "xzr" || number.is_some_and(|n| n.is_ok())
+}
@@ -0,0 +1,1255 @@
+//! The C preprocessor, which runs before the lexer: it follows `#include`s, expands macros
+//! and drops the lines excluded by conditional directives.
+//!
+//! The output is plain C interspersed with GCC-style line markers such as `# 12 "list.h"`.
+//! The lexer skips them, and diagnostics use them to point into the original files.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+
+use crate::error::CompilerError;
+use crate::lexer::PUNCTUATORS;
+use crate::literal;
+use crate::target::Target;
+
+/// Nesting `#include`s deeper than this is taken to be a file including itself
+const MAX_INCLUDE_DEPTH: usize = 200;
+
+/// Output lines skipped by up to this many blank lines rather than a new line marker
+const MAX_BLANK_LINES: usize = 8;
+
+/// Headers that come with the compiler, used when `#include` finds no file of that name.
+/// The system's own headers are full of extensions the parser does not know, these declare
+/// just what a C library, or the runtime of `-nostdlib` programs, provides.
+const BUILTIN_HEADERS: &[(&str, &str)] = &[
+    ("stdarg.h", include_str!("include/stdarg.h")),
+    ("stddef.h", include_str!("include/stddef.h")),
+    ("stdio.h", include_str!("include/stdio.h")),
+    ("stdlib.h", include_str!("include/stdlib.h")),
+    ("string.h", include_str!("include/string.h")),
+    ("unistd.h", include_str!("include/unistd.h")),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Identifier,
+    Number,
+    Char,
+    String,
+    Punct,
+    /// A stray character, or a quote without its closing one, left for the lexer to report
+    Other,
+}
+
+/// A preprocessing token and where it was read from
+#[derive(Debug, Clone)]
+struct PpToken {
+    kind: Kind,
+    text: String,
+    /// Index into `Preprocessor::files`
+    file: usize,
+    line: usize,
+    /// 1-based column, 0 for a token produced by a macro expansion
+    col: usize,
+    space_before: bool,
+    line_start: bool,
+    /// Macros whose expansion produced this token, which must not expand it again
+    hideset: Vec<String>,
+}
+
+impl PpToken {
+    fn is(&self, text: &str) -> bool {
+        matches!(self.kind, Kind::Punct | Kind::Identifier) && self.text == text
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    /// `None` for an object-like macro
+    params: Option<Vec<String>>,
+    /// Whether arguments past `params` are collected into `__VA_ARGS__`
+    variadic: bool,
+    body: Vec<PpToken>,
+}
+
+/// State of an `#if` ... `#endif` block
+struct Conditional {
+    /// Whether the lines of the current group are kept
+    active: bool,
+    /// Whether any group of the block was kept so far, so later ones are dropped
+    taken: bool,
+    /// Whether the block itself is in a kept group
+    enclosing_active: bool,
+    seen_else: bool,
+    line: usize,
+}
+
+pub struct Preprocessor {
+    include_dirs: Vec<PathBuf>,
+    macros: HashMap<String, Macro>,
+    files: Vec<String>, // names of the files read so far, as shown in line markers
+    depth: usize,
+    output: String,
+    out_file: usize, // file and line the output is at
+    out_line: usize,
+    out_col: usize,
+}
+
+impl Default for Preprocessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        Self::with_target(Target::host())
+    }
+
+    /// A preprocessor predefining the macros that identify `target`, e.g. `__APPLE__`
+    pub fn with_target(target: Target) -> Self {
+        let mut preprocessor = Self {
+            include_dirs: Vec::new(),
+            macros: HashMap::new(),
+            files: Vec::new(),
+            depth: 0,
+            output: String::new(),
+            out_file: 0,
+            out_line: 1,
+            out_col: 0,
+        };
+        preprocessor.define("__STDC__", "1");
+        preprocessor.define("__aarch64__", "1");
+        let platform: &[&str] = match target {
+            Target::Linux => &["__linux__", "__ELF__"],
+            Target::MacOs => &["__APPLE__", "__MACH__"],
+        };
+        for name in platform {
+            preprocessor.define(name, "1");
+        }
+        preprocessor
+    }
+
+    /// Adds a directory to search for `#include`d files, after those added before
+    pub fn include_dir(&mut self, dir: impl Into<PathBuf>) {
+        self.include_dirs.push(dir.into());
+    }
+
+    /// Defines the object-like macro `name` to stand for `value`, as `-D name=value` does
+    pub fn define(&mut self, name: &str, value: &str) {
+        let file = self.add_file("<command line>");
+        let body = tokenize(value, file).unwrap_or_default();
+        self.macros.insert(
+            name.to_string(),
+            Macro {
+                params: None,
+                variadic: false,
+                body,
+            },
+        );
+    }
+
+    /// Preprocesses the file at `path`
+    pub fn preprocess_file(self, path: &str) -> Result<String, CompilerError> {
+        let source = std::fs::read_to_string(path)?;
+        self.preprocess(path, &source)
+    }
+
+    /// Preprocesses `source`, with `name` as the name of its file, e.g. in `__FILE__`
+    pub fn preprocess(mut self, name: &str, source: &str) -> Result<String, CompilerError> {
+        self.run(Path::new(name), name, source)?;
+        if self.out_col > 0 {
+            self.output.push('\n');
+        }
+        Ok(self.output)
+    }
+
+    fn add_file(&mut self, name: &str) -> usize {
+        self.files.push(name.to_string());
+        self.files.len() - 1
+    }
+
+    fn error(&self, at: &PpToken, message: impl Into<String>) -> CompilerError {
+        CompilerError::Preprocess {
+            file: self.files[at.file].clone(),
+            line: at.line,
+            message: message.into(),
+        }
+    }
+
+    /// Preprocesses one file, appending the result to the output
+    fn run(&mut self, path: &Path, name: &str, source: &str) -> Result<(), CompilerError> {
+        let file = self.add_file(name);
+        let tokens =
+            tokenize(source, file).map_err(|(line, message)| CompilerError::Preprocess {
+                file: name.to_string(),
+                line,
+                message: message.to_string(),
+            })?;
+        let flag = (self.depth > 0).then_some(1);
+        self.marker(file, 1, flag);
+
+        let mut conditionals: Vec<Conditional> = Vec::new();
+        let mut i = 0;
+        while i < tokens.len() {
+            // a directive takes the rest of its line
+            if tokens[i].is("#") && tokens[i].line_start {
+                let end = next_line(&tokens, i + 1);
+                self.directive(path, &tokens[i], &tokens[i + 1..end], &mut conditionals)?;
+                i = end;
+                continue;
+            }
+
+            // other lines are expanded as one run, as macro arguments may span several
+            let end = (i + 1..tokens.len())
+                .find(|&j| tokens[j].is("#") && tokens[j].line_start)
+                .unwrap_or(tokens.len());
+            if conditionals.last().is_none_or(|c| c.active) {
+                let expanded = self.expand(tokens[i..end].to_vec())?;
+                self.emit(&expanded);
+            }
+            i = end;
+        }
+
+        match conditionals.last() {
+            Some(unterminated) => Err(CompilerError::Preprocess {
+                file: name.to_string(),
+                line: unterminated.line,
+                message: "unterminated conditional directive".to_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn directive(
+        &mut self,
+        path: &Path,
+        hash: &PpToken,
+        line: &[PpToken],
+        conditionals: &mut Vec<Conditional>,
+    ) -> Result<(), CompilerError> {
+        // the null directive, a `#` on its own
+        let Some(name) = line.first() else {
+            return Ok(());
+        };
+        let args = &line[1..];
+        let active = conditionals.last().is_none_or(|c| c.active);
+
+        match name.text.as_str() {
+            "if" | "ifdef" | "ifndef" => {
+                let condition = active
+                    && match name.text.as_str() {
+                        "if" => self.evaluate(name, args)?,
+                        "ifdef" => self.macros.contains_key(&self.macro_name(name, args)?),
+                        _ => !self.macros.contains_key(&self.macro_name(name, args)?),
+                    };
+                conditionals.push(Conditional {
+                    active: condition,
+                    taken: condition,
+                    enclosing_active: active,
+                    seen_else: false,
+                    line: name.line,
+                });
+            }
+            "elif" | "else" | "endif" => {
+                let Some(conditional) = conditionals.last_mut() else {
+                    return Err(self.error(name, format!("#{} without #if", name.text)));
+                };
+                if conditional.seen_else && name.text != "endif" {
+                    return Err(self.error(name, format!("#{} after #else", name.text)));
+                }
+                match name.text.as_str() {
+                    "elif" => {
+                        let condition = conditional.enclosing_active
+                            && !conditional.taken
+                            && self.evaluate(name, args)?;
+                        let conditional = conditionals.last_mut().expect("checked above");
+                        conditional.active = condition;
+                        conditional.taken |= condition;
+                    }
+                    "else" => {
+                        conditional.active = conditional.enclosing_active && !conditional.taken;
+                        conditional.taken = true;
+                        conditional.seen_else = true;
+                    }
+                    _ => {
+                        conditionals.pop();
+                    }
+                }
+            }
+            // everything else only matters in kept groups
+            _ if !active => {}
+            "define" => self.define_macro(name, args)?,
+            "undef" => {
+                let macro_name = self.macro_name(name, args)?;
+                self.macros.remove(&macro_name);
+            }
+            "include" => self.include(path, name, args)?,
+            "error" => {
+                return Err(self.error(name, format!("#error {}", join_tokens(args))));
+            }
+            // `#line` is only produced by other preprocessors, and no pragma is supported
+            "line" | "pragma" => {}
+            _ => {
+                return Err(self.error(
+                    hash,
+                    format!("invalid preprocessing directive #{}", name.text),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    // the macro name a directive such as `#ifdef` or `#undef` is about
+    fn macro_name(&self, directive: &PpToken, args: &[PpToken]) -> Result<String, CompilerError> {
+        match args.first() {
+            Some(name) if name.kind == Kind::Identifier => Ok(name.text.clone()),
+            _ => Err(self.error(
+                directive,
+                format!("#{} expects a macro name", directive.text),
+            )),
+        }
+    }
+
+    fn define_macro(&mut self, directive: &PpToken, args: &[PpToken]) -> Result<(), CompilerError> {
+        let name = self.macro_name(directive, args)?;
+        if name == "defined" {
+            return Err(self.error(directive, "'defined' cannot be used as a macro name"));
+        }
+        let mut rest = &args[1..];
+
+        // a parenthesis right after the name starts a parameter list, `#define F (x)` is an
+        // object-like macro standing for `(x)`
+        let mut params = None;
+        let mut variadic = false;
+        if rest.first().is_some_and(|t| t.is("(") && !t.space_before) {
+            let mut names = Vec::new();
+            let mut i = 1;
+            loop {
+                match rest.get(i) {
+                    Some(t) if t.is(")") && names.is_empty() && !variadic => break,
+                    Some(t) if t.is("...") => variadic = true,
+                    Some(t) if t.kind == Kind::Identifier && !variadic => {
+                        names.push(t.text.clone())
+                    }
+                    _ => return Err(self.error(directive, "invalid macro parameter list")),
+                }
+                i += 1;
+                match rest.get(i) {
+                    Some(t) if t.is(")") => break,
+                    Some(t) if t.is(",") && !variadic => i += 1,
+                    _ => return Err(self.error(directive, "invalid macro parameter list")),
+                }
+            }
+            rest = &rest[i + 1..];
+            params = Some(names);
+        }
+
+        let body = rest.to_vec();
+        if body.first().is_some_and(|t| t.is("##")) || body.last().is_some_and(|t| t.is("##")) {
+            return Err(self.error(
+                directive,
+                "'##' cannot appear at either end of a macro expansion",
+            ));
+        }
+        self.macros.insert(
+            name,
+            Macro {
+                params,
+                variadic,
+                body,
+            },
+        );
+        Ok(())
+    }
+
+    fn include(
+        &mut self,
+        path: &Path,
+        directive: &PpToken,
+        args: &[PpToken],
+    ) -> Result<(), CompilerError> {
+        // `#include NAME` takes the file name from the expansion of a macro
+        let args = match args.first() {
+            Some(t) if t.kind == Kind::Identifier => self.expand(args.to_vec())?,
+            _ => args.to_vec(),
+        };
+        let (name, quoted) = match args.first() {
+            Some(t) if t.kind == Kind::String => (t.text[1..t.text.len() - 1].to_string(), true),
+            Some(t) if t.is("<") => {
+                let end = args.iter().position(|t| t.is(">"));
+                let Some(end) = end else {
+                    return Err(self.error(directive, "missing '>' in #include"));
+                };
+                (join_tokens(&args[1..end]).replace(' ', ""), false)
+            }
+            _ => return Err(self.error(directive, "#include expects \"FILENAME\" or <FILENAME>")),
+        };
+
+        // `"file.h"` is looked for next to the including file first, `<file.h>` only in the
+        // include directories, and both fall back to the headers built into the compiler
+        let here = path.parent().map(Path::to_path_buf);
+        let found = quoted
+            .then_some(here)
+            .flatten()
+            .into_iter()
+            .chain(self.include_dirs.iter().cloned())
+            .map(|dir| dir.join(&name))
+            .find(|candidate| candidate.is_file());
+        let builtin = BUILTIN_HEADERS.iter().find(|(header, _)| *header == name);
+        let (found, source) = match (found, builtin) {
+            (Some(found), _) => {
+                let source = std::fs::read_to_string(&found)?;
+                (found, source)
+            }
+            (None, Some((_, source))) => (
+                PathBuf::from(format!("<built-in>/{name}")),
+                source.to_string(),
+            ),
+            _ => return Err(self.error(directive, format!("'{}' file not found", name))),
+        };
+        if self.depth >= MAX_INCLUDE_DEPTH {
+            return Err(self.error(directive, "#include nested too deeply"));
+        }
+
+        let display = found.to_string_lossy().into_owned();
+        self.depth += 1;
+        self.run(&found, &display, &source)?;
+        self.depth -= 1;
+        // continue with the line after the directive
+        self.marker(directive.file, directive.line + 1, Some(2));
+        Ok(())
+    }
+
+    /// Value of the condition of an `#if` or `#elif`
+    fn evaluate(&mut self, directive: &PpToken, args: &[PpToken]) -> Result<bool, CompilerError> {
+        // `defined NAME` and `defined(NAME)` are replaced before any macro is expanded
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < args.len() {
+            if !args[i].is("defined") {
+                tokens.push(args[i].clone());
+                i += 1;
+                continue;
+            }
+            let parenthesized = args.get(i + 1).is_some_and(|t| t.is("("));
+            let name = args.get(i + 1 + parenthesized as usize);
+            let Some(name) = name.filter(|t| t.kind == Kind::Identifier) else {
+                return Err(self.error(directive, "'defined' expects a macro name"));
+            };
+            if parenthesized && !args.get(i + 3).is_some_and(|t| t.is(")")) {
+                return Err(self.error(directive, "missing ')' after 'defined'"));
+            }
+            let mut value = name.clone();
+            value.kind = Kind::Number;
+            value.text = (self.macros.contains_key(&name.text) as u8).to_string();
+            tokens.push(value);
+            i += if parenthesized { 4 } else { 2 };
+        }
+
+        let tokens = self.expand(tokens)?;
+        let mut condition = Condition {
+            tokens,
+            pos: 0,
+            evaluated: true,
+        };
+        let value = condition
+            .conditional()
+            .and_then(|value| match condition.tokens.get(condition.pos) {
+                None => Ok(value),
+                Some(t) => Err(format!("unexpected '{}'", t.text)),
+            })
+            .map_err(|message| {
+                self.error(
+                    directive,
+                    format!("invalid #{} expression: {}", directive.text, message),
+                )
+            })?;
+        Ok(value != 0)
+    }
+
+    /// Expands every macro in `tokens`, including those their expansions contain
+    fn expand(&mut self, tokens: Vec<PpToken>) -> Result<Vec<PpToken>, CompilerError> {
+        let mut input: VecDeque<PpToken> = tokens.into();
+        let mut out = Vec::new();
+        while let Some(token) = input.pop_front() {
+            if token.kind != Kind::Identifier || token.hideset.contains(&token.text) {
+                out.push(token);
+                continue;
+            }
+            match token.text.as_str() {
+                "__LINE__" => {
+                    out.push(PpToken {
+                        kind: Kind::Number,
+                        text: token.line.to_string(),
+                        ..token
+                    });
+                    continue;
+                }
+                "__FILE__" => {
+                    let name = literal::escape(self.files[token.file].as_bytes(), b'"');
+                    out.push(PpToken {
+                        kind: Kind::String,
+                        text: format!("\"{}\"", name),
+                        ..token
+                    });
+                    continue;
+                }
+                _ => {}
+            }
+            let Some(definition) = self.macros.get(&token.text).cloned() else {
+                out.push(token);
+                continue;
+            };
+
+            let (args, hideset) = match &definition.params {
+                None => (Vec::new(), token.hideset.clone()),
+                // a function-like macro name without arguments is left alone
+                Some(_) if !input.front().is_some_and(|t| t.is("(")) => {
+                    out.push(token);
+                    continue;
+                }
+                Some(params) => {
+                    let (args, rparen) = self.collect_args(&mut input, &token, &definition)?;
+                    let expected = params.len();
+                    let count_ok = match definition.variadic {
+                        true => args.len() >= expected,
+                        false => args.len() == expected,
+                    };
+                    if !count_ok {
+                        return Err(self.error(
+                            &token,
+                            format!(
+                                "macro '{}' passed {} arguments, but takes {}",
+                                token.text,
+                                args.len(),
+                                expected
+                            ),
+                        ));
+                    }
+                    // only macros being expanded at both ends of the invocation stay hidden
+                    let hideset = token
+                        .hideset
+                        .iter()
+                        .filter(|name| rparen.hideset.contains(name))
+                        .cloned()
+                        .collect();
+                    (args, hideset)
+                }
+            };
+
+            let mut expansion = self.substitute(&definition, &args, &token)?;
+            for (i, t) in expansion.iter_mut().enumerate() {
+                // the expansion takes the place of the invocation
+                t.file = token.file;
+                t.line = token.line;
+                t.col = 0;
+                t.line_start = false;
+                if i == 0 {
+                    t.space_before = token.space_before;
+                }
+                t.hideset.extend(hideset.iter().cloned());
+                t.hideset.push(token.text.clone());
+            }
+            for t in expansion.into_iter().rev() {
+                input.push_front(t);
+            }
+        }
+        Ok(out)
+    }
+
+    // the arguments of a function-like macro invocation, after its name, up to the closing
+    // parenthesis
+    fn collect_args(
+        &self,
+        input: &mut VecDeque<PpToken>,
+        name: &PpToken,
+        definition: &Macro,
+    ) -> Result<(Vec<Vec<PpToken>>, PpToken), CompilerError> {
+        let named = definition.params.as_ref().map_or(0, Vec::len);
+        input.pop_front(); // `(`
+        let mut args = vec![Vec::new()];
+        let mut depth = 0;
+        loop {
+            let Some(token) = input.pop_front() else {
+                return Err(self.error(
+                    name,
+                    format!("unterminated argument list invoking macro '{}'", name.text),
+                ));
+            };
+            if token.is("(") {
+                depth += 1;
+            } else if token.is(")") {
+                if depth == 0 {
+                    // `F()` passes no arguments at all if `F` takes none
+                    if named == 0 && args.len() == 1 && args[0].is_empty() && !definition.variadic {
+                        args.clear();
+                    }
+                    return Ok((args, token));
+                }
+                depth -= 1;
+            } else if token.is(",") && depth == 0 && !(definition.variadic && args.len() > named) {
+                args.push(Vec::new());
+                continue;
+            }
+            args.last_mut()
+                .expect("starts with one argument")
+                .push(token);
+        }
+    }
+
+    // the body of `definition` with the arguments put in place of its parameters
+    fn substitute(
+        &mut self,
+        definition: &Macro,
+        args: &[Vec<PpToken>],
+        invocation: &PpToken,
+    ) -> Result<Vec<PpToken>, CompilerError> {
+        let empty = Vec::new();
+        let param = |token: &PpToken| -> Option<&Vec<PpToken>> {
+            let params = definition.params.as_ref()?;
+            if token.kind != Kind::Identifier {
+                return None;
+            }
+            if definition.variadic && token.text == "__VA_ARGS__" {
+                return Some(args.get(params.len()).unwrap_or(&empty));
+            }
+            let i = params.iter().position(|p| *p == token.text)?;
+            args.get(i)
+        };
+
+        let body = &definition.body;
+        let mut result: Vec<PpToken> = Vec::new();
+        // the left operand of a `##` was an empty argument
+        let mut placemarker = false;
+        let mut i = 0;
+        while i < body.len() {
+            let token = &body[i];
+
+            if token.is("##") {
+                let rhs = &body[i + 1];
+                i += 2;
+                let rhs_tokens = match param(rhs) {
+                    Some(arg) => arg.clone(),
+                    None => vec![rhs.clone()],
+                };
+                // GNU extension: `, ## __VA_ARGS__` drops the comma if there are no extra arguments
+                // and pastes nothing otherwise
+                let after_comma = result.last().is_some_and(|t| t.is(",")) && !placemarker;
+                if definition.variadic && rhs.text == "__VA_ARGS__" && after_comma {
+                    if rhs_tokens.is_empty() {
+                        result.pop();
+                    }
+                    result.extend(rhs_tokens);
+                    continue;
+                }
+                let Some((first, rest)) = rhs_tokens.split_first() else {
+                    continue;
+                };
+                if placemarker {
+                    placemarker = false;
+                    result.extend(rhs_tokens.iter().cloned());
+                    continue;
+                }
+                let lhs = result.pop().expect("`##` is never first in a body");
+                result.push(self.paste(&lhs, first, invocation)?);
+                result.extend(rest.iter().cloned());
+                continue;
+            }
+
+            if token.is("#") && definition.params.is_some() {
+                let arg = body.get(i + 1).and_then(param);
+                let Some(arg) = arg else {
+                    return Err(self.error(invocation, "'#' is not followed by a macro parameter"));
+                };
+                result.push(PpToken {
+                    kind: Kind::String,
+                    text: stringize(arg),
+                    ..token.clone()
+                });
+                i += 2;
+                continue;
+            }
+
+            if let Some(arg) = param(token) {
+                // an operand of `##` is pasted as written, anything else expanded first
+                let pasted = body.get(i + 1).is_some_and(|t| t.is("##"));
+                let mut tokens = match pasted {
+                    true => arg.clone(),
+                    false => self.expand(arg.clone())?,
+                };
+                if let Some(first) = tokens.first_mut() {
+                    first.space_before = token.space_before;
+                }
+                placemarker = pasted && tokens.is_empty();
+                result.extend(tokens);
+                i += 1;
+                continue;
+            }
+
+            placemarker = false;
+            result.push(token.clone());
+            i += 1;
+        }
+        Ok(result)
+    }
+
+    // joins two tokens by `##` into one
+    fn paste(
+        &self,
+        lhs: &PpToken,
+        rhs: &PpToken,
+        invocation: &PpToken,
+    ) -> Result<PpToken, CompilerError> {
+        let text = format!("{}{}", lhs.text, rhs.text);
+        match tokenize(&text, lhs.file).as_deref() {
+            Ok([pasted]) => Ok(PpToken {
+                space_before: lhs.space_before,
+                hideset: lhs.hideset.clone(),
+                ..pasted.clone()
+            }),
+            _ => Err(self.error(
+                invocation,
+                format!(
+                    "pasting '{}' and '{}' does not give a valid preprocessing token",
+                    lhs.text, rhs.text
+                ),
+            )),
+        }
+    }
+
+    // append tokens to the output, keeping them on the lines and mostly in the columns they
+    // were read from so diagnostics can point at them
+    fn emit(&mut self, tokens: &[PpToken]) {
+        for token in tokens {
+            if token.file != self.out_file
+                || token.line < self.out_line
+                || token.line > self.out_line + MAX_BLANK_LINES
+            {
+                self.marker(token.file, token.line, None);
+            }
+            while self.out_line < token.line {
+                self.output.push('\n');
+                self.out_line += 1;
+                self.out_col = 0;
+            }
+
+            let last = self.output.chars().last();
+            if token.col > self.out_col + 1 {
+                let padding = token.col - 1 - self.out_col;
+                self.output.extend(std::iter::repeat_n(' ', padding));
+                self.out_col += padding;
+            } else if self.out_col > 0
+                && token.col != self.out_col + 1
+                && (token.space_before || last.is_some_and(|c| would_merge(c, &token.text)))
+            {
+                self.output.push(' ');
+                self.out_col += 1;
+            }
+            self.output.push_str(&token.text);
+            self.out_col += token.text.chars().count();
+        }
+    }
+
+    // start a new line telling which file and line the following output comes from,
+    // flagged 1 when entering an included file and 2 when returning from one
+    fn marker(&mut self, file: usize, line: usize, flag: Option<u8>) {
+        if self.out_col > 0 || !self.output.is_empty() && !self.output.ends_with('\n') {
+            self.output.push('\n');
+        }
+        let name = literal::escape(self.files[file].as_bytes(), b'"');
+        self.output.push_str(&format!("# {} \"{}\"", line, name));
+        if let Some(flag) = flag {
+            self.output.push_str(&format!(" {}", flag));
+        }
+        self.output.push('\n');
+        self.out_file = file;
+        self.out_line = line;
+        self.out_col = 0;
+    }
+}
+
+/// Parses the `FILE` and line of a line marker such as `# 12 "list.h" 1`
+pub fn parse_line_marker(line: &str) -> Option<(String, usize)> {
+    let rest = line.strip_prefix("# ")?;
+    let (number, rest) = rest.split_once(' ')?;
+    let number = number.parse().ok()?;
+    let name = rest.strip_prefix('"')?;
+    let end = name.rfind('"')?;
+    let name = literal::parse_string(&format!("\"{}\"", &name[..end])).ok()?;
+    Some((String::from_utf8_lossy(&name).into_owned(), number))
+}
+
+// index of the first token at or after `from` that starts a new line
+fn next_line(tokens: &[PpToken], from: usize) -> usize {
+    (from..tokens.len())
+        .find(|&i| tokens[i].line_start)
+        .unwrap_or(tokens.len())
+}
+
+// whether `text` written right after the character `last` would lex differently, e.g. `-`
+// followed by `-`
+fn would_merge(last: char, text: &str) -> bool {
+    let word = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.';
+    let Some(first) = text.chars().next() else {
+        return false;
+    };
+    (word(last) && word(first)) || (last.is_ascii_punctuation() && first.is_ascii_punctuation())
+}
+
+// the text of `tokens` with spaces where there were any
+fn join_tokens(tokens: &[PpToken]) -> String {
+    let mut text = String::new();
+    for (i, token) in tokens.iter().enumerate() {
+        if i > 0 && token.space_before {
+            text.push(' ');
+        }
+        text.push_str(&token.text);
+    }
+    text
+}
+
+// a string literal spelling `tokens`, as the `#` operator makes it
+fn stringize(tokens: &[PpToken]) -> String {
+    let mut text = String::from("\"");
+    for (i, token) in tokens.iter().enumerate() {
+        if i > 0 && token.space_before {
+            text.push(' ');
+        }
+        match token.kind {
+            Kind::String | Kind::Char => {
+                for c in token.text.chars() {
+                    if c == '"' || c == '\\' {
+                        text.push('\\');
+                    }
+                    text.push(c);
+                }
+            }
+            _ => text.push_str(&token.text),
+        }
+    }
+    text.push('"');
+    text
+}
+
+/// Splits `source` into preprocessing tokens, dropping comments and joining lines ending in a
+/// backslash. Fails with the line and message if a comment is not terminated.
+fn tokenize(source: &str, file: usize) -> Result<Vec<PpToken>, (usize, &'static str)> {
+    // characters with the line and column they were read from, continuations removed
+    let mut chars = Vec::with_capacity(source.len());
+    let (mut line, mut col) = (1, 1);
+    let mut iter = source.chars().peekable();
+    while let Some(c) = iter.next() {
+        if c == '\\' && matches!(iter.peek(), Some('\n')) {
+            iter.next();
+            line += 1;
+            col = 1;
+            continue;
+        }
+        chars.push((c, line, col));
+        if c == '\n' {
+            line += 1;
+            col = 1;
+        } else {
+            col += 1;
+        }
+    }
+    let at = |i: usize| chars.get(i).map(|&(c, _, _)| c);
+
+    let mut tokens = Vec::new();
+    let (mut space_before, mut line_start) = (false, true);
+    let mut i = 0;
+    while let Some(c) = at(i) {
+        if c == '\n' {
+            (space_before, line_start) = (false, true);
+            i += 1;
+            continue;
+        }
+        if c.is_whitespace() {
+            space_before = true;
+            i += 1;
+            continue;
+        }
+        if c == '/' && at(i + 1) == Some('/') {
+            while at(i).is_some_and(|c| c != '\n') {
+                i += 1;
+            }
+            continue;
+        }
+        if c == '/' && at(i + 1) == Some('*') {
+            let start_line = chars[i].1;
+            i += 2;
+            while !(at(i) == Some('*') && at(i + 1) == Some('/')) {
+                if at(i).is_none() {
+                    return Err((start_line, "unterminated comment"));
+                }
+                i += 1;
+            }
+            i += 2;
+            space_before = true;
+            continue;
+        }
+
+        let start = i;
+        let kind = if c.is_ascii_alphabetic() || c == '_' {
+            while at(i).is_some_and(|c| c.is_ascii_alphanumeric() || c == '_') {
+                i += 1;
+            }
+            Kind::Identifier
+        } else if c.is_ascii_digit() || (c == '.' && at(i + 1).is_some_and(|c| c.is_ascii_digit()))
+        {
+            i += 1;
+            loop {
+                match (at(i), at(i + 1)) {
+                    (Some('e' | 'E' | 'p' | 'P'), Some('+' | '-')) => i += 2,
+                    (Some(c), _) if c.is_ascii_alphanumeric() || c == '_' || c == '.' => i += 1,
+                    _ => break,
+                }
+            }
+            Kind::Number
+        } else if c == '\'' || c == '"' {
+            i += 1;
+            let mut closed = false;
+            while let Some(d) = at(i) {
+                if d == '\n' {
+                    break;
+                }
+                i += 1;
+                if d == '\\' && at(i).is_some_and(|e| e != '\n') {
+                    i += 1;
+                } else if d == c {
+                    closed = true;
+                    break;
+                }
+            }
+            match (closed, c) {
+                (true, '\'') => Kind::Char,
+                (true, _) => Kind::String,
+                (false, _) => Kind::Other,
+            }
+        } else {
+            let rest: String = chars[i..].iter().take(3).map(|&(c, _, _)| c).collect();
+            match PUNCTUATORS.iter().find(|p| rest.starts_with(**p)) {
+                Some(punct) => {
+                    i += punct.len();
+                    Kind::Punct
+                }
+                None => {
+                    i += 1;
+                    Kind::Other
+                }
+            }
+        };
+
+        tokens.push(PpToken {
+            kind,
+            text: chars[start..i].iter().map(|&(c, _, _)| c).collect(),
+            file,
+            line: chars[start].1,
+            col: chars[start].2,
+            space_before,
+            line_start,
+            hideset: Vec::new(),
+        });
+        (space_before, line_start) = (false, false);
+    }
+    Ok(tokens)
+}
+
+/// Parser evaluating the expression of an `#if` once its macros are expanded, in `i64`
+struct Condition {
+    tokens: Vec<PpToken>,
+    pos: usize,
+    /// Whether the operand being parsed is evaluated, which e.g. the right one of `0 && x` is not
+    evaluated: bool,
+}
+
+impl Condition {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|t| t.text.as_str())
+    }
+
+    fn eat(&mut self, text: &str) -> bool {
+        let found = self.tokens.get(self.pos).is_some_and(|t| t.is(text));
+        self.pos += found as usize;
+        found
+    }
+
+    // parses an operand with `parse`, as one that is not evaluated if `skip`
+    fn operand(
+        &mut self,
+        skip: bool,
+        parse: impl FnOnce(&mut Self) -> Result<i64, String>,
+    ) -> Result<i64, String> {
+        let evaluated = self.evaluated;
+        self.evaluated &= !skip;
+        let value = parse(self);
+        self.evaluated = evaluated;
+        value
+    }
+
+    fn conditional(&mut self) -> Result<i64, String> {
+        let condition = self.binary(0)?;
+        if !self.eat("?") {
+            return Ok(condition);
+        }
+        let then_value = self.operand(condition == 0, Self::conditional)?;
+        if !self.eat(":") {
+            return Err("expected ':'".to_string());
+        }
+        let else_value = self.operand(condition != 0, Self::conditional)?;
+        Ok(if condition != 0 {
+            then_value
+        } else {
+            else_value
+        })
+    }
+
+    // binary operators by precedence, lowest first
+    fn binary(&mut self, level: usize) -> Result<i64, String> {
+        const LEVELS: &[&[&str]] = &[
+            &["||"],
+            &["&&"],
+            &["|"],
+            &["^"],
+            &["&"],
+            &["==", "!="],
+            &["<", "<=", ">", ">="],
+            &["<<", ">>"],
+            &["+", "-"],
+            &["*", "/", "%"],
+        ];
+        let Some(operators) = LEVELS.get(level) else {
+            return self.unary();
+        };
+        let mut value = self.binary(level + 1)?;
+        while let Some(op) = self.peek().filter(|op| operators.contains(op)) {
+            let op = op.to_string();
+            self.pos += 1;
+            // as in C, the right operand is not evaluated once the left one decides the result
+            let skip = match op.as_str() {
+                "||" => value != 0,
+                "&&" => value == 0,
+                _ => false,
+            };
+            let rhs = self.operand(skip, |condition| condition.binary(level + 1))?;
+            value = match op.as_str() {
+                "||" => (value != 0 || rhs != 0) as i64,
+                "&&" => (value != 0 && rhs != 0) as i64,
+                "|" => value | rhs,
+                "^" => value ^ rhs,
+                "&" => value & rhs,
+                "==" => (value == rhs) as i64,
+                "!=" => (value != rhs) as i64,
+                "<" => (value < rhs) as i64,
+                "<=" => (value <= rhs) as i64,
+                ">" => (value > rhs) as i64,
+                ">=" => (value >= rhs) as i64,
+                "<<" => value.wrapping_shl(rhs as u32),
+                ">>" => value.wrapping_shr(rhs as u32),
+                "+" => value.wrapping_add(rhs),
+                "-" => value.wrapping_sub(rhs),
+                "*" => value.wrapping_mul(rhs),
+                _ if rhs == 0 && !self.evaluated => 0,
+                _ if rhs == 0 => return Err("division by zero".to_string()),
+                "/" => value.wrapping_div(rhs),
+                _ => value.wrapping_rem(rhs),
+            };
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        if self.eat("+") {
+            return self.unary();
+        }
+        if self.eat("-") {
+            return Ok(self.unary()?.wrapping_neg());
+        }
+        if self.eat("~") {
+            return Ok(!self.unary()?);
+        }
+        if self.eat("!") {
+            return Ok((self.unary()? == 0) as i64);
+        }
+        if self.eat("(") {
+            let value = self.conditional()?;
+            if !self.eat(")") {
+                return Err("expected ')'".to_string());
+            }
+            return Ok(value);
+        }
+
+        let Some(token) = self.tokens.get(self.pos) else {
+            return Err("unexpected end of expression".to_string());
+        };
+        self.pos += 1;
+        match token.kind {
+            Kind::Number => literal::parse_integer(&token.text)
+                .map(|(value, _)| value as i64)
+                .map_err(str::to_string),
+            Kind::Char => literal::parse_char(&token.text)
+                .map(|c| c as i8 as i64)
+                .map_err(str::to_string),
+            // identifiers left after expansion are not macros
+            Kind::Identifier => Ok(0),
+            _ => Err(format!("unexpected '{}'", token.text)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preprocess(source: &str) -> Result<String, CompilerError> {
+        Preprocessor::with_target(Target::Linux).preprocess("main.c", source)
+    }
+
+    // the code of the output without line markers, on one line with single spaces
+    fn code(output: &str) -> String {
+        output
+            .lines()
+            .filter(|line| parse_line_marker(line).is_none())
+            .flat_map(str::split_whitespace)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn expand(source: &str) -> String {
+        code(&preprocess(source).unwrap())
+    }
+
+    #[test]
+    fn object_and_function_like_macros() {
+        let source = "#define N 3\n#define SQ(x) ((x) * (x))\nint a = SQ(N + 1);\n";
+        assert_eq!(expand(source), "int a = ( (3 + 1) * (3 + 1) ) ;");
+        // without parentheses a function-like macro's name is just an identifier
+        assert_eq!(expand("#define f(x) x\nint f; f(2);"), "int f; 2 ;");
+        let source = "#define log(fmt, ...) printf(fmt, __VA_ARGS__)\nlog(\"%d\", 1, 2);";
+        assert_eq!(expand(source), "printf( \"%d\" , 1, 2) ;");
+        assert_eq!(expand("#define N 1\n#undef N\nN"), "N");
+    }
+
+    #[test]
+    fn macros_do_not_expand_themselves() {
+        assert_eq!(expand("#define foo foo + 1\nfoo;"), "foo + 1;");
+        assert_eq!(expand("#define a b\n#define b a\na; b;"), "a; b;");
+        // `f` is only hidden within its own expansion, not from the tokens following it
+        let source = "#define f(x) g(x)\n#define g(x) x + f\nf(1)(2);";
+        assert_eq!(expand(source), "1 + f(2) ;");
+    }
+
+    #[test]
+    fn stringizing_and_pasting() {
+        let source =
+            "#define str(x) #x\n#define cat(a, b) a ## b\nstr(1 + \"a\\n\"); int cat(x, 1);";
+        assert_eq!(expand(source), r#""1 + \"a\\n\""; int x1 ;"#);
+        // an argument of `#` or `##` is not expanded first
+        let source = "#define N 2\n#define str(x) #x\n#define cat(a, b) a ## b\nstr(N) cat(N, N)";
+        assert_eq!(expand(source), "\"N\" NN");
+    }
+
+    #[test]
+    fn conditionals() {
+        let source = "#if 0\nA\n#elif defined(X) && X > 1\nB\n#else\nC\n#endif";
+        assert_eq!(expand(source), "C");
+        assert_eq!(expand(&format!("#define X 2\n{source}")), "B");
+        assert_eq!(expand(&format!("#define X 1\n{source}")), "C");
+        let source =
+            "#ifdef X\nA\n#endif\n#ifndef X\nB\n#endif\n#if (1 << 4) % 5 == 1 ? -1 : 0\nC\n#endif";
+        assert_eq!(expand(source), "B C");
+        // the groups of a skipped block are not evaluated at all
+        assert_eq!(expand("#if 0\n#if 1/0\n#endif\n#else\nA\n#endif"), "A");
+    }
+
+    #[test]
+    fn conditions_short_circuit() {
+        assert_eq!(expand("#if 1 || 1/0\nA\n#endif"), "A");
+        assert_eq!(expand("#if 0 && 1 % 0\n#else\nB\n#endif"), "B");
+        assert_eq!(expand("#if 0 ? 1/0 : 1 ? 2 : 1/0\nC\n#endif"), "C");
+        let error = preprocess("#if 0 || 1/0\n#endif").unwrap_err();
+        assert!(error.to_string().contains("division by zero"), "{error}");
+    }
+
+    #[test]
+    fn include_search_order() {
+        let dir = std::env::temp_dir().join(format!("compiler-pp-{}", std::process::id()));
+        for (path, text) in [
+            ("src/a.h", "int here;"),
+            ("first/a.h", "int first;"),
+            ("first/b.h", "int first_b;"),
+            ("second/a.h", "int second;"),
+            ("second/b.h", "int second_b;"),
+            ("second/c.h", "int second_c;"),
+        ] {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
+        }
+        let mut preprocessor = Preprocessor::with_target(Target::Linux);
+        preprocessor.include_dir(dir.join("first"));
+        preprocessor.include_dir(dir.join("second"));
+        let main = dir.join("src/main.c");
+        let source = "#include \"a.h\"\n#include <a.h>\n#include \"b.h\"\n#include <c.h>\n";
+        let output = preprocessor
+            .preprocess(main.to_str().unwrap(), source)
+            .unwrap();
+        // `"..."` looks next to the including file first, then both in the `-I` order
+        assert_eq!(
+            code(&output),
+            "int here; int first; int first_b; int second_c;"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn builtin_headers_are_the_fallback() {
+        let output = preprocess("#include <stddef.h>\nsize_t n;").unwrap();
+        assert!(
+            output.contains("# 1 \"<built-in>/stddef.h\" 1\n"),
+            "{output}"
+        );
+        let error = preprocess("#include <missing.h>").unwrap_err();
+        assert!(error.to_string().contains("'missing.h' file not found"));
+    }
+
+    #[test]
+    fn line_markers() {
+        let blank = "\n".repeat(MAX_BLANK_LINES + 1);
+        let source = format!("#include <stddef.h>\n\nint a;\n{blank}int b;\n");
+        let output = preprocess(&source).unwrap();
+        let b_line = 4 + MAX_BLANK_LINES + 1;
+        let markers: Vec<_> = output.lines().filter_map(parse_line_marker).collect();
+        // entering and leaving the header, then skipping the blank lines
+        assert_eq!(markers.first(), Some(&("main.c".to_string(), 1)));
+        assert_eq!(markers[1], ("<built-in>/stddef.h".to_string(), 1));
+        assert!(markers.contains(&("main.c".to_string(), 2)), "{output}");
+        assert!(
+            markers.contains(&("main.c".to_string(), b_line)),
+            "{output}"
+        );
+
+        // diagnostics find the file, line and column of any offset through them
+        let offset = output.find("int b").unwrap();
+        assert_eq!(
+            crate::diagnostics::locate(&output, offset),
+            (Some("main.c".to_string()), b_line, 1)
+        );
+        let offset = output.find("int a").unwrap();
+        assert_eq!(
+            crate::diagnostics::locate(&output, offset),
+            (Some("main.c".to_string()), 3, 1)
+        );
+    }
+
+    #[test]
+    fn tokens_keep_their_columns() {
+        let output = preprocess("#define N 1\nint   x = N;\n").unwrap();
+        assert!(output.contains("\nint   x = 1;"), "{output}");
+        assert_eq!(
+            parse_line_marker("# 12 \"dir/a \\\"b\\\".h\" 2"),
+            Some(("dir/a \"b\".h".to_string(), 12))
+        );
+    }
+}
@@ -1,78 +1,192 @@
 use itertools::Itertools;
 
-use crate::ast::{Expr, Program, Statement, Type};
+use crate::ast::{Expr, ExprKind, Initializer, Program, Statement, StatementKind, Type};
+use crate::literal;
 
 fn pretty_print_expr(expr: &Expr) -> String {
-    match expr {
-        Expr::Const(value) => value.to_string(),
-        Expr::Var(name) => name.clone(),
-        Expr::Unary(op, expr) => {
-            let op_str = match op {
-                crate::ast::UnaryOp::Negate => "!",
-                crate::ast::UnaryOp::BitwiseNegate => "~",
-                crate::ast::UnaryOp::Negative => "-",
+    match &expr.kind {
+        ExprKind::Const(value, ty) => {
+            // the suffix keeps the constant's type, e.g. `3000000000` is a `long`
+            let suffix = match ty {
+                Type::UnsignedInt => "U",
+                Type::Long => "L",
+                Type::UnsignedLong => "UL",
+                Type::LongLong => "LL",
+                Type::UnsignedLongLong => "ULL",
+                _ => "",
             };
-            format!("{}{}", op_str, pretty_print_expr(expr))
-        }
-        Expr::Binary(op, left, right) => {
-            let op_str = match op {
-                crate::ast::BinaryOp::Add => "+",
-                crate::ast::BinaryOp::Subtract => "-",
-                crate::ast::BinaryOp::Multiply => "*",
-                crate::ast::BinaryOp::Divide => "/",
-                crate::ast::BinaryOp::Equal => "==",
-                crate::ast::BinaryOp::NotEqual => "!=",
-                crate::ast::BinaryOp::Less => "<",
-                crate::ast::BinaryOp::LessEqual => "<=",
-                crate::ast::BinaryOp::Greater => ">",
-                crate::ast::BinaryOp::GreaterEqual => ">=",
-                crate::ast::BinaryOp::LogicalAnd => "&&",
-                crate::ast::BinaryOp::LogicalOr => "||",
-            };
-            format!(
-                "({} {} {})",
-                pretty_print_expr(left),
-                op_str,
-                pretty_print_expr(right)
-            )
+            format!("{}{}", value, suffix)
         }
-        Expr::Group(expr) => format!("({})", pretty_print_expr(expr)),
-        Expr::Assignment(var, expr) => format!("{} = {}", var, pretty_print_expr(expr)),
-        Expr::Conditional(cond, then_expr, else_expr) => format!(
+        // `{:?}` always keeps a `.` or exponent, so the constant stays floating-point
+        ExprKind::FloatConst(value, Type::Float) => format!("{:?}f", value),
+        ExprKind::FloatConst(value, _) => format!("{:?}", value),
+        ExprKind::Char(c) => format!("'{}'", literal::escape(&[*c], b'\'')),
+        ExprKind::String(bytes) => format!("\"{}\"", literal::escape(bytes, b'"')),
+        ExprKind::Var(name) => name.clone(),
+        ExprKind::Unary(op, expr) => format!("{}{}", op, pretty_print_expr(expr)),
+        ExprKind::Binary(op, left, right) => format!(
+            "({} {} {})",
+            pretty_print_expr(left),
+            op,
+            pretty_print_expr(right)
+        ),
+        ExprKind::Group(expr) => format!("({})", pretty_print_expr(expr)),
+        ExprKind::Assignment(target, expr) => format!(
+            "{} = {}",
+            pretty_print_expr(target),
+            pretty_print_expr(expr)
+        ),
+        ExprKind::CompoundAssignment(op, target, expr) => format!(
+            "{} {}= {}",
+            pretty_print_expr(target),
+            op,
+            pretty_print_expr(expr)
+        ),
+        ExprKind::Conditional(cond, then_expr, else_expr) => format!(
             "({} ? {} : {})",
             pretty_print_expr(cond),
             pretty_print_expr(then_expr),
             pretty_print_expr(else_expr)
         ),
+        ExprKind::AddressOf(expr) => format!("&{}", pretty_print_expr(expr)),
+        ExprKind::Deref(expr) => format!("*{}", pretty_print_expr(expr)),
+        ExprKind::Subscript(array, index) => {
+            format!("{}[{}]", pretty_print_expr(array), pretty_print_expr(index))
+        }
+        // only there after semantic analysis, which also gave it its type
+        ExprKind::Convert(inner) => match &expr.ty {
+            Some(ty) => format!("({}){}", ty, pretty_print_expr(inner)),
+            None => pretty_print_expr(inner),
+        },
+        ExprKind::Cast(ty, expr) => format!("({}){}", ty, pretty_print_expr(expr)),
+        ExprKind::Call(name, args) => {
+            format!(
+                "{}({})",
+                name,
+                args.iter().map(pretty_print_expr).join(", ")
+            )
+        }
+        ExprKind::Member(base, member) => format!("{}.{}", pretty_print_expr(base), member),
+        ExprKind::PtrMember(base, member) => format!("{}->{}", pretty_print_expr(base), member),
+        ExprKind::SizeOf(expr) => format!("sizeof {}", pretty_print_expr(expr)),
+        ExprKind::SizeOfType(ty) => format!("sizeof({})", ty),
+        ExprKind::AlignOf(ty) => format!("_Alignof({})", ty),
+        ExprKind::Comma(first, second) => {
+            format!(
+                "({}, {})",
+                pretty_print_expr(first),
+                pretty_print_expr(second)
+            )
+        }
+        ExprKind::VaStart(ap, Some(last)) => format!(
+            "__builtin_va_start({}, {})",
+            pretty_print_expr(ap),
+            pretty_print_expr(last)
+        ),
+        ExprKind::VaStart(ap, None) => format!("__builtin_va_start({})", pretty_print_expr(ap)),
+        ExprKind::VaArg(ap, ty) => format!("__builtin_va_arg({}, {})", pretty_print_expr(ap), ty),
+        ExprKind::VaEnd(ap) => format!("__builtin_va_end({})", pretty_print_expr(ap)),
+        ExprKind::VaCopy(dest, src) => format!(
+            "__builtin_va_copy({}, {})",
+            pretty_print_expr(dest),
+            pretty_print_expr(src)
+        ),
+    }
+}
+
+fn pretty_print_initializer(init: &Initializer) -> String {
+    match init {
+        Initializer::Single(expr) => pretty_print_expr(expr),
+        Initializer::List(items, _) => format!(
+            "{{{}}}",
+            items.iter().map(pretty_print_initializer).join(", ")
+        ),
+    }
+}
+
+// array dimensions go after the name, e.g. `int a[2][3]`
+fn pretty_print_declarator(var_type: &Type, name: &str) -> String {
+    let mut dims = String::new();
+    let mut base = var_type;
+    while let Type::Array(element, len) = base {
+        dims.push_str(&format!("[{}]", len));
+        base = element;
+    }
+    format!("{} {}{}", pretty_print_type(base), name, dims)
+}
+
+// like the type's `Display`, but a struct or union definition keeps its members
+fn pretty_print_type(ty: &Type) -> String {
+    match ty {
+        Type::Pointer(pointee) => format!("{}*", pretty_print_type(pointee)),
+        Type::Struct(struct_type) => {
+            let Some(members) = &struct_type.members else {
+                return ty.to_string();
+            };
+            let members = members
+                .iter()
+                .map(|member| {
+                    format!(
+                        "{}; ",
+                        pretty_print_declarator(&member.member_type, &member.name)
+                    )
+                })
+                .join("");
+            match &struct_type.tag {
+                Some(tag) => format!("{} {} {{ {}}}", struct_type.kind, tag, members),
+                None => format!("{} {{ {}}}", struct_type.kind, members),
+            }
+        }
+        Type::Enum(enum_type) => {
+            let Some(enumerators) = &enum_type.enumerators else {
+                return ty.to_string();
+            };
+            let enumerators = enumerators
+                .iter()
+                .map(|enumerator| match &enumerator.value {
+                    Some(value) => format!("{} = {}", enumerator.name, pretty_print_expr(value)),
+                    None => enumerator.name.clone(),
+                })
+                .join(", ");
+            match &enum_type.tag {
+                Some(tag) => format!("enum {} {{ {} }}", tag, enumerators),
+                None => format!("enum {{ {} }}", enumerators),
+            }
+        }
+        _ => ty.to_string(),
     }
 }
 
 fn pretty_print_statement(stmt: &Statement, indent: usize) -> String {
     let indent_str = "  ".repeat(indent);
-    match stmt {
-        Statement::Return(expr) => {
+    match &stmt.kind {
+        StatementKind::Return(expr) => {
             format!("{}return {};", indent_str, pretty_print_expr(expr))
         }
-        Statement::Expr(expr) => {
+        StatementKind::Expr(expr) => {
             format!("{}{};", indent_str, pretty_print_expr(expr))
         }
-        Statement::Declare(type_name, var_name, init) => {
-            let type_str = match type_name {
-                Type::Int => "int",
-                Type::Void => "void",
-            };
+        StatementKind::Declare(type_name, var_name, init) => {
+            let declarator = pretty_print_declarator(type_name, var_name);
             match init {
-                Some(expr) => format!(
-                    "{}{} {} = {};",
+                Some(init) => format!(
+                    "{}{} = {};",
                     indent_str,
-                    type_str,
-                    var_name,
-                    pretty_print_expr(expr)
+                    declarator,
+                    pretty_print_initializer(init)
                 ),
-                None => format!("{}{} {};", indent_str, type_str, var_name),
+                None => format!("{}{};", indent_str, declarator),
             }
         }
-        Statement::If(condition, then_stmt, else_stmt) => {
+        StatementKind::DeclareType(ty) => format!("{}{};", indent_str, pretty_print_type(ty)),
+        StatementKind::Typedef(ty, name) => {
+            format!(
+                "{}typedef {};",
+                indent_str,
+                pretty_print_declarator(ty, name)
+            )
+        }
+        StatementKind::If(condition, then_stmt, else_stmt) => {
             let mut result = format!("{}if ({})\n", indent_str, pretty_print_expr(condition));
             result.push_str(&pretty_print_statement(then_stmt, indent));
             if let Some(else_stmt) = else_stmt {
@@ -81,40 +195,71 @@ fn pretty_print_statement(stmt: &Statement, indent: usize) -> String {
             }
             result
         }
-        Statement::Compound(statements) => {
+        StatementKind::Switch(value, body) => {
+            let mut result = format!("{}switch ({})\n", indent_str, pretty_print_expr(value));
+            result.push_str(&pretty_print_statement(body, indent));
+            result
+        }
+        StatementKind::Case(value, stmt) => {
+            let mut result = format!("{}case {}:\n", indent_str, pretty_print_expr(value));
+            result.push_str(&pretty_print_statement(stmt, indent + 1));
+            result
+        }
+        StatementKind::Default(stmt) => {
+            let mut result = format!("{}default:\n", indent_str);
+            result.push_str(&pretty_print_statement(stmt, indent + 1));
+            result
+        }
+        StatementKind::Break => format!("{}break;", indent_str),
+        StatementKind::Labeled(label, stmt) => {
+            let mut result = format!("{}{}:\n", indent_str, label);
+            result.push_str(&pretty_print_statement(stmt, indent));
+            result
+        }
+        StatementKind::Goto(label) => format!("{}goto {};", indent_str, label),
+        StatementKind::Empty => format!("{};", indent_str),
+        StatementKind::Compound(statements) => {
             let mut result = format!("{}{{\n", indent_str);
             for stmt in statements {
                 result.push_str(&format!("{}\n", pretty_print_statement(stmt, indent + 1)));
             }
             format!("{}{}}}", result, indent_str)
         }
+        StatementKind::Error => format!("{}<error>;", indent_str),
     }
 }
 
-pub fn pretty_print(program: &Program) {
+/// The program in the layout `pretty_print` prints
+pub fn render(program: &Program) -> String {
+    let mut out = String::new();
+    for declaration in program.declarations.iter() {
+        out.push_str(&format!("{}\n", pretty_print_statement(declaration, 0)));
+    }
     for f in program.functions.iter() {
-        let return_type = match f.return_type {
-            Type::Int => "int",
-            Type::Void => "void",
-        };
-        println!("FUNC {} {}:", return_type, f.name);
-        println!(
-            "\tparams: ({})",
+        out.push_str(&format!("FUNC {} {}:\n", f.return_type, f.name));
+        out.push_str(&format!(
+            "\tparams: ({})\n",
             f.params
                 .iter()
-                .map(|p| {
-                    let param_type = match p.param_type {
-                        Type::Int => "int",
-                        Type::Void => "void",
-                    };
-                    format!("{} {}", param_type, p.param_name)
-                })
-                .intersperse(", ".to_string())
-                .collect::<String>()
-        );
-        println!("\tbody:");
-        for s in f.block_items.iter() {
-            println!("\t{}", pretty_print_statement(s, 1));
+                .map(|p| format!("{} {}", p.param_type, p.param_name)
+                    .trim_end()
+                    .to_string())
+                .chain(f.variadic.then(|| "...".to_string()))
+                .join(", ")
+        ));
+        match &f.block_items {
+            Some(block_items) => {
+                out.push_str("\tbody:\n");
+                for s in block_items.iter() {
+                    out.push_str(&format!("\t{}\n", pretty_print_statement(s, 1)));
+                }
+            }
+            None => out.push_str("\tdeclaration only\n"),
         }
     }
+    out
+}
+
+pub fn pretty_print(program: &Program) {
+    print!("{}", render(program));
 }
@@ -0,0 +1,466 @@
+//! Prints the AST back as C source that parses to the same AST again.
+//!
+//! Parentheses are only added where precedence needs them; the ones of the source are kept as
+//! `Group` expressions. Braces go on the line of the statement they belong to, and an `else`
+//! holding another `if` becomes an `else if`. The order of the source is kept only within the
+//! declarations and within the functions, see [`print_program`].
+
+use itertools::Itertools;
+
+use crate::ast::{Expr, ExprKind, Func, Initializer, Program, Statement, StatementKind, Type};
+use crate::literal;
+
+const INDENT: &str = "    ";
+
+// precedence levels of the grammar's expression rules, lowest first
+const COMMA: u8 = 0;
+const ASSIGN: u8 = 1;
+const CONDITIONAL: u8 = 2;
+const LOGICAL_OR: u8 = 3;
+const LOGICAL_AND: u8 = 4;
+const EQUALITY: u8 = 5;
+const RELATIONAL: u8 = 6;
+const ADDITIVE: u8 = 7;
+const MULTIPLICATIVE: u8 = 8;
+const CAST: u8 = 9;
+const UNARY: u8 = 10;
+const POSTFIX: u8 = 11;
+const PRIMARY: u8 = 12;
+
+/// The whole program as C. `Program` keeps the declarations apart from the functions, so all
+/// of them are printed ahead of the first function rather than in the order of the source.
+pub fn print_program(program: &Program) -> String {
+    let mut printer = Printer::default();
+    for declaration in &program.declarations {
+        printer.statement(declaration, 0);
+    }
+    for (i, f) in program.functions.iter().enumerate() {
+        if i > 0 || !program.declarations.is_empty() {
+            printer.out.push('\n');
+        }
+        printer.function(f);
+    }
+    printer.out
+}
+
+/// A single statement as C, without indentation
+pub fn print_statement(stmt: &Statement) -> String {
+    let mut printer = Printer::default();
+    printer.statement(stmt, 0);
+    printer.out
+}
+
+/// An expression as C
+pub fn print_expr(expr: &Expr) -> String {
+    Printer::default().expr(expr, COMMA)
+}
+
+#[derive(Default)]
+struct Printer {
+    out: String,
+}
+
+impl Printer {
+    fn line(&mut self, indent: usize, text: &str) {
+        self.out.push_str(&INDENT.repeat(indent));
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn function(&mut self, f: &Func) {
+        let params = f
+            .params
+            .iter()
+            .map(|param| match param.param_name.as_str() {
+                "" => self.type_name(&param.param_type, 0),
+                name => self.declarator(&param.param_type, name, 0),
+            })
+            .chain(f.variadic.then(|| "...".to_string()))
+            .join(", ");
+        // `()` would say nothing about the parameters
+        let params = if params.is_empty() { "void" } else { &params };
+        let head = self.declarator(&f.return_type, &format!("{}({})", f.name, params), 0);
+        match &f.block_items {
+            Some(block_items) => {
+                self.line(0, &format!("{head} {{"));
+                for stmt in block_items {
+                    self.statement(stmt, 1);
+                }
+                self.line(0, "}");
+            }
+            None => self.line(0, &format!("{head};")),
+        }
+    }
+
+    fn statement(&mut self, stmt: &Statement, indent: usize) {
+        match &stmt.kind {
+            StatementKind::Return(expr) => {
+                let expr = self.expr(expr, COMMA);
+                self.line(indent, &format!("return {expr};"));
+            }
+            StatementKind::Expr(expr) => {
+                let expr = self.expr(expr, COMMA);
+                self.line(indent, &format!("{expr};"));
+            }
+            StatementKind::Declare(ty, name, init) => {
+                let declarator = self.declarator(ty, name, indent);
+                match init {
+                    Some(init) => {
+                        let init = self.initializer(init);
+                        self.line(indent, &format!("{declarator} = {init};"));
+                    }
+                    None => self.line(indent, &format!("{declarator};")),
+                }
+            }
+            StatementKind::DeclareType(ty) => {
+                let ty = self.type_name(ty, indent);
+                self.line(indent, &format!("{ty};"));
+            }
+            StatementKind::Typedef(ty, name) => {
+                let declarator = self.declarator(ty, name, indent);
+                self.line(indent, &format!("typedef {declarator};"));
+            }
+            StatementKind::If(..) => {
+                self.out.push_str(&INDENT.repeat(indent));
+                self.if_statement(stmt, indent);
+            }
+            StatementKind::Switch(value, body) => {
+                let value = self.expr(value, COMMA);
+                self.out.push_str(&INDENT.repeat(indent));
+                self.out.push_str(&format!("switch ({value})"));
+                self.body(body, indent);
+            }
+            // labels stand out one level from the statements around them
+            StatementKind::Case(value, stmt) => {
+                let value = self.expr(value, CONDITIONAL);
+                self.line(indent.saturating_sub(1), &format!("case {value}:"));
+                self.statement(stmt, indent);
+            }
+            StatementKind::Default(stmt) => {
+                self.line(indent.saturating_sub(1), "default:");
+                self.statement(stmt, indent);
+            }
+            StatementKind::Labeled(label, stmt) => {
+                self.line(indent.saturating_sub(1), &format!("{label}:"));
+                self.statement(stmt, indent);
+            }
+            StatementKind::Break => self.line(indent, "break;"),
+            StatementKind::Goto(label) => self.line(indent, &format!("goto {label};")),
+            StatementKind::Empty => self.line(indent, ";"),
+            StatementKind::Compound(stmts) => {
+                self.line(indent, "{");
+                for stmt in stmts {
+                    self.statement(stmt, indent + 1);
+                }
+                self.line(indent, "}");
+            }
+            StatementKind::Error => self.line(indent, "/* syntax error */;"),
+        }
+    }
+
+    // `if` and its `else if` chain, continuing the current line
+    fn if_statement(&mut self, stmt: &Statement, indent: usize) {
+        let StatementKind::If(condition, then_stmt, else_stmt) = &stmt.kind else {
+            unreachable!("not an if statement");
+        };
+        let condition = self.expr(condition, COMMA);
+        self.out.push_str(&format!("if ({condition})"));
+        let Some(else_stmt) = else_stmt else {
+            self.body(then_stmt, indent);
+            return;
+        };
+        // an `else` goes with the innermost `if`, so one without its own needs braces around it
+        if is_open(then_stmt) {
+            self.out.push_str(" {\n");
+            self.statement(then_stmt, indent + 1);
+            self.out.push_str(&INDENT.repeat(indent));
+            self.out.push('}');
+        } else if let StatementKind::Compound(stmts) = &then_stmt.kind {
+            self.block(stmts, indent);
+            self.out.pop();
+        } else {
+            self.body(then_stmt, indent);
+            self.out.push_str(&INDENT.repeat(indent));
+            self.out.push_str("else");
+            return self.else_body(else_stmt, indent);
+        }
+        self.out.push_str(" else");
+        self.else_body(else_stmt, indent);
+    }
+
+    fn else_body(&mut self, stmt: &Statement, indent: usize) {
+        if let StatementKind::If(..) = stmt.kind {
+            self.out.push(' ');
+            self.if_statement(stmt, indent);
+        } else {
+            self.body(stmt, indent);
+        }
+    }
+
+    // the statement controlled by an `if`, `else` or `switch`, a block opening on its line
+    fn body(&mut self, stmt: &Statement, indent: usize) {
+        match &stmt.kind {
+            StatementKind::Compound(stmts) => self.block(stmts, indent),
+            _ => {
+                self.out.push('\n');
+                self.statement(stmt, indent + 1);
+            }
+        }
+    }
+
+    fn block(&mut self, stmts: &[Statement], indent: usize) {
+        self.out.push_str(" {\n");
+        for stmt in stmts {
+            self.statement(stmt, indent + 1);
+        }
+        self.line(indent, "}");
+    }
+
+    fn initializer(&self, init: &Initializer) -> String {
+        match init {
+            Initializer::Single(expr) => self.expr(expr, ASSIGN),
+            Initializer::List(items, _) => {
+                let items = items.iter().map(|item| self.initializer(item)).join(", ");
+                format!("{{{items}}}")
+            }
+        }
+    }
+
+    // the declaration of `name` as a `ty`, array dimensions going after the name
+    fn declarator(&self, ty: &Type, name: &str, indent: usize) -> String {
+        let mut dims = String::new();
+        let mut base = ty;
+        while let Type::Array(element, len) = base {
+            dims.push_str(&format!("[{len}]"));
+            base = element;
+        }
+        let base = self.type_name(base, indent);
+        let space = if base.ends_with('*') { "" } else { " " };
+        format!("{base}{space}{name}{dims}")
+    }
+
+    // like the type's `Display`, but definitions of structs and enums keep their bodies
+    fn type_name(&self, ty: &Type, indent: usize) -> String {
+        match ty {
+            Type::Pointer(pointee) => {
+                let pointee = self.type_name(pointee, indent);
+                match pointee.ends_with('*') {
+                    true => format!("{pointee}*"),
+                    false => format!("{pointee} *"),
+                }
+            }
+            Type::Array(..) => self.declarator(ty, "", indent),
+            Type::Struct(struct_type) => {
+                let Some(members) = &struct_type.members else {
+                    return ty.to_string();
+                };
+                let mut out = match &struct_type.tag {
+                    Some(tag) => format!("{} {tag} {{\n", struct_type.kind),
+                    None => format!("{} {{\n", struct_type.kind),
+                };
+                for member in members {
+                    let member = self.declarator(&member.member_type, &member.name, indent + 1);
+                    out.push_str(&format!("{}{member};\n", INDENT.repeat(indent + 1)));
+                }
+                out.push_str(&format!("{}}}", INDENT.repeat(indent)));
+                out
+            }
+            Type::Enum(enum_type) => {
+                let Some(enumerators) = &enum_type.enumerators else {
+                    return ty.to_string();
+                };
+                let enumerators = enumerators
+                    .iter()
+                    .map(|enumerator| match &enumerator.value {
+                        Some(value) => {
+                            format!("{} = {}", enumerator.name, self.expr(value, CONDITIONAL))
+                        }
+                        None => enumerator.name.clone(),
+                    })
+                    .join(", ");
+                match &enum_type.tag {
+                    Some(tag) => format!("enum {tag} {{ {enumerators} }}"),
+                    None => format!("enum {{ {enumerators} }}"),
+                }
+            }
+            _ => ty.to_string(),
+        }
+    }
+
+    // `expr`, in parentheses unless it binds at least as tight as `level`
+    fn expr(&self, expr: &Expr, level: u8) -> String {
+        let text = self.expr_unparenthesized(expr);
+        match precedence(expr) < level {
+            true => format!("({text})"),
+            false => text,
+        }
+    }
+
+    fn expr_unparenthesized(&self, expr: &Expr) -> String {
+        match &expr.kind {
+            ExprKind::Const(value, ty) => {
+                // the suffix keeps the constant's type, e.g. `3000000000` is a `long`
+                let suffix = match ty {
+                    Type::UnsignedInt => "U",
+                    Type::Long => "L",
+                    Type::UnsignedLong => "UL",
+                    Type::LongLong => "LL",
+                    Type::UnsignedLongLong => "ULL",
+                    _ => "",
+                };
+                format!("{value}{suffix}")
+            }
+            // `{:?}` always keeps a `.` or exponent, so the constant stays floating-point
+            ExprKind::FloatConst(value, Type::Float) => format!("{value:?}f"),
+            ExprKind::FloatConst(value, _) => format!("{value:?}"),
+            ExprKind::Char(c) => format!("'{}'", literal::escape(&[*c], b'\'')),
+            ExprKind::String(bytes) => format!("\"{}\"", literal::escape(bytes, b'"')),
+            ExprKind::Var(name) => name.clone(),
+            ExprKind::Unary(op, operand) => prefix(&op.to_string(), &self.expr(operand, CAST)),
+            ExprKind::AddressOf(operand) => prefix("&", &self.expr(operand, CAST)),
+            ExprKind::Deref(operand) => prefix("*", &self.expr(operand, CAST)),
+            ExprKind::Binary(op, left, right) => {
+                let level = binary_precedence(expr);
+                // left associative, so only the right operand needs parentheses at the same level
+                let (left, right) = (self.expr(left, level), self.expr(right, level + 1));
+                format!("{left} {op} {right}")
+            }
+            ExprKind::Group(inner) => format!("({})", self.expr(inner, COMMA)),
+            ExprKind::Assignment(target, value) => {
+                let (target, value) = (self.expr(target, UNARY), self.expr(value, ASSIGN));
+                format!("{target} = {value}")
+            }
+            ExprKind::CompoundAssignment(op, target, value) => {
+                let (target, value) = (self.expr(target, UNARY), self.expr(value, ASSIGN));
+                format!("{target} {op}= {value}")
+            }
+            ExprKind::Conditional(condition, then_expr, else_expr) => format!(
+                "{} ? {} : {}",
+                self.expr(condition, LOGICAL_OR),
+                self.expr(then_expr, COMMA),
+                self.expr(else_expr, CONDITIONAL)
+            ),
+            ExprKind::Subscript(array, index) => {
+                format!("{}[{}]", self.expr(array, POSTFIX), self.expr(index, COMMA))
+            }
+            // implicit, the conversion shows in the types only
+            ExprKind::Convert(inner) => self.expr_unparenthesized(inner),
+            ExprKind::Call(name, args) => {
+                let args = args.iter().map(|arg| self.expr(arg, ASSIGN)).join(", ");
+                format!("{name}({args})")
+            }
+            ExprKind::Cast(ty, operand) => {
+                format!("({}){}", self.type_name(ty, 0), self.expr(operand, CAST))
+            }
+            // `1.x` would lex as the floating-point `1.` followed by `x`
+            ExprKind::Member(base, member) if is_integer(base) => {
+                format!("({}).{member}", self.expr(base, POSTFIX))
+            }
+            ExprKind::Member(base, member) => format!("{}.{member}", self.expr(base, POSTFIX)),
+            ExprKind::PtrMember(base, member) => {
+                format!("{}->{member}", self.expr(base, POSTFIX))
+            }
+            ExprKind::SizeOf(operand) => format!("sizeof {}", self.expr(operand, UNARY)),
+            ExprKind::SizeOfType(ty) => format!("sizeof({})", self.type_name(ty, 0)),
+            ExprKind::AlignOf(ty) => format!("_Alignof({})", self.type_name(ty, 0)),
+            ExprKind::Comma(first, second) => {
+                format!("{}, {}", self.expr(first, COMMA), self.expr(second, ASSIGN))
+            }
+            ExprKind::VaStart(ap, last) => {
+                let args = std::iter::once(ap)
+                    .chain(last)
+                    .map(|arg| self.expr(arg, ASSIGN))
+                    .join(", ");
+                format!("__builtin_va_start({args})")
+            }
+            ExprKind::VaArg(ap, ty) => format!(
+                "__builtin_va_arg({}, {})",
+                self.expr(ap, ASSIGN),
+                self.type_name(ty, 0)
+            ),
+            ExprKind::VaEnd(ap) => format!("__builtin_va_end({})", self.expr(ap, ASSIGN)),
+            ExprKind::VaCopy(dest, src) => format!(
+                "__builtin_va_copy({}, {})",
+                self.expr(dest, ASSIGN),
+                self.expr(src, ASSIGN)
+            ),
+        }
+    }
+}
+
+// an integer constant, which a member access has to parenthesize as `1.x` lexes as `1.` and `x`
+fn is_integer(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Const(..) => true,
+        ExprKind::Convert(inner) => is_integer(inner),
+        _ => false,
+    }
+}
+
+// a prefix operator, kept apart from an operand starting with the same one where the two
+// would lex as `--` or `&&`
+fn prefix(op: &str, operand: &str) -> String {
+    match matches!(op, "-" | "&") && operand.starts_with(op) {
+        true => format!("{op} {operand}"),
+        false => format!("{op}{operand}"),
+    }
+}
+
+fn precedence(expr: &Expr) -> u8 {
+    match &expr.kind {
+        ExprKind::Comma(..) => COMMA,
+        ExprKind::Assignment(..) | ExprKind::CompoundAssignment(..) => ASSIGN,
+        ExprKind::Conditional(..) => CONDITIONAL,
+        ExprKind::Binary(..) => binary_precedence(expr),
+        ExprKind::Cast(..) => CAST,
+        ExprKind::Unary(..)
+        | ExprKind::AddressOf(_)
+        | ExprKind::Deref(_)
+        | ExprKind::SizeOf(_)
+        | ExprKind::SizeOfType(_)
+        | ExprKind::AlignOf(_)
+        | ExprKind::VaStart(..)
+        | ExprKind::VaArg(..)
+        | ExprKind::VaEnd(_)
+        | ExprKind::VaCopy(..) => UNARY,
+        ExprKind::Subscript(..)
+        | ExprKind::Call(..)
+        | ExprKind::Member(..)
+        | ExprKind::PtrMember(..) => POSTFIX,
+        ExprKind::Convert(inner) => precedence(inner),
+        ExprKind::Const(..)
+        | ExprKind::FloatConst(..)
+        | ExprKind::Char(_)
+        | ExprKind::String(_)
+        | ExprKind::Var(_)
+        | ExprKind::Group(_) => PRIMARY,
+    }
+}
+
+fn binary_precedence(expr: &Expr) -> u8 {
+    use crate::ast::BinaryOp::*;
+    match &expr.kind {
+        ExprKind::Binary(op, ..) => match op {
+            LogicalOr => LOGICAL_OR,
+            LogicalAnd => LOGICAL_AND,
+            Equal | NotEqual => EQUALITY,
+            Less | LessEqual | Greater | GreaterEqual => RELATIONAL,
+            Add | Subtract => ADDITIVE,
+            Multiply | Divide => MULTIPLICATIVE,
+        },
+        _ => unreachable!("not a binary expression"),
+    }
+}
+
+// whether the statement ends in an `if` without an `else`, which would take a following `else`
+fn is_open(stmt: &Statement) -> bool {
+    match &stmt.kind {
+        StatementKind::If(_, _, None) => true,
+        StatementKind::If(_, _, Some(stmt))
+        | StatementKind::Switch(_, stmt)
+        | StatementKind::Case(_, stmt)
+        | StatementKind::Default(stmt)
+        | StatementKind::Labeled(_, stmt) => is_open(stmt),
+        _ => false,
+    }
+}
@@ -0,0 +1,257 @@
+//! A tiny C library for programs built without the system's, i.e. with `-nostdlib`. It is
+//! assembly making the system calls itself, appended to the generated program.
+//!
+//! It provides `exit`, `write`, `putchar`, `puts`, `malloc`, `free`, `memcpy` and `memset`.
+//! `malloc` is a bump allocator taking memory from `brk` on Linux and `mmap` on macOS, and
+//! `free` never gives anything back.
+
+use crate::target::{Syscall, Target};
+
+/// Bytes `malloc` asks the system for beyond what the current request needs
+const HEAP_CHUNK: u64 = 1 << 20;
+
+struct Runtime {
+    target: Target,
+    buffer: String,
+}
+
+/// Assembly of the runtime for `target`
+pub fn generate(target: Target) -> String {
+    let mut runtime = Runtime {
+        target,
+        buffer: String::new(),
+    };
+    runtime.emit_line(".text");
+    runtime.emit_line(".align 2");
+    runtime.exit();
+    runtime.write();
+    runtime.putchar();
+    runtime.puts();
+    runtime.malloc();
+    runtime.free();
+    runtime.memcpy();
+    runtime.memset();
+
+    // the allocator's next free byte and the end of the memory it got so far
+    runtime.emit_line(".data");
+    runtime.emit_line(".align 3");
+    let heap = runtime.target.local_label("heap");
+    runtime.emit_label(&heap);
+    runtime.emit_line("\t.quad 0");
+    runtime.emit_line("\t.quad 0");
+    runtime.buffer
+}
+
+impl Runtime {
+    fn emit_line(&mut self, code: &str) {
+        self.buffer.push_str(code);
+        self.buffer.push('\n');
+    }
+
+    fn emit_label(&mut self, label: &str) {
+        self.buffer.push_str(label);
+        self.buffer.push_str(":\n");
+    }
+
+    // start the global function `name`
+    fn emit_function(&mut self, name: &str) {
+        let symbol = self.target.symbol(name);
+        self.emit_line(&format!(".global {symbol}"));
+        self.emit_label(&symbol);
+    }
+
+    fn emit_syscall(&mut self, call: Syscall) {
+        for line in self.target.syscall(call) {
+            self.emit_line(&line);
+        }
+    }
+
+    fn emit_call(&mut self, name: &str) {
+        let symbol = self.target.symbol(name);
+        self.emit_line(&format!("\tbl {symbol}"));
+    }
+
+    // a label local to the runtime, e.g. for loops
+    fn local(&self, name: &str) -> String {
+        self.target.local_label(&format!("rt_{name}"))
+    }
+
+    // void exit(int status)
+    fn exit(&mut self) {
+        self.emit_function("exit");
+        self.emit_syscall(Syscall::Exit);
+    }
+
+    // long write(int fd, const void *buffer, unsigned long count), -1 on failure
+    fn write(&mut self) {
+        self.emit_function("write");
+        self.emit_syscall(Syscall::Write);
+        match self.target {
+            // Linux returns the negated error number
+            Target::Linux => {
+                self.emit_line("\tcmp x0, #0");
+                self.emit_line("\tcsinv x0, x0, xzr, ge");
+            }
+            // macOS sets the carry flag on failure
+            Target::MacOs => self.emit_line("\tcsinv x0, x0, xzr, cc"),
+        }
+        self.emit_line("\tret");
+    }
+
+    // int putchar(int c), the character written or -1
+    fn putchar(&mut self) {
+        self.emit_function("putchar");
+        self.emit_line("\tstp fp, lr, [sp, #-32]!");
+        self.emit_line("\tmov fp, sp");
+        self.emit_line("\tand x0, x0, #0xff");
+        self.emit_line("\tstr x0, [sp, #16]");
+        self.emit_line("\tadd x1, sp, #16");
+        self.emit_line("\tmov x0, #1");
+        self.emit_line("\tmov x2, #1");
+        self.emit_call("write");
+        self.emit_line("\tcmp x0, #1");
+        self.emit_line("\tldr x0, [sp, #16]");
+        self.emit_line("\tcsinv x0, x0, xzr, eq");
+        self.emit_line("\tldp fp, lr, [sp], #32");
+        self.emit_line("\tret");
+    }
+
+    // int puts(const char *s), writing `s` and a newline; 0 or -1
+    fn puts(&mut self) {
+        let (length, found, failed, done) = (
+            self.local("puts_length"),
+            self.local("puts_found"),
+            self.local("puts_failed"),
+            self.local("puts_done"),
+        );
+        self.emit_function("puts");
+        self.emit_line("\tstp fp, lr, [sp, #-16]!");
+        self.emit_line("\tmov fp, sp");
+        self.emit_line("\tmov x2, #0");
+        self.emit_label(&length);
+        self.emit_line("\tldrb w3, [x0, x2]");
+        self.emit_line(&format!("\tcbz w3, {found}"));
+        self.emit_line("\tadd x2, x2, #1");
+        self.emit_line(&format!("\tb {length}"));
+        self.emit_label(&found);
+        self.emit_line("\tmov x1, x0");
+        self.emit_line("\tmov x0, #1");
+        self.emit_call("write");
+        self.emit_line("\tcmp x0, #0");
+        self.emit_line(&format!("\tb.lt {failed}"));
+        self.emit_line("\tmov x0, #10");
+        self.emit_call("putchar");
+        self.emit_line("\tcmp x0, #0");
+        self.emit_line(&format!("\tb.lt {failed}"));
+        self.emit_line("\tmov x0, #0");
+        self.emit_line(&format!("\tb {done}"));
+        self.emit_label(&failed);
+        self.emit_line("\tmov x0, #-1");
+        self.emit_label(&done);
+        self.emit_line("\tldp fp, lr, [sp], #16");
+        self.emit_line("\tret");
+    }
+
+    // void *malloc(unsigned long size), 16-byte aligned memory or NULL
+    fn malloc(&mut self) {
+        let heap = self.target.local_label("heap");
+        let (grow, fits, failed) = (
+            self.local("malloc_grow"),
+            self.local("malloc_fits"),
+            self.local("malloc_failed"),
+        );
+        self.emit_function("malloc");
+        // x10 is the block handed out, x11 the end of the heap and x13 the new next free byte
+        self.emit_line("\tadd x0, x0, #15");
+        self.emit_line("\tand x12, x0, #-16");
+        for line in self.target.load_address(9, &heap) {
+            self.emit_line(&line);
+        }
+        self.emit_line("\tldp x10, x11, [x9]");
+        self.emit_line("\tadd x13, x10, x12");
+        self.emit_line(&format!("\tcbz x10, {grow}"));
+        self.emit_line("\tcmp x13, x11");
+        self.emit_line(&format!("\tb.ls {fits}"));
+        self.emit_label(&grow);
+        self.emit_line(&format!("\tmov x14, #{HEAP_CHUNK}"));
+        match self.target {
+            // the heap grows in place, starting at the initial break
+            Target::Linux => {
+                let started = self.local("malloc_started");
+                self.emit_line(&format!("\tcbnz x10, {started}"));
+                self.emit_line("\tmov x0, #0");
+                self.emit_syscall(Syscall::Brk);
+                self.emit_line("\tmov x10, x0");
+                self.emit_line("\tadd x13, x10, x12");
+                self.emit_label(&started);
+                self.emit_line("\tadd x14, x13, x14");
+                self.emit_line("\tmov x0, x14");
+                self.emit_syscall(Syscall::Brk);
+                // on failure the break stays where it was
+                self.emit_line("\tcmp x0, x14");
+                self.emit_line(&format!("\tb.lo {failed}"));
+                self.emit_line("\tmov x11, x0");
+            }
+            // each time in a new mapping, leaving the rest of the last one unused
+            Target::MacOs => {
+                self.emit_line("\tadd x14, x12, x14");
+                self.emit_line("\tmov x0, #0");
+                self.emit_line("\tmov x1, x14");
+                self.emit_line("\tmov x2, #3"); // PROT_READ | PROT_WRITE
+                self.emit_line("\tmov x3, #0x1002"); // MAP_PRIVATE | MAP_ANON
+                self.emit_line("\tmov x4, #-1");
+                self.emit_line("\tmov x5, #0");
+                self.emit_syscall(Syscall::Mmap);
+                self.emit_line(&format!("\tb.cs {failed}"));
+                self.emit_line("\tmov x10, x0");
+                self.emit_line("\tadd x11, x10, x14");
+                self.emit_line("\tadd x13, x10, x12");
+            }
+        }
+        for line in self.target.load_address(9, &heap) {
+            self.emit_line(&line);
+        }
+        self.emit_label(&fits);
+        self.emit_line("\tstp x13, x11, [x9]");
+        self.emit_line("\tmov x0, x10");
+        self.emit_line("\tret");
+        self.emit_label(&failed);
+        self.emit_line("\tmov x0, #0");
+        self.emit_line("\tret");
+    }
+
+    // void free(void *p), which keeps the memory allocated
+    fn free(&mut self) {
+        self.emit_function("free");
+        self.emit_line("\tret");
+    }
+
+    // void *memcpy(void *dest, const void *src, unsigned long n)
+    fn memcpy(&mut self) {
+        let (copy, done) = (self.local("memcpy_loop"), self.local("memcpy_done"));
+        self.emit_function("memcpy");
+        self.emit_line("\tmov x3, x0");
+        self.emit_line(&format!("\tcbz x2, {done}"));
+        self.emit_label(&copy);
+        self.emit_line("\tldrb w4, [x1], #1");
+        self.emit_line("\tstrb w4, [x3], #1");
+        self.emit_line("\tsubs x2, x2, #1");
+        self.emit_line(&format!("\tb.ne {copy}"));
+        self.emit_label(&done);
+        self.emit_line("\tret");
+    }
+
+    // void *memset(void *s, int c, unsigned long n)
+    fn memset(&mut self) {
+        let (fill, done) = (self.local("memset_loop"), self.local("memset_done"));
+        self.emit_function("memset");
+        self.emit_line("\tmov x3, x0");
+        self.emit_line(&format!("\tcbz x2, {done}"));
+        self.emit_label(&fill);
+        self.emit_line("\tstrb w1, [x3], #1");
+        self.emit_line("\tsubs x2, x2, #1");
+        self.emit_line(&format!("\tb.ne {fill}"));
+        self.emit_label(&done);
+        self.emit_line("\tret");
+    }
+}
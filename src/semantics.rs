@@ -1,110 +1,1374 @@
-use crate::ast::{Expr, Program, Statement};
+use crate::ast::{
+    BinaryOp, EnumType, Expr, ExprKind, Func, Initializer, Program, Span, Statement, StatementKind,
+    StructKind, StructType, Type, UnaryOp,
+};
+use crate::diagnostics::{Diagnostic, Diagnostics};
 use crate::error::CompilerError;
-use std::collections::HashMap;
+use crate::layout::Layouts;
+use itertools::Itertools;
+use std::collections::{HashMap, HashSet};
+
+/// An ordinary identifier, i.e. anything but a tag or member, referred to by its index in
+/// `SemanticAnalyzer::declarations`
+struct Declaration {
+    name: String,
+    span: Span,
+    ty: Type,
+    kind: DeclarationKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DeclarationKind {
+    /// A local or parameter
+    Variable,
+    /// A `typedef` name, standing for the declaration's type
+    Typedef,
+    /// An `enum` constant with this value
+    Enumerator(i64),
+}
+
+/// What a `struct`, `union` or `enum` tag in some scope refers to
+#[derive(Debug, Clone, Copy)]
+enum Tag {
+    /// A `struct` or `union`, by its index in the layout table
+    Struct(usize),
+    Enum,
+}
+
+/// Which declarations are definitely initialized at the current point of the function
+#[derive(Clone, Default)]
+struct InitState {
+    initialized: HashSet<usize>,
+    // set after a `return`; such a path never reaches the code that follows
+    diverges: bool,
+}
+
+impl InitState {
+    /// Combines the states at the end of two alternative paths
+    fn merge(self, other: InitState) -> InitState {
+        match (self.diverges, other.diverges) {
+            (true, _) => other,
+            (_, true) => self,
+            _ => InitState {
+                initialized: self
+                    .initialized
+                    .intersection(&other.initialized)
+                    .copied()
+                    .collect(),
+                diverges: false,
+            },
+        }
+    }
+}
+
+/// The labels of a `switch` body seen so far
+struct SwitchCases {
+    /// Of the controlling expression after promotion, which the case values are converted to
+    ty: Type,
+    values: HashMap<i64, Span>,
+    default: Option<Span>,
+    /// Initialization state on entering the switch, with which every label can be reached
+    entry: InitState,
+    /// States at the `break` statements leaving the switch
+    breaks: Vec<InitState>,
+}
+
+/// A label of the function being analyzed, defined or only jumped to so far
+#[derive(Default)]
+struct Label {
+    defined_at: Option<Span>,
+    /// The first `goto` to the label, reported if it is never defined
+    used_at: Option<Span>,
+    /// States at the `goto`s before the label; jumps back from later code are not
+    /// tracked and assumed to initialize at least as much
+    jumps: Vec<InitState>,
+}
+
+/// Type of a declared function, shared by all its declarations
+struct Signature {
+    return_type: Type,
+    params: Vec<Type>,
+    variadic: bool,
+    declared_at: Span,
+    defined_at: Option<Span>,
+}
 
 #[derive(Default)]
 pub struct SemanticAnalyzer {
-    variables: HashMap<String, bool>, // true if initialized
+    functions: HashMap<String, Signature>, // declared so far, calls must come after a declaration
+    scopes: Vec<HashMap<String, usize>>,   // ordinary identifiers, file scope first
+    tag_scopes: Vec<HashMap<String, Tag>>, // tags live in a namespace of their own
+    layouts: Layouts,
+    declarations: Vec<Declaration>,
+    init_state: InitState,
+    return_type: Option<Type>,      // of the function being analyzed
+    variadic: bool,                 // whether the function being analyzed takes `...`
+    switches: Vec<SwitchCases>,     // enclosing the current statement, innermost last
+    labels: HashMap<String, Label>, // of the function being analyzed
+    diagnostics: Diagnostics,
 }
 
 impl SemanticAnalyzer {
     pub fn new() -> Self {
         Self {
-            variables: HashMap::new(),
+            functions: HashMap::new(),
+            scopes: vec![HashMap::new()],
+            tag_scopes: vec![HashMap::new()],
+            layouts: Layouts::new(),
+            declarations: Vec::new(),
+            init_state: InitState::default(),
+            return_type: None,
+            variadic: false,
+            switches: Vec::new(),
+            labels: HashMap::new(),
+            diagnostics: Diagnostics::new(),
         }
     }
 
-    pub fn analyze(&mut self, program: &Program) -> Result<(), CompilerError> {
-        for function in &program.functions {
-            // Reset variables for each function
-            self.variables.clear();
+    /// Checks the whole program, recording every problem found instead of
+    /// stopping at the first one. Inspect the result via `diagnostics()`.
+    ///
+    /// Every expression gets its type filled in, which code generation relies on.
+    pub fn analyze(&mut self, program: &mut Program) {
+        for declaration in &mut program.declarations {
+            self.analyze_statement(declaration);
+        }
 
-            // Add parameters to the scope
+        for function in &mut program.functions {
+            // Only file scope declarations and tags carry over from the previous function, the
+            // ones of the signature belong to this one
+            self.scopes.truncate(1);
+            self.scopes.push(HashMap::new());
+            self.tag_scopes.truncate(1);
+            self.tag_scopes.push(HashMap::new());
+            self.resolve_type(&mut function.return_type, function.span);
+            for param in &mut function.params {
+                self.resolve_type(&mut param.param_type, param.span);
+            }
+            self.declare_function(function);
+            let Some(block_items) = &mut function.block_items else {
+                continue;
+            };
+            if function.return_type != Type::Void {
+                self.check_complete(&function.return_type, function.span);
+            }
             for param in &function.params {
-                self.variables.insert(param.param_name.clone(), true);
+                self.check_complete(&param.param_type, param.span);
+            }
+
+            self.init_state = InitState::default();
+            self.return_type = Some(function.return_type.clone());
+            self.variadic = function.variadic;
+
+            // Add parameters to the scope, they share it with the function body
+            for param in function.params.iter().filter(|p| !p.param_name.is_empty()) {
+                let id = self.declare(
+                    &param.param_name,
+                    param.span,
+                    param.param_type.clone(),
+                    DeclarationKind::Variable,
+                );
+                self.init_state.initialized.insert(id);
             }
 
             // Analyze function body
-            for statement in &function.block_items {
-                self.analyze_statement(statement)?;
+            for statement in block_items {
+                self.analyze_statement(statement);
             }
+            self.check_labels();
         }
-        Ok(())
     }
 
-    fn analyze_statement(&mut self, statement: &Statement) -> Result<(), CompilerError> {
-        match statement {
-            Statement::Return(expr) => {
-                self.analyze_expr(expr)?;
+    /// Reports jumps to labels the function just analyzed never defines
+    fn check_labels(&mut self) {
+        let undefined = std::mem::take(&mut self.labels)
+            .into_iter()
+            .filter(|(_, label)| label.defined_at.is_none())
+            .filter_map(|(name, label)| Some((label.used_at?, name)))
+            .sorted_by_key(|(span, _)| span.start);
+        for (span, name) in undefined {
+            self.diagnostics
+                .error(CompilerError::UndefinedLabel(name), span);
+        }
+    }
+
+    /// Records the signature of `function`, reporting a second definition or a
+    /// declaration that does not match an earlier one
+    fn declare_function(&mut self, function: &Func) {
+        let params: Vec<Type> = function
+            .params
+            .iter()
+            .map(|param| param.param_type.clone())
+            .collect();
+        let defined_at = function.block_items.is_some().then_some(function.span);
+        let Some(previous) = self.functions.get_mut(&function.name) else {
+            self.functions.insert(
+                function.name.clone(),
+                Signature {
+                    return_type: function.return_type.clone(),
+                    params,
+                    variadic: function.variadic,
+                    declared_at: function.span,
+                    defined_at,
+                },
+            );
+            return;
+        };
+
+        if previous.return_type != function.return_type
+            || previous.params != params
+            || previous.variadic != function.variadic
+        {
+            let declared_at = previous.declared_at;
+            self.diagnostics.push(
+                Diagnostic::error(
+                    CompilerError::ConflictingTypes(function.name.clone()),
+                    function.span,
+                )
+                .with_note(
+                    declared_at,
+                    format!("previous declaration of '{}' is here", function.name),
+                ),
+            );
+        } else if let (Some(first), Some(_)) = (previous.defined_at, defined_at) {
+            self.diagnostics.push(
+                Diagnostic::error(
+                    CompilerError::FunctionRedefined(function.name.clone()),
+                    function.span,
+                )
+                .with_note(first, format!("'{}' first defined here", function.name)),
+            );
+        } else if defined_at.is_some() {
+            previous.defined_at = defined_at;
+        }
+    }
+
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    pub fn into_diagnostics(self) -> Diagnostics {
+        self.diagnostics
+    }
+
+    /// Layout of every `struct` and `union`, needed by code generation
+    pub fn layouts(&self) -> &Layouts {
+        &self.layouts
+    }
+
+    /// Replaces typedef names and `enum` types in `ty` by the types they stand for, and links
+    /// every `struct` or `union` to its entry in the layout table. A definition adds a new
+    /// entry, a reference finds the innermost declaration with that tag or declares the
+    /// type without its members.
+    fn resolve_type(&mut self, ty: &mut Type, span: Span) {
+        let struct_type = match ty {
+            Type::Pointer(inner) | Type::Array(inner, _) => return self.resolve_type(inner, span),
+            Type::Struct(struct_type) => struct_type,
+            Type::Enum(enum_type) => {
+                self.resolve_enum(enum_type, span);
+                // enumerations are just `int`s
+                *ty = Type::Int;
+                return;
+            }
+            Type::Typedef(name) => {
+                *ty = match self.lookup(name).map(|id| &self.declarations[id]) {
+                    Some(declaration) if declaration.kind == DeclarationKind::Typedef => {
+                        declaration.ty.clone()
+                    }
+                    _ => {
+                        self.diagnostics
+                            .error(CompilerError::UnknownTypeName(name.clone()), span);
+                        Type::Int
+                    }
+                };
+                return;
+            }
+            _ => return,
+        };
+        let (kind, tag) = (struct_type.kind, struct_type.tag.clone());
+        let Some(members) = &mut struct_type.members else {
+            let id = match tag.as_ref().and_then(|tag| self.lookup_tag(tag)) {
+                Some(Tag::Struct(id)) if self.layouts.get(id).kind == kind => id,
+                Some(_) => {
+                    self.diagnostics.error(
+                        CompilerError::TagRedefined(Type::Struct(struct_type.clone())),
+                        span,
+                    );
+                    self.layouts.declare(kind, tag)
+                }
+                None => self.declare_tag(kind, tag),
+            };
+            struct_type.id = Some(id);
+            return;
+        };
+
+        // a definition completes a declaration of the same scope, e.g. `struct node;`
+        let current = self.tag_scopes.last().expect("file scope is never popped");
+        let id = match tag.as_ref().and_then(|tag| current.get(tag).copied()) {
+            Some(Tag::Struct(id))
+                if self.layouts.get(id).kind == kind && !self.layouts.get(id).complete =>
+            {
+                id
+            }
+            Some(_) => {
+                let previous = Type::Struct(Box::new(StructType {
+                    kind,
+                    tag: tag.clone(),
+                    members: None,
+                    id: None,
+                }));
+                self.diagnostics
+                    .error(CompilerError::TagRedefined(previous), span);
+                self.layouts.declare(kind, tag)
             }
-            Statement::Expr(expr) => {
-                self.analyze_expr(expr)?;
+            None => self.declare_tag(kind, tag),
+        };
+
+        // the tag is already in scope for members like `struct node *next`
+        let mut placed: Vec<(String, Type)> = Vec::with_capacity(members.len());
+        for member in members.iter_mut() {
+            self.resolve_type(&mut member.member_type, member.span);
+            self.check_complete(&member.member_type, member.span);
+            if placed.iter().any(|(name, _)| *name == member.name) {
+                self.diagnostics.error(
+                    CompilerError::DuplicateMember(member.name.clone()),
+                    member.span,
+                );
             }
-            Statement::Declare(_type, name, init_expr) => {
-                // Check for redeclaration
-                if self.variables.contains_key(name) {
-                    return Err(CompilerError::FunctionRedefined(name.clone()));
+            placed.push((member.name.clone(), member.member_type.clone()));
+        }
+        self.layouts.define(id, placed);
+        struct_type.id = Some(id);
+    }
+
+    /// Declares the constants of an `enum` definition, each one more than the one before
+    /// unless it has a value of its own, or checks that a referenced `enum` is defined
+    fn resolve_enum(&mut self, enum_type: &mut EnumType, span: Span) {
+        let as_type = |tag: &String| {
+            Type::Enum(Box::new(EnumType {
+                tag: Some(tag.clone()),
+                enumerators: None,
+            }))
+        };
+        let Some(enumerators) = &mut enum_type.enumerators else {
+            if let Some(tag) = &enum_type.tag {
+                match self.lookup_tag(tag) {
+                    Some(Tag::Enum) => {}
+                    Some(Tag::Struct(_)) => self
+                        .diagnostics
+                        .error(CompilerError::TagRedefined(as_type(tag)), span),
+                    None => self
+                        .diagnostics
+                        .error(CompilerError::IncompleteType(as_type(tag)), span),
                 }
+            }
+            return;
+        };
+
+        if let Some(tag) = &enum_type.tag {
+            let current = self
+                .tag_scopes
+                .last_mut()
+                .expect("file scope is never popped");
+            if current.insert(tag.clone(), Tag::Enum).is_some() {
+                self.diagnostics
+                    .error(CompilerError::TagRedefined(as_type(tag)), span);
+            }
+        }
+        let mut next = 0i64;
+        for enumerator in enumerators.iter_mut() {
+            if let Some(value) = &mut enumerator.value {
+                next = self.evaluate_constant(value).unwrap_or(next);
+            }
+            self.declare(
+                &enumerator.name,
+                enumerator.span,
+                Type::Int,
+                DeclarationKind::Enumerator(next),
+            );
+            next = next.wrapping_add(1);
+        }
+    }
 
-                // Add variable to scope (initialized if it has an initializer)
-                let is_initialized = init_expr.is_some();
-                self.variables.insert(name.clone(), is_initialized);
+    /// Value of an integer constant expression such as `1 << 4` or `RED + 1`,
+    /// reporting `expr` if it is not one
+    fn evaluate_constant(&mut self, expr: &mut Expr) -> Option<i64> {
+        let ty = self.analyze_value(expr);
+        let value = constant_value(expr).filter(|_| ty.is_integer());
+        if value.is_none() {
+            self.diagnostics
+                .error(CompilerError::NotConstant, expr.span);
+        }
+        value
+    }
+
+    /// Adds a new, still incomplete type to the innermost scope
+    fn declare_tag(&mut self, kind: StructKind, tag: Option<String>) -> usize {
+        let id = self.layouts.declare(kind, tag.clone());
+        if let Some(tag) = tag {
+            self.tag_scopes
+                .last_mut()
+                .expect("file scope is never popped")
+                .insert(tag, Tag::Struct(id));
+        }
+        id
+    }
+
+    fn lookup_tag(&self, tag: &str) -> Option<Tag> {
+        self.tag_scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(tag).copied())
+    }
+
+    /// Reports an object whose size is unknown, e.g. of a struct that is only declared
+    fn check_complete(&mut self, ty: &Type, span: Span) {
+        if !self.layouts.is_complete(ty) {
+            self.diagnostics
+                .error(CompilerError::IncompleteType(ty.clone()), span);
+        }
+    }
+
+    /// Adds `name` to the innermost scope, reporting a clash with an earlier declaration there.
+    /// Only a typedef may be repeated, if it stands for the same type.
+    fn declare(&mut self, name: &str, span: Span, ty: Type, kind: DeclarationKind) -> usize {
+        let id = self.declarations.len();
+        self.declarations.push(Declaration {
+            name: name.to_string(),
+            span,
+            ty,
+            kind,
+        });
+        let scope = self.scopes.last_mut().expect("file scope is never popped");
+        let Some(previous) = scope.insert(name.to_string(), id) else {
+            return id;
+        };
+        let (previous, current) = (&self.declarations[previous], &self.declarations[id]);
+        let error = match (previous.kind, kind) {
+            (DeclarationKind::Typedef, DeclarationKind::Typedef) if previous.ty == current.ty => {
+                return id;
+            }
+            (DeclarationKind::Variable, DeclarationKind::Variable) => {
+                CompilerError::VariableRedeclared(name.to_string())
+            }
+            _ => CompilerError::ConflictingDeclaration(name.to_string()),
+        };
+        let previous_span = previous.span;
+        self.diagnostics
+            .push(Diagnostic::error(error, span).with_note(
+                previous_span,
+                format!("previous declaration of '{}' is here", name),
+            ));
+        id
+    }
+
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+    }
+
+    /// The innermost declaration of `name`, if it is a variable
+    fn lookup_variable(&self, name: &str) -> Option<usize> {
+        self.lookup(name)
+            .filter(|id| self.declarations[*id].kind == DeclarationKind::Variable)
+    }
+
+    fn analyze_statement(&mut self, statement: &mut Statement) {
+        match &mut statement.kind {
+            StatementKind::Return(expr) => {
+                self.analyze_value(expr);
+                match self.return_type.clone() {
+                    Some(Type::Void) | None => {}
+                    Some(return_type) => self.check_assignable(&return_type, expr),
+                }
+                self.init_state.diverges = true;
+            }
+            StatementKind::Expr(expr) => {
+                self.analyze_value(expr);
+            }
+            StatementKind::Declare(var_type, name, init) => {
+                self.resolve_type(var_type, statement.span);
+                self.check_complete(var_type, statement.span);
+                // The variable is already in scope within its own initializer
+                let id = self.declare(
+                    name,
+                    statement.span,
+                    var_type.clone(),
+                    DeclarationKind::Variable,
+                );
 
                 // Analyze initializer if present
-                if let Some(expr) = init_expr {
-                    self.analyze_expr(expr)?;
+                if let Some(init) = init {
+                    self.check_initializer(var_type, init);
+                    self.init_state.initialized.insert(id);
+                }
+                // elements and members are usually filled in one by one, which isn't tracked
+                if var_type.is_array() || var_type.is_struct() {
+                    self.init_state.initialized.insert(id);
                 }
             }
-            Statement::If(condition, then_stmt, else_stmt) => {
-                self.analyze_expr(condition)?;
-                self.analyze_statement(then_stmt)?;
+            StatementKind::DeclareType(ty) => match ty {
+                // `struct node;` declares a new type even if an outer scope has one of that name
+                Type::Struct(struct_type) if struct_type.members.is_none() => {
+                    let current = self.tag_scopes.last().expect("file scope is never popped");
+                    let tag = struct_type.tag.clone();
+                    let id = match tag.as_ref().and_then(|tag| current.get(tag).copied()) {
+                        Some(Tag::Struct(id)) if self.layouts.get(id).kind == struct_type.kind => {
+                            id
+                        }
+                        Some(_) => {
+                            self.diagnostics.error(
+                                CompilerError::TagRedefined(Type::Struct(struct_type.clone())),
+                                statement.span,
+                            );
+                            self.layouts.declare(struct_type.kind, tag)
+                        }
+                        None => self.declare_tag(struct_type.kind, tag),
+                    };
+                    struct_type.id = Some(id);
+                }
+                _ => self.resolve_type(ty, statement.span),
+            },
+            StatementKind::Typedef(ty, name) => {
+                self.resolve_type(ty, statement.span);
+                self.declare(name, statement.span, ty.clone(), DeclarationKind::Typedef);
+            }
+            StatementKind::If(condition, then_stmt, else_stmt) => {
+                self.analyze_condition(condition);
+
+                // a variable is only initialized afterwards if both paths initialize it
+                let before = self.init_state.clone();
+                self.analyze_statement(then_stmt);
+                let after_then = std::mem::replace(&mut self.init_state, before);
                 if let Some(else_stmt) = else_stmt {
-                    self.analyze_statement(else_stmt)?;
+                    self.analyze_statement(else_stmt);
+                }
+                let after_else = std::mem::take(&mut self.init_state);
+                self.init_state = after_then.merge(after_else);
+            }
+            StatementKind::Switch(value, body) => {
+                let value_ty = self.analyze_value(value);
+                let ty = if value_ty.is_integer() {
+                    let promoted = value_ty.promote();
+                    convert(value, promoted.clone());
+                    promoted
+                } else {
+                    self.diagnostics
+                        .error(CompilerError::SwitchNotInteger(value_ty), value.span);
+                    Type::Int
+                };
+                self.switches.push(SwitchCases {
+                    ty,
+                    values: HashMap::new(),
+                    default: None,
+                    entry: self.init_state.clone(),
+                    breaks: Vec::new(),
+                });
+                self.analyze_statement(body);
+                let switch = self.switches.pop().expect("pushed above");
+
+                // the code after the switch is reached by falling off the end of the body,
+                // by a `break`, or directly if no label matches
+                let mut after = std::mem::take(&mut self.init_state);
+                if switch.default.is_none() {
+                    after = after.merge(switch.entry);
+                }
+                self.init_state = switch.breaks.into_iter().fold(after, InitState::merge);
+            }
+            StatementKind::Case(value, stmt) => {
+                let constant = self.evaluate_constant(value);
+                match self.switches.last_mut() {
+                    None => self
+                        .diagnostics
+                        .error(CompilerError::LabelOutsideSwitch("case"), statement.span),
+                    Some(switch) => {
+                        if let Some(constant) = constant {
+                            // compared against the promoted value, so `case -1` of an unsigned
+                            // switch matches `UINT_MAX`
+                            let constant = wrap(constant, &switch.ty);
+                            value.kind = ExprKind::Const(constant as u64, switch.ty.clone());
+                            value.ty = Some(switch.ty.clone());
+                            if let Some(previous) = switch.values.insert(constant, value.span) {
+                                let shown = match switch.ty.is_signed() {
+                                    true => constant.to_string(),
+                                    false => (constant as u64).to_string(),
+                                };
+                                self.diagnostics.push(
+                                    Diagnostic::error(
+                                        CompilerError::DuplicateCase(shown),
+                                        value.span,
+                                    )
+                                    .with_note(previous, "previous case is here".to_string()),
+                                );
+                            }
+                        }
+                        self.init_state =
+                            std::mem::take(&mut self.init_state).merge(switch.entry.clone());
+                    }
+                }
+                self.analyze_statement(stmt);
+            }
+            StatementKind::Default(stmt) => {
+                match self.switches.last_mut() {
+                    None => self
+                        .diagnostics
+                        .error(CompilerError::LabelOutsideSwitch("default"), statement.span),
+                    Some(switch) => {
+                        if let Some(previous) = switch.default.replace(statement.span) {
+                            self.diagnostics.push(
+                                Diagnostic::error(CompilerError::DuplicateDefault, statement.span)
+                                    .with_note(previous, "previous default is here".to_string()),
+                            );
+                        }
+                        self.init_state =
+                            std::mem::take(&mut self.init_state).merge(switch.entry.clone());
+                    }
+                }
+                self.analyze_statement(stmt);
+            }
+            StatementKind::Break => match self.switches.last_mut() {
+                None => self
+                    .diagnostics
+                    .error(CompilerError::BreakOutsideSwitch, statement.span),
+                Some(switch) => {
+                    switch.breaks.push(self.init_state.clone());
+                    // like `return`, nothing after a `break` runs
+                    self.init_state.diverges = true;
+                }
+            },
+            StatementKind::Labeled(name, stmt) => {
+                let label = self.labels.entry(name.clone()).or_default();
+                if let Some(previous) = label.defined_at.replace(statement.span) {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            CompilerError::DuplicateLabel(name.clone()),
+                            statement.span,
+                        )
+                        .with_note(
+                            previous,
+                            format!("previous definition of '{}' is here", name),
+                        ),
+                    );
+                }
+                let jumps = std::mem::take(&mut label.jumps);
+                self.init_state = jumps
+                    .into_iter()
+                    .fold(std::mem::take(&mut self.init_state), InitState::merge);
+                self.analyze_statement(stmt);
+            }
+            StatementKind::Goto(name) => {
+                let label = self.labels.entry(name.clone()).or_default();
+                label.used_at.get_or_insert(statement.span);
+                if label.defined_at.is_none() {
+                    label.jumps.push(self.init_state.clone());
                 }
+                self.init_state.diverges = true;
             }
-            Statement::Compound(statements) => {
+            StatementKind::Compound(statements) => {
+                self.scopes.push(HashMap::new());
+                self.tag_scopes.push(HashMap::new());
                 for stmt in statements {
-                    self.analyze_statement(stmt)?;
+                    self.analyze_statement(stmt);
                 }
+                self.tag_scopes.pop();
+                self.scopes.pop();
+            }
+            StatementKind::Empty => {}
+            StatementKind::Error => {
+                // already reported by the parser
             }
         }
-        Ok(())
     }
 
-    fn analyze_expr(&mut self, expr: &Expr) -> Result<(), CompilerError> {
-        match expr {
-            Expr::Const(_) => {
-                // Constants are always valid
+    /// Computes and records the type of `expr` and its subexpressions.
+    /// Ill-typed expressions are reported and treated as `int` so analysis can go on.
+    fn analyze_expr(&mut self, expr: &mut Expr) -> Type {
+        let span = expr.span;
+        // an enumeration constant is just its value
+        if let ExprKind::Var(name) = &expr.kind {
+            if let Some(id) = self.lookup(name) {
+                if let DeclarationKind::Enumerator(value) = self.declarations[id].kind {
+                    expr.kind = ExprKind::Const(value as u64, Type::Int);
+                }
             }
-            Expr::Var(name) => {
+        }
+        if let Some(value) = self.layout_query(expr) {
+            expr.kind = ExprKind::Const(value as u64, Type::size_t());
+        }
+        let ty = match &mut expr.kind {
+            ExprKind::Const(_, ty) | ExprKind::FloatConst(_, ty) => ty.clone(),
+            ExprKind::Char(_) => Type::Int,
+            // the array includes the terminating NUL
+            ExprKind::String(bytes) => Type::array_of(Type::Char, bytes.len() + 1),
+            ExprKind::Var(name) => {
                 // Check if variable is declared
-                if !self.variables.contains_key(name) {
-                    return Err(CompilerError::UndeclaredVariable(name.clone()));
+                match self.lookup_variable(name) {
+                    Some(id) => {
+                        self.check_initialized(id, span);
+                        self.declarations[id].ty.clone()
+                    }
+                    None => {
+                        self.diagnostics
+                            .error(CompilerError::UndeclaredVariable(name.clone()), span);
+                        Type::Int
+                    }
+                }
+            }
+            ExprKind::Unary(op, operand) => {
+                let operand_ty = self.analyze_value(operand);
+                match op {
+                    UnaryOp::Negate if operand_ty.is_scalar() => Type::Int,
+                    UnaryOp::Negative if operand_ty.is_floating() => operand_ty,
+                    UnaryOp::BitwiseNegate | UnaryOp::Negative if operand_ty.is_integer() => {
+                        let promoted = operand_ty.promote();
+                        convert(operand, promoted.clone());
+                        promoted
+                    }
+                    _ => {
+                        self.diagnostics.error(
+                            CompilerError::InvalidOperand(op.to_string(), operand_ty),
+                            span,
+                        );
+                        Type::Int
+                    }
                 }
             }
-            Expr::Unary(_op, expr) => {
-                self.analyze_expr(expr)?;
+            ExprKind::Binary(op, left, right) => {
+                self.analyze_value(left);
+                match op {
+                    // the right operand might not be evaluated at all
+                    BinaryOp::LogicalAnd | BinaryOp::LogicalOr => {
+                        let before = self.init_state.clone();
+                        self.analyze_value(right);
+                        self.init_state = before;
+                    }
+                    _ => {
+                        self.analyze_value(right);
+                    }
+                }
+                self.binary_type(op, left, right, span)
             }
-            Expr::Binary(_op, left, right) => {
-                self.analyze_expr(left)?;
-                self.analyze_expr(right)?;
+            ExprKind::Group(inner) => self.analyze_expr(inner),
+            ExprKind::Assignment(target, value) => {
+                self.analyze_value(value);
+                let target_ty = self.analyze_place(target);
+                if !target.is_lvalue() || target_ty.is_array() {
+                    self.diagnostics
+                        .error(CompilerError::NotAssignable, target.span);
+                }
+                self.check_assignable(&target_ty, value);
+                target_ty
             }
-            Expr::Group(expr) => {
-                self.analyze_expr(expr)?;
+            ExprKind::CompoundAssignment(op, target, value) => {
+                self.analyze_value(value);
+                // read as well as written, unlike the target of `=`
+                let target_ty = self.analyze_expr(target);
+                if !target.is_lvalue() || target_ty.is_array() {
+                    self.diagnostics
+                        .error(CompilerError::NotAssignable, target.span);
+                }
+                // typed as `target op value`, with the target standing in for the value it holds
+                let mut current = Expr {
+                    kind: ExprKind::Group(target.clone()),
+                    span: target.span,
+                    ty: Some(target_ty.clone()),
+                };
+                let errors = self.diagnostics.error_count();
+                let result_ty = self.binary_type(op, &mut current, value, span);
+                let assignable = (result_ty.is_arithmetic() && target_ty.is_arithmetic())
+                    || result_ty == target_ty;
+                if !assignable && self.diagnostics.error_count() == errors {
+                    self.diagnostics.error(
+                        CompilerError::IncompatibleTypes {
+                            expected: target_ty.clone(),
+                            found: result_ty,
+                        },
+                        span,
+                    );
+                }
+                target_ty
             }
-            Expr::Assignment(name, expr) => {
-                // Check if variable is declared
-                if !self.variables.contains_key(name) {
-                    return Err(CompilerError::UndeclaredVariable(name.clone()));
+            ExprKind::Comma(first, second) => {
+                self.analyze_value(first);
+                self.analyze_value(second)
+            }
+            ExprKind::Conditional(cond, then_expr, else_expr) => {
+                self.analyze_condition(cond);
+                let before = self.init_state.clone();
+                let then_ty = self.analyze_value(then_expr);
+                let after_then = std::mem::replace(&mut self.init_state, before);
+                let else_ty = self.analyze_value(else_expr);
+                let after_else = std::mem::take(&mut self.init_state);
+                self.init_state = after_then.merge(after_else);
+
+                if then_ty.is_arithmetic() && else_ty.is_arithmetic() {
+                    let common = Type::common(&then_ty, &else_ty);
+                    convert(then_expr, common.clone());
+                    convert(else_expr, common.clone());
+                    common
+                } else if then_ty == else_ty
+                    || (then_ty.is_pointer() && is_null_pointer_constant(else_expr))
+                {
+                    then_ty
+                } else if else_ty.is_pointer() && is_null_pointer_constant(then_expr) {
+                    else_ty
+                } else {
+                    self.diagnostics.error(
+                        CompilerError::IncompatibleTypes {
+                            expected: then_ty,
+                            found: else_ty,
+                        },
+                        else_expr.span,
+                    );
+                    Type::Int
+                }
+            }
+            ExprKind::AddressOf(operand) => {
+                let operand_ty = self.analyze_place(operand);
+                if !operand.is_lvalue() {
+                    self.diagnostics
+                        .error(CompilerError::NotAddressable, operand.span);
+                }
+                Type::pointer_to(operand_ty)
+            }
+            ExprKind::Deref(operand) => match self.analyze_value(operand) {
+                Type::Pointer(pointee) if *pointee != Type::Void => *pointee,
+                operand_ty => {
+                    self.diagnostics
+                        .error(CompilerError::InvalidDereference(operand_ty), span);
+                    Type::Int
+                }
+            },
+            // `a[i]` is `*(a + i)`, so `i[a]` is fine as well
+            ExprKind::Subscript(array, index) => {
+                match (self.analyze_value(array), self.analyze_value(index)) {
+                    (Type::Pointer(element), index_ty) | (index_ty, Type::Pointer(element))
+                        if *element != Type::Void && index_ty.is_integer() =>
+                    {
+                        *element
+                    }
+                    (array_ty, index_ty) => {
+                        self.diagnostics
+                            .error(CompilerError::InvalidSubscript(array_ty, index_ty), span);
+                        Type::Int
+                    }
+                }
+            }
+            ExprKind::Call(name, args) => {
+                for arg in args.iter_mut() {
+                    self.analyze_value(arg);
+                }
+                match self.functions.get(name) {
+                    Some(signature) => {
+                        let return_type = signature.return_type.clone();
+                        let params = signature.params.clone();
+                        let variadic = signature.variadic;
+                        if variadic && args.len() < params.len() {
+                            self.diagnostics.error(
+                                CompilerError::TooFewArguments {
+                                    name: name.clone(),
+                                    expected: params.len(),
+                                    found: args.len(),
+                                },
+                                span,
+                            );
+                        } else if !variadic && params.len() != args.len() {
+                            self.diagnostics.error(
+                                CompilerError::WrongArgumentCount {
+                                    name: name.clone(),
+                                    expected: params.len(),
+                                    found: args.len(),
+                                },
+                                span,
+                            );
+                        }
+                        // arguments are converted as if assigned to the parameters
+                        for (param, arg) in params.iter().zip(args.iter_mut()) {
+                            self.check_assignable(param, arg);
+                        }
+                        // the default argument promotions apply to those matching the `...`
+                        for arg in args.iter_mut().skip(params.len()) {
+                            let promoted = match arg.ty() {
+                                Type::Float => Type::Double,
+                                ty if ty.is_integer() => ty.promote(),
+                                ty => ty.clone(),
+                            };
+                            convert(arg, promoted);
+                        }
+                        return_type
+                    }
+                    None => {
+                        self.diagnostics
+                            .error(CompilerError::UndeclaredFunction(name.clone()), span);
+                        Type::Int
+                    }
+                }
+            }
+            ExprKind::VaStart(ap, last) => {
+                self.check_va_list(ap, true);
+                if let Some(last) = last {
+                    self.analyze_value(last);
+                }
+                if !self.variadic {
+                    self.diagnostics
+                        .error(CompilerError::VaStartOutsideVariadic, span);
+                }
+                Type::Void
+            }
+            ExprKind::VaArg(ap, ty) => {
+                self.check_va_list(ap, true);
+                self.resolve_type(ty, span);
+                // anything narrower than `int` or `double` arrives promoted
+                let passed = ty.is_pointer()
+                    || ty.is_struct()
+                    || *ty == Type::Double
+                    || (ty.is_integer() && ty.promote() == *ty);
+                if ty.is_struct() {
+                    self.check_complete(ty, span);
+                }
+                if !passed {
+                    self.diagnostics
+                        .error(CompilerError::InvalidVaArg(ty.clone()), span);
+                }
+                ty.clone()
+            }
+            ExprKind::VaEnd(ap) => {
+                self.check_va_list(ap, true);
+                Type::Void
+            }
+            ExprKind::VaCopy(dest, src) => {
+                self.check_va_list(dest, true);
+                self.check_va_list(src, false);
+                Type::Void
+            }
+            ExprKind::Cast(target, operand) => {
+                self.resolve_type(target, span);
+                let operand_ty = self.analyze_value(operand);
+                let legal = *target == Type::Void
+                    || (target.is_arithmetic() && operand_ty.is_arithmetic())
+                    || ((target.is_pointer() || target.is_integer())
+                        && (operand_ty.is_pointer() || operand_ty.is_integer()));
+                if !legal {
+                    self.diagnostics
+                        .error(CompilerError::InvalidCast(operand_ty, target.clone()), span);
+                }
+                target.clone()
+            }
+            ExprKind::Member(base, name) => {
+                let base_ty = self.analyze_expr(base);
+                self.member_type(&base_ty, name, span)
+            }
+            ExprKind::PtrMember(base, name) => match self.analyze_value(base) {
+                Type::Pointer(pointee) if pointee.is_struct() => {
+                    self.member_type(&pointee, name, span)
+                }
+                base_ty => {
+                    self.diagnostics
+                        .error(CompilerError::NotAStruct(base_ty), base.span);
+                    Type::Int
+                }
+            },
+            ExprKind::Convert(_) => unreachable!("conversions are inserted by semantic analysis"),
+            ExprKind::SizeOf(_) | ExprKind::SizeOfType(_) | ExprKind::AlignOf(_) => {
+                unreachable!("folded into a constant above")
+            }
+        };
+        expr.ty = Some(ty.clone());
+        ty
+    }
+
+    /// Size or alignment asked for by a `sizeof` or `_Alignof` expression, taken from the
+    /// layout table. `None` for any other expression.
+    fn layout_query(&mut self, expr: &mut Expr) -> Option<usize> {
+        let span = expr.span;
+        let (ty, alignment) = match &mut expr.kind {
+            ExprKind::SizeOf(operand) => {
+                // the operand is never evaluated, so it neither reads nor assigns a variable
+                let saved = self.init_state.clone();
+                self.init_state.diverges = true;
+                // arrays don't decay here, `sizeof arr` is the size of the whole array
+                let ty = self.analyze_expr(operand);
+                self.init_state = saved;
+                (ty, false)
+            }
+            ExprKind::SizeOfType(ty) | ExprKind::AlignOf(ty) => {
+                self.resolve_type(ty, span);
+                (ty.clone(), matches!(expr.kind, ExprKind::AlignOf(_)))
+            }
+            _ => return None,
+        };
+        self.check_complete(&ty, span);
+        Some(if alignment {
+            self.layouts.align_of(&ty)
+        } else {
+            self.layouts.size_of(&ty)
+        })
+    }
+
+    /// Type of the member `name` of the struct or union `base_ty`
+    fn member_type(&mut self, base_ty: &Type, name: &str, span: Span) -> Type {
+        if !base_ty.is_struct() {
+            self.diagnostics
+                .error(CompilerError::NotAStruct(base_ty.clone()), span);
+            return Type::Int;
+        }
+        if !self.layouts.is_complete(base_ty) {
+            self.diagnostics
+                .error(CompilerError::IncompleteType(base_ty.clone()), span);
+            return Type::Int;
+        }
+        match self.layouts.member(base_ty, name) {
+            Some(member) => member.member_type.clone(),
+            None => {
+                self.diagnostics.error(
+                    CompilerError::NoSuchMember(base_ty.clone(), name.to_string()),
+                    span,
+                );
+                Type::Int
+            }
+        }
+    }
+
+    /// Analyzes an expression whose value is used, so arrays decay to a pointer to their first element
+    fn analyze_value(&mut self, expr: &mut Expr) -> Type {
+        match self.analyze_expr(expr) {
+            Type::Array(element, _) => {
+                let pointer = Type::Pointer(element);
+                convert(expr, pointer.clone());
+                pointer
+            }
+            ty => ty,
+        }
+    }
+
+    /// Analyzes an expression that is written to or has its address taken rather than being read
+    fn analyze_place(&mut self, expr: &mut Expr) -> Type {
+        let ty = match &mut expr.kind {
+            ExprKind::Var(name) => match self.lookup_variable(name) {
+                Some(id) => {
+                    // may be initialized through here, so no more warnings for it
+                    self.init_state.initialized.insert(id);
+                    self.declarations[id].ty.clone()
+                }
+                None => {
+                    self.diagnostics
+                        .error(CompilerError::UndeclaredVariable(name.clone()), expr.span);
+                    Type::Int
+                }
+            },
+            ExprKind::Group(inner) => self.analyze_place(inner),
+            _ => return self.analyze_expr(expr),
+        };
+        expr.ty = Some(ty.clone());
+        ty
+    }
+
+    /// Checks that `ap` is a `va_list` object, which `<stdarg.h>` declares as `char *` on Apple
+    /// platforms and as `struct __va_list` elsewhere. The builtins modifying it count as
+    /// initializing it.
+    fn check_va_list(&mut self, ap: &mut Expr, modified: bool) {
+        let ty = if modified {
+            self.analyze_place(ap)
+        } else {
+            self.analyze_expr(ap)
+        };
+        let is_va_list = match &ty {
+            Type::Pointer(pointee) => **pointee == Type::Char,
+            Type::Struct(struct_type) => struct_type.tag.as_deref() == Some("__va_list"),
+            _ => false,
+        };
+        if !is_va_list {
+            self.diagnostics
+                .error(CompilerError::NotAVaList(ty), ap.span);
+        } else if !ap.is_lvalue() {
+            self.diagnostics
+                .error(CompilerError::NotAddressable, ap.span);
+        }
+    }
+
+    /// Checks an initializer against the declared type, recursing into brace-enclosed lists
+    fn check_initializer(&mut self, ty: &Type, init: &mut Initializer) {
+        match init {
+            Initializer::Single(expr) => {
+                // `char s[4] = "abc";` copies the string, its NUL is dropped if it does not fit
+                if let (Type::Array(element, len), ExprKind::String(bytes)) = (ty, &expr.kind) {
+                    if element.is_char() {
+                        if bytes.len() > *len {
+                            self.diagnostics
+                                .error(CompilerError::TooManyInitializers(ty.clone()), expr.span);
+                        }
+                        self.analyze_expr(expr);
+                        return;
+                    }
+                }
+                self.analyze_value(expr);
+                if ty.is_array() {
+                    self.diagnostics
+                        .error(CompilerError::InvalidInitializer(ty.clone()), expr.span);
+                } else {
+                    self.check_assignable(ty, expr);
                 }
-                self.analyze_expr(expr)?;
             }
-            Expr::Conditional(cond, then_expr, else_expr) => {
-                self.analyze_expr(cond)?;
-                self.analyze_expr(then_expr)?;
-                self.analyze_expr(else_expr)?;
+            Initializer::List(items, span) => match ty {
+                Type::Array(element, len) => {
+                    if items.len() > *len {
+                        self.diagnostics
+                            .error(CompilerError::TooManyInitializers(ty.clone()), *span);
+                    }
+                    for item in items {
+                        self.check_initializer(element, item);
+                    }
+                }
+                // members are initialized in order, a union only through its first member
+                Type::Struct(_) => {
+                    let layout = self.layouts.of(ty).expect("struct types are resolved");
+                    let mut members: Vec<Type> = layout
+                        .members
+                        .iter()
+                        .map(|member| member.member_type.clone())
+                        .collect();
+                    if layout.kind == StructKind::Union {
+                        members.truncate(1);
+                    }
+                    if items.len() > members.len() {
+                        self.diagnostics
+                            .error(CompilerError::TooManyInitializers(ty.clone()), *span);
+                    }
+                    for (member, item) in members.iter().zip(items) {
+                        self.check_initializer(member, item);
+                    }
+                }
+                // braces around a scalar, e.g. `int x = {1};`
+                _ => match items.as_mut_slice() {
+                    [item] => self.check_initializer(ty, item),
+                    _ => self
+                        .diagnostics
+                        .error(CompilerError::InvalidInitializer(ty.clone()), *span),
+                },
+            },
+        }
+    }
+
+    fn analyze_condition(&mut self, condition: &mut Expr) {
+        let ty = self.analyze_value(condition);
+        if !ty.is_scalar() {
+            self.diagnostics.error(
+                CompilerError::IncompatibleTypes {
+                    expected: Type::Int,
+                    found: ty,
+                },
+                condition.span,
+            );
+        }
+    }
+
+    /// Result type of a binary operation on already analyzed operands.
+    /// Arithmetic operands are converted to their common type first.
+    fn binary_type(
+        &mut self,
+        op: &BinaryOp,
+        left: &mut Expr,
+        right: &mut Expr,
+        span: Span,
+    ) -> Type {
+        let (l, r) = (left.ty().clone(), right.ty().clone());
+        if l.is_arithmetic() && r.is_arithmetic() {
+            let common = Type::common(&l, &r);
+            convert(left, common.clone());
+            convert(right, common.clone());
+            return match op {
+                BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Multiply | BinaryOp::Divide => {
+                    common
+                }
+                _ => Type::Int,
+            };
+        }
+
+        let (l, r) = (&l, &r);
+        let result = match op {
+            BinaryOp::Add => match (l, r) {
+                (Type::Pointer(_), _) if r.is_integer() => Some(l.clone()),
+                (_, Type::Pointer(_)) if l.is_integer() => Some(r.clone()),
+                _ => None,
+            },
+            BinaryOp::Subtract => match (l, r) {
+                (Type::Pointer(_), _) if r.is_integer() => Some(l.clone()),
+                // distance between two elements of the same array, a `ptrdiff_t`
+                (Type::Pointer(_), Type::Pointer(_)) if l == r => Some(Type::Long),
+                _ => None,
+            },
+            BinaryOp::Multiply | BinaryOp::Divide => None,
+            BinaryOp::Equal | BinaryOp::NotEqual => {
+                let comparable = (l.is_pointer() && r.is_pointer() && pointers_compatible(l, r))
+                    || (l.is_pointer() && is_null_pointer_constant(right))
+                    || (r.is_pointer() && is_null_pointer_constant(left));
+                comparable.then_some(Type::Int)
+            }
+            BinaryOp::Less | BinaryOp::LessEqual | BinaryOp::Greater | BinaryOp::GreaterEqual => {
+                (l.is_pointer() && l == r).then_some(Type::Int)
+            }
+            BinaryOp::LogicalAnd | BinaryOp::LogicalOr => {
+                (l.is_scalar() && r.is_scalar()).then_some(Type::Int)
             }
+        };
+        result.unwrap_or_else(|| {
+            self.diagnostics.error(
+                CompilerError::InvalidOperands(op.to_string(), l.clone(), r.clone()),
+                span,
+            );
+            Type::Int
+        })
+    }
+
+    /// Reports `value` if it cannot be implicitly converted to `target`, as in an assignment,
+    /// and otherwise converts it
+    fn check_assignable(&mut self, target: &Type, value: &mut Expr) {
+        if target.is_arithmetic() && value.ty().is_arithmetic() {
+            convert(value, target.clone());
+            return;
+        }
+        let found = value.ty();
+        let ok = target == found
+            || (target.is_pointer() && is_null_pointer_constant(value))
+            || (target.is_pointer() && found.is_pointer() && pointers_compatible(target, found));
+        if !ok {
+            self.diagnostics.error(
+                CompilerError::IncompatibleTypes {
+                    expected: target.clone(),
+                    found: found.clone(),
+                },
+                value.span,
+            );
+        }
+    }
+
+    /// Warns when a variable is read on a path where it was never assigned
+    fn check_initialized(&mut self, id: usize, span: Span) {
+        if self.init_state.diverges || self.init_state.initialized.contains(&id) {
+            return;
         }
-        Ok(())
+        let declaration = &self.declarations[id];
+        self.diagnostics.push(
+            Diagnostic::warning(CompilerError::UseBeforeInit(declaration.name.clone()), span)
+                .with_note(
+                    declaration.span,
+                    format!(
+                        "'{}' declared here without an initializer",
+                        declaration.name
+                    ),
+                ),
+        );
+        // only warn once per variable
+        self.init_state.initialized.insert(id);
+    }
+}
+
+/// Wraps `expr` in an implicit conversion to `ty`, unless it already has that type
+fn convert(expr: &mut Expr, ty: Type) {
+    if expr.ty.as_ref() == Some(&ty) {
+        return;
+    }
+    let span = expr.span;
+    let inner = std::mem::replace(expr, Expr::new(ExprKind::Const(0, Type::Int), span));
+    *expr = Expr {
+        kind: ExprKind::Convert(Box::new(inner)),
+        span,
+        ty: Some(ty),
+    };
+}
+
+/// Value of an integer constant expression that has been analyzed, `None` if `expr` is not one
+fn constant_value(expr: &Expr) -> Option<i64> {
+    let value = match &expr.kind {
+        ExprKind::Const(value, _) => *value as i64,
+        ExprKind::Char(c) => *c as i8 as i64,
+        ExprKind::Group(inner) | ExprKind::Convert(inner) | ExprKind::Cast(_, inner) => {
+            constant_value(inner)?
+        }
+        ExprKind::Unary(op, operand) => {
+            let value = constant_value(operand)?;
+            match op {
+                UnaryOp::Negate => (value == 0) as i64,
+                UnaryOp::Negative => value.wrapping_neg(),
+                UnaryOp::BitwiseNegate => !value,
+            }
+        }
+        ExprKind::Binary(op, left, right) => {
+            let (l, r) = (constant_value(left)?, constant_value(right)?);
+            // operands share their type after the usual arithmetic conversions
+            let signed = left.ty().is_signed();
+            let compare = |signed_result: bool, unsigned_result: bool| {
+                (if signed {
+                    signed_result
+                } else {
+                    unsigned_result
+                }) as i64
+            };
+            match op {
+                BinaryOp::Add => l.wrapping_add(r),
+                BinaryOp::Subtract => l.wrapping_sub(r),
+                BinaryOp::Multiply => l.wrapping_mul(r),
+                BinaryOp::Divide if r == 0 => return None,
+                BinaryOp::Divide if signed => l.wrapping_div(r),
+                BinaryOp::Divide => (l as u64 / r as u64) as i64,
+                BinaryOp::Equal => (l == r) as i64,
+                BinaryOp::NotEqual => (l != r) as i64,
+                BinaryOp::Less => compare(l < r, (l as u64) < r as u64),
+                BinaryOp::LessEqual => compare(l <= r, l as u64 <= r as u64),
+                BinaryOp::Greater => compare(l > r, l as u64 > r as u64),
+                BinaryOp::GreaterEqual => compare(l >= r, l as u64 >= r as u64),
+                BinaryOp::LogicalAnd => (l != 0 && r != 0) as i64,
+                BinaryOp::LogicalOr => (l != 0 || r != 0) as i64,
+            }
+        }
+        ExprKind::Conditional(cond, then_expr, else_expr) => {
+            if constant_value(cond)? != 0 {
+                constant_value(then_expr)?
+            } else {
+                constant_value(else_expr)?
+            }
+        }
+        _ => return None,
+    };
+    // e.g. `(struct s)1`, which only has a value once it is rejected
+    if !expr.ty().is_integer() {
+        return None;
+    }
+    Some(wrap(value, expr.ty()))
+}
+
+/// Truncates `value` to the integer type `ty`, e.g. `(unsigned char)300` is 44
+fn wrap(value: i64, ty: &Type) -> i64 {
+    if !ty.is_integer() || ty.size() == 8 {
+        return value;
+    }
+    let bits = ty.size() * 8;
+    let shift = 64 - bits;
+    if ty.is_signed() {
+        (value << shift) >> shift
+    } else {
+        ((value as u64) << shift >> shift) as i64
+    }
+}
+
+/// An integer constant `0`, possibly cast to `void *`, which converts to any pointer type
+fn is_null_pointer_constant(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Const(0, _) => true,
+        ExprKind::Cast(Type::Pointer(pointee), inner) if **pointee == Type::Void => {
+            is_null_pointer_constant(inner)
+        }
+        ExprKind::Group(inner) => is_null_pointer_constant(inner),
+        _ => false,
+    }
+}
+
+/// Pointers of the same type, or where one side is `void *`
+fn pointers_compatible(a: &Type, b: &Type) -> bool {
+    match (a, b) {
+        (Type::Pointer(a), Type::Pointer(b)) => a == b || **a == Type::Void || **b == Type::Void,
+        _ => false,
     }
 }
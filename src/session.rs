@@ -0,0 +1,248 @@
+//! The compiler as a library. A [`Session`] runs the stages of the pipeline on one source file
+//! one at a time, [`compile`] runs all of them.
+//!
+//! ```no_run
+//! let output = compiler::compile("int main() { return 0; }", &compiler::Options::default());
+//! ```
+
+use lalrpop_util::ParseError;
+
+use crate::ast::{Program, Span};
+use crate::diagnostics::{self, Diagnostic, Diagnostics};
+use crate::error::CompilerError;
+use crate::generate::CodeGenerator;
+use crate::grammar;
+use crate::layout::Layouts;
+use crate::lexer::{Lexer, Token, TypedefNames};
+use crate::peephole;
+use crate::preprocess::Preprocessor;
+use crate::semantics::SemanticAnalyzer;
+use crate::target::Target;
+
+/// How to compile a file, the part of the command line that is not about files
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub target: Target,
+    /// `-I`, searched in order
+    pub include_dirs: Vec<String>,
+    /// `-D` macros and their values, in order
+    pub defines: Vec<(String, String)>,
+    /// `-O1` and up run the peephole optimizer
+    pub opt_level: u8,
+    /// `-nostdlib`, the program runs without a C library
+    pub freestanding: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            target: Target::host(),
+            include_dirs: Vec::new(),
+            defines: Vec::new(),
+            opt_level: 0,
+            freestanding: false,
+        }
+    }
+}
+
+/// A successfully compiled file
+#[derive(Debug)]
+pub struct Output {
+    pub asm: String,
+    /// The warnings, whose spans point into `source_map`
+    pub diagnostics: Diagnostics,
+    pub source_map: SourceMap,
+}
+
+/// The preprocessed source being compiled, which spans point into
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SourceMap {
+    /// Name of the file, e.g. in diagnostics
+    pub name: String,
+    pub text: String,
+}
+
+impl SourceMap {
+    /// File, 1-based line and column of a byte offset, as the preprocessor's line markers tell
+    pub fn locate(&self, offset: usize) -> (String, usize, usize) {
+        let (file, line, col) = diagnostics::locate(&self.text, offset);
+        (file.unwrap_or_else(|| self.name.clone()), line, col)
+    }
+
+    /// Renders `diagnostics` with the lines of source they point at
+    pub fn render(&self, diagnostics: &Diagnostics) -> String {
+        diagnostics.render(&self.name, &self.text)
+    }
+}
+
+/// One file going through the compiler, with the diagnostics of every stage so far
+#[derive(Debug)]
+pub struct Session {
+    options: Options,
+    source_map: SourceMap,
+    diagnostics: Diagnostics,
+}
+
+impl Session {
+    pub fn new(options: Options) -> Self {
+        Self {
+            options,
+            source_map: SourceMap::default(),
+            diagnostics: Diagnostics::new(),
+        }
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn target(&self) -> Target {
+        self.options.target
+    }
+
+    pub fn source_map(&self) -> &SourceMap {
+        &self.source_map
+    }
+
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    pub fn into_diagnostics(self) -> Diagnostics {
+        self.diagnostics
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.has_errors()
+    }
+
+    /// Preprocesses `source`, with `name` as the name of its file, making the result the
+    /// session's source. Preprocessor errors have no span, they name the file and line.
+    pub fn preprocess(&mut self, name: &str, source: &str) -> Result<&str, CompilerError> {
+        let text = self.preprocessor().preprocess(name, source)?;
+        self.source_map = SourceMap {
+            name: name.to_string(),
+            text,
+        };
+        Ok(&self.source_map.text)
+    }
+
+    /// Reads and preprocesses the file at `path`, like [`Session::preprocess`]
+    pub fn preprocess_file(&mut self, path: &str) -> Result<&str, CompilerError> {
+        let source = std::fs::read_to_string(path)?;
+        self.preprocess(path, &source)
+    }
+
+    fn preprocessor(&self) -> Preprocessor {
+        let mut preprocessor = Preprocessor::with_target(self.options.target);
+        for dir in &self.options.include_dirs {
+            preprocessor.include_dir(dir);
+        }
+        let hosted = if self.options.freestanding { "0" } else { "1" };
+        preprocessor.define("__STDC_HOSTED__", hosted);
+        if self.options.opt_level > 0 {
+            preprocessor.define("__OPTIMIZE__", "1");
+        }
+        for (name, value) in &self.options.defines {
+            preprocessor.define(name, value);
+        }
+        preprocessor
+    }
+
+    /// Reads a program from a JSON document such as `--emit=ast-json` writes, see
+    /// [`crate::json`], making the source in it the session's. The program is yet to be
+    /// analyzed.
+    #[cfg(feature = "serde")]
+    pub fn load_ast_json(&mut self, json: &str) -> Result<Program, CompilerError> {
+        let (program, source_map) = crate::json::from_str(json)?;
+        self.source_map = source_map;
+        Ok(program)
+    }
+
+    /// The tokens of the session's source up to the first one the lexer rejects. Typedef
+    /// names show as identifiers, as only the parser declares them.
+    pub fn tokens(&mut self) -> Vec<(Span, Token<'_>)> {
+        let names = TypedefNames::new();
+        let mut tokens = Vec::new();
+        for token in Lexer::new(&self.source_map.text, &names) {
+            match token {
+                Ok((start, token, end)) => tokens.push((Span::new(start, end), token)),
+                Err(error) => {
+                    self.diagnostics.push(ParseError::User { error }.into());
+                    break;
+                }
+            }
+        }
+        tokens
+    }
+
+    /// Parses the session's source. Syntax errors the parser recovered from leave `Error`
+    /// statements in the program, `None` means it could not recover.
+    pub fn parse(&mut self) -> Option<Program> {
+        let mut syntax_errors = Vec::new();
+        let names = TypedefNames::new();
+        let parsed = grammar::ProgramParser::new().parse(
+            &mut syntax_errors,
+            &names,
+            Lexer::new(&self.source_map.text, &names),
+        );
+        for recovered in syntax_errors {
+            self.diagnostics.push(recovered.error.into());
+        }
+        match parsed {
+            Ok(program) => Some(program),
+            Err(e) => {
+                self.diagnostics.push(e.into());
+                None
+            }
+        }
+    }
+
+    /// Checks `program`, filling in the types of its expressions, and lays out its structs
+    pub fn analyze(&mut self, program: &mut Program) -> Layouts {
+        let mut analyzer = SemanticAnalyzer::new();
+        analyzer.analyze(program);
+        let layouts = analyzer.layouts().clone();
+        self.diagnostics.extend(analyzer.into_diagnostics());
+        layouts
+    }
+
+    /// Assembly for the analyzed `program`, optimized as the options ask
+    pub fn generate(&self, program: &Program, layouts: Layouts) -> String {
+        let mut generator = CodeGenerator::with_target(self.options.target);
+        generator.freestanding(self.options.freestanding);
+        generator.generate(program, layouts);
+        let asm = generator.output();
+        match self.options.opt_level {
+            0 => asm,
+            _ => peephole::optimize(&asm),
+        }
+    }
+}
+
+/// Compiles the C source `source` to assembly. A preprocessor error is the one diagnostic
+/// without a span of its own, it points at the start.
+pub fn compile(source: &str, options: &Options) -> Result<Output, Diagnostics> {
+    let mut session = Session::new(options.clone());
+    if let Err(e) = session.preprocess("<source>", source) {
+        session
+            .diagnostics
+            .push(Diagnostic::error(e, Span::default()));
+        return Err(session.diagnostics);
+    }
+    let Some(mut program) = session.parse() else {
+        return Err(session.diagnostics);
+    };
+    // semantic analysis checks, also run on a partially parsed program to report as much as possible
+    let layouts = session.analyze(&mut program);
+    if session.has_errors() {
+        return Err(session.diagnostics);
+    }
+    let asm = session.generate(&program, layouts);
+    Ok(Output {
+        asm,
+        diagnostics: session.diagnostics,
+        source_map: session.source_map,
+    })
+}
@@ -0,0 +1,130 @@
+/// Platform the generated assembly is meant for, both use AArch64 but differ in assembler syntax
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Linux,
+    MacOs,
+}
+
+impl std::str::FromStr for Target {
+    type Err = String;
+
+    /// Parses `--target`, either `linux` or `macos`, or a triple such as `aarch64-linux-gnu`
+    /// or `arm64-apple-darwin`
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "linux" => return Ok(Target::Linux),
+            "macos" | "darwin" => return Ok(Target::MacOs),
+            _ => {}
+        }
+        let unknown = || format!("unknown target '{name}'");
+        let (arch, rest) = name.split_once('-').ok_or_else(unknown)?;
+        if !matches!(arch, "aarch64" | "arm64") {
+            return Err(format!(
+                "unsupported target '{name}', only AArch64 is supported"
+            ));
+        }
+        if rest.contains("linux") {
+            Ok(Target::Linux)
+        } else if ["apple", "darwin", "macos"]
+            .iter()
+            .any(|os| rest.contains(os))
+        {
+            Ok(Target::MacOs)
+        } else {
+            Err(unknown())
+        }
+    }
+}
+
+impl Target {
+    /// The platform the compiler itself runs on, anything but macOS is treated as Linux
+    pub fn host() -> Self {
+        if cfg!(target_os = "macos") {
+            Target::MacOs
+        } else {
+            Target::Linux
+        }
+    }
+
+    /// Section directive for read-only data such as string literals
+    pub fn rodata_section(self) -> &'static str {
+        match self {
+            Target::Linux => ".section .rodata",
+            Target::MacOs => ".section __TEXT,__const",
+        }
+    }
+
+    /// Assembly name of the C function or object `name`, Mach-O prefixes C symbols with `_`
+    pub fn symbol(self, name: &str) -> String {
+        match self {
+            Target::Linux => name.to_string(),
+            Target::MacOs => format!("_{name}"),
+        }
+    }
+
+    /// Instructions making the system call `call`, with its arguments in x0-x5
+    pub fn syscall(self, call: Syscall) -> [String; 2] {
+        match self {
+            Target::Linux => [
+                format!("\tmov x8, #{}", call.linux()),
+                "\tsvc #0".to_string(),
+            ],
+            Target::MacOs => [
+                format!("\tmov x16, #{}", call.macos()),
+                "\tsvc #0x80".to_string(),
+            ],
+        }
+    }
+
+    /// Label that stays local to the object file instead of becoming a symbol
+    pub fn local_label(self, name: &str) -> String {
+        match self {
+            Target::Linux => format!(".L{name}"),
+            Target::MacOs => format!("L{name}"),
+        }
+    }
+
+    /// Instructions loading the address of `label` into x{reg}, through its 4KiB page
+    pub fn load_address(self, reg: u8, label: &str) -> [String; 2] {
+        match self {
+            Target::Linux => [
+                format!("\tadrp x{reg}, {label}"),
+                format!("\tadd x{reg}, x{reg}, :lo12:{label}"),
+            ],
+            Target::MacOs => [
+                format!("\tadrp x{reg}, {label}@PAGE"),
+                format!("\tadd x{reg}, x{reg}, {label}@PAGEOFF"),
+            ],
+        }
+    }
+}
+
+/// System calls the generated code makes itself, when there is no C library to do them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syscall {
+    Exit,
+    Write,
+    /// Only on Linux, macOS has no `brk`
+    Brk,
+    Mmap,
+}
+
+impl Syscall {
+    fn linux(self) -> u16 {
+        match self {
+            Syscall::Exit => 93,
+            Syscall::Write => 64,
+            Syscall::Brk => 214,
+            Syscall::Mmap => 222,
+        }
+    }
+
+    fn macos(self) -> u16 {
+        match self {
+            Syscall::Exit => 1,
+            Syscall::Write => 4,
+            Syscall::Brk => unreachable!("macOS has no brk system call"),
+            Syscall::Mmap => 197,
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! The AST written as JSON reads back as the same program, which compiles like the source.
+#![cfg(feature = "serde")]
+
+use compiler::error::CompilerError;
+use compiler::{json, Options, Session};
+
+const SOURCE: &str = r#"#include <stdio.h>
+
+struct point { int x; int y; };
+typedef struct point point_t;
+enum color { RED, GREEN = 4 };
+
+int main(void) {
+    point_t p = {1, GREEN};
+    char *s = "hi\n";
+    double d = 2.5;
+    if (p.x > 0) printf("%s %f\n", s, d); else return -1;
+    switch (p.y) { case 4: return (int)sizeof(p); default: break; }
+    return 0;
+}
+"#;
+
+fn session() -> Session {
+    let options = Options {
+        target: "linux".parse().unwrap(),
+        ..Options::default()
+    };
+    Session::new(options)
+}
+
+fn compile(session: &mut Session, mut program: compiler::ast::Program) -> String {
+    let layouts = session.analyze(&mut program);
+    assert!(!session.has_errors(), "{:?}", session.diagnostics());
+    session.generate(&program, layouts)
+}
+
+#[test]
+fn compiles_like_the_source() {
+    let mut direct = session();
+    direct.preprocess("point.c", SOURCE).unwrap();
+    let program = direct.parse().unwrap();
+    let json = json::to_string(&program, direct.source_map());
+    let asm = compile(&mut direct, program);
+
+    let mut loaded = session();
+    let program = loaded.load_ast_json(&json).unwrap();
+    assert_eq!(loaded.source_map().text, direct.source_map().text);
+    let reloaded = json::to_string(&program, loaded.source_map());
+    assert_eq!(json, reloaded);
+    assert_eq!(compile(&mut loaded, program), asm);
+}
+
+#[test]
+fn types_may_be_left_out() {
+    let json = r#"{
+        "version": 1,
+        "program": {
+            "functions": [{
+                "return_type": "Int",
+                "name": "main",
+                "block_items": [{
+                    "kind": { "Return": { "kind": { "Const": [7, "Int"] }, "span": { "start": 0, "end": 0 } } },
+                    "span": { "start": 0, "end": 0 }
+                }],
+                "params": [],
+                "variadic": false,
+                "span": { "start": 0, "end": 0 }
+            }],
+            "declarations": []
+        }
+    }"#;
+    let mut session = session();
+    let program = session.load_ast_json(json).unwrap();
+    assert!(compile(&mut session, program).contains("main:"));
+}
+
+#[test]
+fn rejects_other_versions() {
+    let json = r#"{ "version": 2, "program": { "functions": [], "declarations": [] } }"#;
+    let error = session().load_ast_json(json).unwrap_err();
+    assert!(matches!(error, CompilerError::InvalidAstJson(_)), "{error}");
+}
+
+// the diagnostics of a program returning the undeclared `x`, whose span is `span`
+fn undeclared(source: &str, span: &str) -> String {
+    let json = format!(
+        r#"{{
+            "version": 1,
+            {source}
+            "program": {{
+                "functions": [{{
+                    "return_type": "Int",
+                    "name": "main",
+                    "block_items": [{{
+                        "kind": {{ "Return": {{ "kind": {{ "Var": "x" }}, "span": {span} }} }},
+                        "span": {span}
+                    }}],
+                    "params": [],
+                    "variadic": false,
+                    "span": {span}
+                }}],
+                "declarations": []
+            }}
+        }}"#
+    );
+    let mut session = session();
+    let mut program = session.load_ast_json(&json).unwrap();
+    session.analyze(&mut program);
+    let codes: Vec<_> = session.diagnostics().iter().map(|d| d.code).collect();
+    assert_eq!(codes, ["E0002"]);
+    session.source_map().render(session.diagnostics())
+}
+
+#[test]
+fn spans_past_the_source_point_at_its_end() {
+    let source = r#""source": { "name": "main.c", "text": "int main(void) {\n    return x;\n}" },"#;
+    let rendered = undeclared(source, r#"{ "start": 500, "end": 400 }"#);
+    assert!(rendered.contains("--> main.c:3:2"), "{rendered}");
+    assert!(rendered.contains("3 | }"), "{rendered}");
+}
+
+#[test]
+fn diagnostics_without_source_have_no_snippet() {
+    let rendered = undeclared("", r#"{ "start": 31, "end": 32 }"#);
+    assert!(rendered.starts_with("error[E0002]"), "{rendered}");
+    assert!(!rendered.contains("-->"), "{rendered}");
+}
+
+#[test]
+fn typedefs_of_a_body_end_with_it() {
+    // `int f(void) { typedef int T; return 0; } int g(T x) { return x; }`, which the parser
+    // would reject already
+    let json = r#"{
+        "version": 1,
+        "program": {
+            "functions": [{
+                "return_type": "Int",
+                "name": "f",
+                "block_items": [
+                    { "kind": { "Typedef": ["Int", "T"] }, "span": { "start": 0, "end": 0 } },
+                    {
+                        "kind": { "Return": { "kind": { "Const": [0, "Int"] }, "span": { "start": 0, "end": 0 } } },
+                        "span": { "start": 0, "end": 0 }
+                    }
+                ],
+                "params": [],
+                "variadic": false,
+                "span": { "start": 0, "end": 0 }
+            }, {
+                "return_type": "Int",
+                "name": "g",
+                "block_items": [{
+                    "kind": { "Return": { "kind": { "Var": "x" }, "span": { "start": 0, "end": 0 } } },
+                    "span": { "start": 0, "end": 0 }
+                }],
+                "params": [{ "param_type": { "Typedef": "T" }, "param_name": "x", "span": { "start": 0, "end": 0 } }],
+                "variadic": false,
+                "span": { "start": 0, "end": 0 }
+            }],
+            "declarations": []
+        }
+    }"#;
+    let mut session = session();
+    let mut program = session.load_ast_json(json).unwrap();
+    session.analyze(&mut program);
+    let codes: Vec<_> = session.diagnostics().iter().map(|d| d.code).collect();
+    assert_eq!(codes, ["E0025"]);
+}
+
+// a document of `int main(void)` with the statements `block_items`
+fn main_with(block_items: &str) -> String {
+    format!(
+        r#"{{
+            "version": 1,
+            "program": {{
+                "functions": [{{
+                    "return_type": "Int",
+                    "name": "main",
+                    "block_items": [{block_items}],
+                    "params": [],
+                    "variadic": false,
+                    "span": {{ "start": 0, "end": 0 }}
+                }}],
+                "declarations": []
+            }}
+        }}"#
+    )
+}
+
+#[test]
+fn rejects_conversions() {
+    let json = main_with(
+        r#"{
+            "kind": { "Return": {
+                "kind": { "Convert": { "kind": { "Const": [7, "Int"] }, "span": { "start": 7, "end": 8 } } },
+                "span": { "start": 7, "end": 8 }
+            } },
+            "span": { "start": 0, "end": 9 }
+        }"#,
+    );
+    let error = session().load_ast_json(&json).unwrap_err();
+    assert!(matches!(error, CompilerError::InvalidAstJson(_)), "{error}");
+    assert!(
+        error.to_string().contains("`Convert` expression at 7..8"),
+        "{error}"
+    );
+}
+
+#[test]
+fn rejects_syntax_errors() {
+    let json = main_with(
+        r#"{
+            "kind": { "If": [
+                { "kind": { "Const": [1, "Int"] }, "span": { "start": 4, "end": 5 } },
+                { "kind": "Error", "span": { "start": 7, "end": 12 } },
+                null
+            ] },
+            "span": { "start": 0, "end": 12 }
+        }"#,
+    );
+    let error = session().load_ast_json(&json).unwrap_err();
+    assert!(matches!(error, CompilerError::InvalidAstJson(_)), "{error}");
+    assert!(
+        error.to_string().contains("`Error` statement at 7..12"),
+        "{error}"
+    );
+}
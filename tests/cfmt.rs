@@ -0,0 +1,141 @@
+//! `cfmt` lays out C source by its structure, keeping comments, directives and every token,
+//! and formatting its output again changes nothing.
+
+use std::process::Command;
+
+use compiler::format::{self, BraceStyle, Style};
+
+const MESSY: &str = r#"#include <stdio.h>
+#define MAX(a, b) ((a) > (b) ? \
+    (a) : (b))
+
+/* A point
+ * in the plane */
+struct point{int x;int y; // the y
+};
+enum color {RED,GREEN=4, /* green */ BLUE};
+
+
+int main(void)
+{
+  struct point p={1,2};  size_t n=sizeof(struct point);
+  int x=-p.x+ - -p.y*(int)n;
+  x+=0x10;
+  if(x>1)if(x>2)x=1;else x=2;
+  if (x) { x = 3; } else if (x==1) x=4; else {x=5;}
+  switch(x){case 1:case 2:break;case 3:{x=1;break;}default:goto done;}
+done:
+  printf("%d\n",x); // print
+  // the end
+  return x?*&x:(int)'a';
+}
+"#;
+
+const FORMATTED: &str = r#"#include <stdio.h>
+#define MAX(a, b) ((a) > (b) ? \
+    (a) : (b))
+
+/* A point
+ * in the plane */
+struct point {
+    int x;
+    int y; // the y
+};
+enum color {
+    RED,
+    GREEN = 4, /* green */
+    BLUE
+};
+
+int main(void) {
+    struct point p = {1, 2};
+    size_t n = sizeof(struct point);
+    int x = -p.x + - -p.y * (int)n;
+    x += 0x10;
+    if (x > 1)
+        if (x > 2)
+            x = 1;
+        else
+            x = 2;
+    if (x) {
+        x = 3;
+    } else if (x == 1)
+        x = 4;
+    else {
+        x = 5;
+    }
+    switch (x) {
+    case 1:
+    case 2:
+        break;
+    case 3: {
+        x = 1;
+        break;
+    }
+    default:
+        goto done;
+    }
+done:
+    printf("%d\n", x); // print
+    // the end
+    return x ? *&x : (int)'a';
+}
+"#;
+
+fn style(braces: BraceStyle, indent_width: usize) -> Style {
+    Style {
+        indent_width,
+        braces,
+    }
+}
+
+#[test]
+fn formats_c() {
+    let typedefs = ["size_t".to_string()];
+    let formatted = format::format(MESSY, &Style::default(), &typedefs).unwrap();
+    assert_eq!(formatted, FORMATTED);
+}
+
+#[test]
+fn brace_styles() {
+    let source = "int f(int a) { if (a) { return 1; } else { return 2; } }";
+    let linux = "int f(int a)\n{\n  if (a) {\n    return 1;\n  } else {\n    return 2;\n  }\n}\n";
+    let allman =
+        "int f(int a)\n{\n  if (a)\n  {\n    return 1;\n  }\n  else\n  {\n    return 2;\n  }\n}\n";
+    let formatted = format::format(source, &style(BraceStyle::Linux, 2), &[]).unwrap();
+    assert_eq!(formatted, linux);
+    let formatted = format::format(source, &style(BraceStyle::Allman, 2), &[]).unwrap();
+    assert_eq!(formatted, allman);
+}
+
+#[test]
+fn idempotent() {
+    for braces in [BraceStyle::Attach, BraceStyle::Linux, BraceStyle::Allman] {
+        let style = style(braces, 3);
+        let once = format::format(MESSY, &style, &[]).unwrap();
+        let twice = format::format(&once, &style, &[]).unwrap();
+        assert_eq!(once, twice, "{braces:?}");
+    }
+}
+
+#[test]
+fn check_then_format_in_place() {
+    let dir = std::env::temp_dir().join(format!("compiler-cfmt-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("messy.c");
+    std::fs::write(&path, MESSY).unwrap();
+    let cfmt = || Command::new(env!("CARGO_BIN_EXE_cfmt"));
+
+    let output = cfmt().arg("--check").arg(&path).output().unwrap();
+    assert!(
+        !output.status.success(),
+        "--check passed an unformatted file"
+    );
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), MESSY);
+
+    // `size_t` comes from <stdio.h>
+    assert!(cfmt().arg(&path).status().unwrap().success());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), FORMATTED);
+    assert!(cfmt().arg("--check").arg(&path).status().unwrap().success());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,589 @@
+//! The assembly emitted for constructs whose lowering is easy to get wrong, checked through the
+//! library so no assembler is needed.
+
+use compiler::{Options, Output};
+
+fn compile(source: &str, target: &str) -> String {
+    let options = Options {
+        target: target.parse().unwrap(),
+        ..Options::default()
+    };
+    match compiler::compile(source, &options) {
+        Ok(Output { asm, .. }) => asm,
+        Err(diagnostics) => panic!("{diagnostics:?} compiling\n{source}"),
+    }
+}
+
+// the lines of `asm` that are exactly `line`, apart from indentation
+fn count(asm: &str, line: &str) -> usize {
+    asm.lines().filter(|l| l.trim() == line).count()
+}
+
+#[test]
+fn compound_assignment_evaluates_its_target_once() {
+    let source = r#"
+        int g(int *calls) { *calls = *calls + 1; return 1; }
+        int main(void) {
+            int a[3] = {1, 2, 3};
+            int calls = 0;
+            a[g(&calls)] += 10;
+            return calls;
+        }
+    "#;
+    let asm = compile(source, "linux");
+    assert_eq!(count(&asm, "bl g"), 1, "{asm}");
+}
+
+// the labels the entries of the jump tables in `asm` point at, in order
+fn jump_table(asm: &str) -> Vec<&str> {
+    asm.lines()
+        .filter_map(|line| line.trim().strip_prefix(".word "))
+        .filter_map(|entry| entry.split(" - ").next())
+        .collect()
+}
+
+fn switch(cases: &str) -> String {
+    let source = format!("int f(int x) {{ switch (x) {{ {cases} default: return 99; }} }}");
+    compile(&source, "linux")
+}
+
+#[test]
+fn dense_switch_uses_a_jump_table() {
+    let asm = switch("case 1: return 1; case 2: return 2; case 3: return 3; case 5: return 5;");
+    assert_eq!(count(&asm, "br x1"), 1, "{asm}");
+    assert!(asm.contains("\tadr x1, switch_table"), "{asm}");
+    // one entry for each value from 1 to 5, the missing 4 goes to the default
+    let table = jump_table(&asm);
+    assert_eq!(table.len(), 5, "{asm}");
+    assert!(table[3].starts_with("switch_default"), "{asm}");
+    assert!(!asm.contains("b.eq"), "{asm}");
+}
+
+#[test]
+fn sparse_switch_compares_each_case() {
+    let asm =
+        switch("case 1: return 1; case 10: return 2; case 100: return 3; case 1000: return 4;");
+    assert!(jump_table(&asm).is_empty(), "{asm}");
+    assert_eq!(
+        asm.lines().filter(|l| l.contains("b.eq")).count(),
+        4,
+        "{asm}"
+    );
+}
+
+#[test]
+fn jump_table_starts_at_a_negative_case() {
+    let asm = switch("case -2: return 1; case -1: return 2; case 0: return 3; case 2: return 4;");
+    let table = jump_table(&asm);
+    assert_eq!(table.len(), 5, "{asm}");
+    assert!(table[3].starts_with("switch_default"), "{asm}");
+    // the value is rebased by the smallest case, -2, whose bits take all four moves
+    assert!(asm.contains("\tmovz x1, #65534\n"), "{asm}");
+    assert_eq!(count(&asm, "movk x1, #65535, lsl #48"), 1, "{asm}");
+    assert!(asm.contains("\tsub x0, x0, x1\n\tmov x1, #4\n\tcmp x0, x1\n\tb.hi switch_default"));
+}
+
+// the instructions of `function`, up to the next function or data
+fn body<'a>(asm: &'a str, function: &str) -> &'a str {
+    let start = asm
+        .find(&format!("\n{function}:\n"))
+        .expect("function is emitted")
+        + 1;
+    let end = asm[start..]
+        .find(".global")
+        .or_else(|| asm[start..].find(".section"))
+        .map_or(asm.len(), |end| start + end);
+    &asm[start..end]
+}
+
+#[test]
+fn functions_return_after_a_label_past_their_return() {
+    let source = r#"
+        int f(int x) { if (x) goto done; return 1; done: x = 2; }
+        int g(int x) { switch (x) { case 1: return 5; default: x = 3; } }
+        int main(void) { return f(0) + g(1); }
+    "#;
+    let asm = compile(source, "linux");
+    for function in ["f", "g"] {
+        let last = body(&asm, function).lines().last().unwrap();
+        assert_eq!(last.trim(), "ret", "{function} falls through in\n{asm}");
+    }
+}
+
+// a variadic function taking one argument of type `ty` from its va_list
+fn va_arg(ty: &str, target: &str) -> String {
+    let source = format!(
+        r#"
+        struct two {{ long a; long b; }};
+        struct big {{ long a; long b; long c; }};
+        struct hfa {{ double x; double y; }};
+        void f(int n, ...) {{
+            __builtin_va_list ap;
+            __builtin_va_start(ap, n);
+            {ty} x = __builtin_va_arg(ap, {ty});
+            __builtin_va_end(ap);
+        }}
+        "#
+    );
+    let source = match target {
+        "linux" => source.replace(
+            "__builtin_va_list",
+            "struct __va_list { void *stack; void *gr_top; void *vr_top; int gr_offs; int vr_offs; }",
+        ),
+        _ => source.replace("__builtin_va_list", "char *"),
+    };
+    compile(&source, target)
+}
+
+#[test]
+fn va_start_points_past_the_named_parameters() {
+    let asm = va_arg("int", "linux");
+    // n took x0, so 7 general purpose registers and all 8 FP ones are left
+    assert!(
+        asm.contains("\tmov w1, #-56\n\tstr w1, [x0, #24]\n"),
+        "{asm}"
+    );
+    assert!(
+        asm.contains("\tmov w1, #-128\n\tstr w1, [x0, #28]\n"),
+        "{asm}"
+    );
+    let asm = va_arg("int", "macos");
+    assert!(asm.contains("\tadd x1, fp, #16\n\tstr x1, [x0]\n"), "{asm}");
+}
+
+#[test]
+fn va_arg_takes_saved_registers_then_the_stack_on_linux() {
+    let asm = va_arg("int", "linux");
+    assert!(asm.contains("\tldrsw x2, [x1, #24]\n"), "{asm}");
+    assert!(asm.contains("\tadd w3, w2, #8\n\tstr w3, [x1, #24]\n\tldr x0, [x1, #8]\n"));
+    assert!(asm.contains("\tldr x0, [x1]\n\tadd x3, x0, #8\n\tstr x3, [x1]\n"));
+    assert_eq!(count(&asm, "ldrsw x0, [x0]"), 1, "{asm}");
+
+    let asm = va_arg("double", "linux");
+    assert!(asm.contains("\tldrsw x2, [x1, #28]\n"), "{asm}");
+    assert!(asm.contains("\tadd w3, w2, #16\n\tstr w3, [x1, #28]\n\tldr x0, [x1, #16]\n"));
+    assert_eq!(count(&asm, "ldr x0, [x0]"), 1, "{asm}");
+}
+
+#[test]
+fn va_arg_of_structs_on_linux() {
+    // two registers, or the stack if just one is left
+    let asm = va_arg("struct two", "linux");
+    assert!(asm.contains("\tadd w3, w2, #16\n\tstr w3, [x1, #24]\n\tcmp w3, #0\n\tb.gt va_stack"));
+    assert!(asm.contains("\tadd x3, x0, #16\n"), "{asm}");
+    // by the address of a copy
+    let asm = va_arg("struct big", "linux");
+    assert!(
+        asm.contains("\tadd w3, w2, #8\n\tstr w3, [x1, #24]\n"),
+        "{asm}"
+    );
+    assert_eq!(count(&asm, "ldr x0, [x0]"), 1, "{asm}");
+    // the members are gathered from two FP registers saved 16 bytes apart
+    let asm = va_arg("struct hfa", "linux");
+    assert!(
+        asm.contains("\tadd w3, w2, #32\n\tstr w3, [x1, #28]\n"),
+        "{asm}"
+    );
+    assert!(
+        asm.contains("\tldr x3, [x0, #0]\n\tstr x3, [x4, #0]\n"),
+        "{asm}"
+    );
+    assert!(
+        asm.contains("\tldr x3, [x0, #16]\n\tstr x3, [x4, #8]\n"),
+        "{asm}"
+    );
+}
+
+#[test]
+fn va_arg_takes_the_stack_on_macos() {
+    for (ty, size) in [
+        ("int", 8),
+        ("double", 8),
+        ("struct two", 16),
+        ("struct hfa", 16),
+        ("struct big", 8),
+    ] {
+        let asm = va_arg(ty, "macos");
+        let step = format!("\tldr x0, [x1]\n\tadd x3, x0, #{size}\n\tstr x3, [x1]\n");
+        assert!(asm.contains(&step), "{ty} in\n{asm}");
+        assert!(!asm.contains("ldrsw x2"), "{ty} in\n{asm}");
+    }
+    assert_eq!(count(&va_arg("struct big", "macos"), "ldr x0, [x0]"), 1);
+    assert_eq!(count(&va_arg("int", "macos"), "ldrsw x0, [x0]"), 1);
+}
+
+#[test]
+fn arrays_are_indexed_by_their_element_size() {
+    let source = r#"
+        int f(int i) {
+            int a[4] = {1, 2};
+            int m[2][3];
+            m[1][2] = 7;
+            return a[i] + m[1][2];
+        }
+    "#;
+    let asm = compile(source, "linux");
+    // a row of `m` is 12 bytes, an element 4
+    assert_eq!(count(&asm, "mov x2, #12"), 2, "{asm}");
+    assert_eq!(count(&asm, "mov x2, #4"), 3, "{asm}");
+    assert_eq!(count(&asm, "mul x1, x1, x2"), 5, "{asm}");
+    // the 16 bytes of `a` are zeroed, then the two initializers stored
+    assert!(asm.contains("\tsub x9, fp, #20\n\tmov x10, #16\n"), "{asm}");
+    assert_eq!(count(&asm, "strb wzr, [x9], #1"), 1, "{asm}");
+    assert_eq!(count(&asm, "str w0, [fp, #-20]"), 1, "{asm}");
+    assert_eq!(count(&asm, "str w0, [fp, #-16]"), 1, "{asm}");
+}
+
+#[test]
+fn chars_load_by_their_signedness() {
+    let source = r#"
+        int f(char *s) {
+            char c = 'A';
+            unsigned char u = 200;
+            signed char n = -1;
+            return s[1] + c + u + n;
+        }
+    "#;
+    let asm = compile(source, "linux");
+    assert!(asm.contains("\tmov x0, #65\n"), "{asm}");
+    assert_eq!(count(&asm, "strb w0, [fp, #-9]"), 1, "{asm}");
+    // plain `char` is signed, as on Apple arm64
+    assert_eq!(count(&asm, "ldrsb x0, [x0]"), 1, "{asm}");
+    assert_eq!(count(&asm, "ldrsb x0, [fp, #-9]"), 1, "{asm}");
+    assert_eq!(count(&asm, "ldrb w0, [fp, #-10]"), 1, "{asm}");
+    assert_eq!(count(&asm, "ldrsb x0, [fp, #-11]"), 1, "{asm}");
+    assert_eq!(count(&asm, "uxtb w0, w0"), 1, "{asm}");
+}
+
+#[test]
+fn string_literals_are_read_only_data() {
+    let source = r#"int f(char *s); int main(void) { return f("hi\n"); }"#;
+    let asm = compile(source, "linux");
+    assert!(asm.contains("\tadrp x0, .Lstr_0\n\tadd x0, x0, :lo12:.Lstr_0\n"));
+    assert!(
+        asm.contains(".section .rodata\n.Lstr_0:\n\t.asciz \"hi\\n\"\n"),
+        "{asm}"
+    );
+    let asm = compile(source, "macos");
+    assert!(asm.contains("\tadrp x0, Lstr_0@PAGE\n\tadd x0, x0, Lstr_0@PAGEOFF\n"));
+    assert!(
+        asm.contains(".section __TEXT,__const\nLstr_0:\n\t.asciz \"hi\\n\"\n"),
+        "{asm}"
+    );
+}
+
+#[test]
+fn unsigned_operands_divide_and_compare_unsigned() {
+    let source = r#"
+        unsigned f(unsigned a, unsigned b) { return a / b; }
+        int g(int a, int b) { return a / b; }
+        int h(unsigned a, unsigned b) { return a < b; }
+        int i(int a, int b) { return a > b; }
+        int m(int a, unsigned b) { return a <= b; }
+        int n(unsigned long a, long b) { return a > b; }
+    "#;
+    let asm = compile(source, "linux");
+    assert_eq!(count(body(&asm, "f"), "udiv x0, x0, x1"), 1, "{asm}");
+    assert_eq!(count(body(&asm, "g"), "sdiv x0, x0, x1"), 1, "{asm}");
+    for (function, condition) in [("h", "LO"), ("i", "GT"), ("m", "LS"), ("n", "HI")] {
+        let set = format!("cset x0, {condition}");
+        assert_eq!(count(body(&asm, function), &set), 1, "{function} in\n{asm}");
+    }
+}
+
+// the statements of `source` after semantic analysis, with the conversions it inserted
+fn ir(source: &str) -> String {
+    let mut session = compiler::Session::new(Options {
+        target: "linux".parse().unwrap(),
+        ..Options::default()
+    });
+    session.preprocess("test.c", source).unwrap();
+    let mut program = session.parse().unwrap();
+    session.analyze(&mut program);
+    assert!(!session.has_errors(), "{:?}", session.diagnostics());
+    compiler::pretty_print::render(&program)
+}
+
+#[test]
+fn usual_arithmetic_conversions() {
+    let source = r#"
+        long j(int a) { return a; }
+        short l(long a) { return a; }
+        int m(int a, unsigned b) { return a < b; }
+        long n(long a, unsigned b) { return a + b; }
+        unsigned long o(unsigned a, long long b) { return a * b; }
+        int p(char a, short b) { return a - b; }
+    "#;
+    let ir = ir(source);
+    let returns: Vec<_> = ir
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix("return "))
+        .collect();
+    assert_eq!(
+        returns,
+        [
+            "(long)a;",
+            "(short)a;",
+            "((unsigned int)a < b);",
+            "(a + (long)b);",
+            "(unsigned long)((long long)a * b);",
+            "((int)a - (int)b);",
+        ]
+    );
+    // the conversions sign or zero extend by the type converted from, and truncate
+    let asm = compile(source, "linux");
+    assert_eq!(count(body(&asm, "j"), "ldrsw x0, [fp, #-4]"), 1, "{asm}");
+    assert_eq!(count(body(&asm, "l"), "sxth x0, w0"), 1, "{asm}");
+    assert_eq!(count(body(&asm, "m"), "mov w0, w0"), 1, "{asm}");
+    assert_eq!(count(body(&asm, "n"), "ldr w0, [fp, #-12]"), 1, "{asm}");
+}
+
+#[test]
+fn floating_point_arguments_take_v_registers() {
+    let source = r#"
+        double f(double a, float b, int c, double d, double e, double g, double h, double i,
+                 double j) { return a + b + c + j; }
+        float s(float x) { return x; }
+        int main(void) { return f(1.0, 2.0f, 3, 4, 5, 6, 7, 8, 9.5) + s(1.5f); }
+    "#;
+    let asm = compile(source, "linux");
+    // the int takes x0 whatever comes before it, the FP arguments v0 to v7 in order
+    let saves = "\tstr d0, [fp, #-8]\n\tstr s1, [fp, #-12]\n\tstr w0, [fp, #-16]\n\
+                 \tstr d2, [fp, #-24]\n\tstr d3, [fp, #-32]\n\tstr d4, [fp, #-40]\n\
+                 \tstr d5, [fp, #-48]\n\tstr d6, [fp, #-56]\n\tstr d7, [fp, #-64]\n";
+    assert!(body(&asm, "f").contains(saves), "{asm}");
+    let loads = "\tldr d0, [sp, #128]\n\tldr s1, [sp, #112]\n\tldr x0, [sp, #96]\n\
+                 \tldr d2, [sp, #80]\n";
+    assert!(body(&asm, "main").contains(loads), "{asm}");
+    // results come back in d0 and s0
+    let epilogue = "\tmov sp, fp\n\tldp fp, lr, [sp], #16\n\tret\n";
+    assert!(body(&asm, "f").ends_with(&format!("\tfmov d0, x0\n{epilogue}")));
+    assert!(body(&asm, "s").ends_with(&format!("\tfmov s0, w0\n{epilogue}")));
+    assert!(body(&asm, "main").contains("\tbl s\n\tadd sp, sp, #16\n\tfmov w0, s0\n"));
+}
+
+#[test]
+fn conversions_between_integers_and_floating_point() {
+    let source = r#"
+        double widen(float x) { return x; }
+        float narrow(double x) { return x; }
+        int t(double x) { return x; }
+        unsigned u(double x) { return x; }
+        double v(long x) { return x; }
+        double w(unsigned long x) { return x; }
+    "#;
+    let asm = compile(source, "linux");
+    for (function, instruction) in [
+        ("widen", "fcvt d0, s0"),
+        ("narrow", "fcvt s0, d0"),
+        ("t", "fcvtzs x0, d0"),
+        ("u", "fcvtzu x0, d0"),
+        ("v", "scvtf d0, x0"),
+        ("w", "ucvtf d0, x0"),
+    ] {
+        assert_eq!(
+            count(body(&asm, function), instruction),
+            1,
+            "{function} in\n{asm}"
+        );
+    }
+}
+
+#[test]
+fn floating_point_comparisons_are_false_when_unordered() {
+    let source = r#"
+        int lt(double a, double b) { return a < b; }
+        int le(double a, double b) { return a <= b; }
+        int gt(double a, double b) { return a > b; }
+        int ge(double a, double b) { return a >= b; }
+        int eq(double a, double b) { return a == b; }
+        int ne(double a, double b) { return a != b; }
+    "#;
+    let asm = compile(source, "linux");
+    // a NaN sets C and V, which `lt` and `le` would take for less
+    for (function, condition) in [
+        ("lt", "MI"),
+        ("le", "LS"),
+        ("gt", "GT"),
+        ("ge", "GE"),
+        ("eq", "EQ"),
+        ("ne", "NE"),
+    ] {
+        let function_body = body(&asm, function);
+        assert_eq!(
+            count(function_body, "fcmp d0, d1"),
+            1,
+            "{function} in\n{asm}"
+        );
+        let set = format!("cset x0, {condition}");
+        assert_eq!(count(function_body, &set), 1, "{function} in\n{asm}");
+    }
+}
+
+#[test]
+fn casts_truncate_and_extend() {
+    let source = r#"
+        int f(long x) { return (int)x + (char)x; }
+        int *g(long x) { return (int *)x; }
+        long h(int *p) { return (long)p; }
+        int i(double d) { return (unsigned char)d; }
+        void j(int x) { (void)x; }
+    "#;
+    let asm = compile(source, "linux");
+    assert_eq!(count(body(&asm, "f"), "sxtb x0, w0"), 1, "{asm}");
+    assert_eq!(count(body(&asm, "i"), "fcvtzu x0, d0"), 1, "{asm}");
+    assert_eq!(count(body(&asm, "i"), "uxtb w0, w0"), 1, "{asm}");
+    // a pointer and a `long` have the same bits
+    for function in ["g", "h"] {
+        let function_body = body(&asm, function);
+        assert!(!function_body.contains("xt"), "{function} in\n{asm}");
+    }
+    let ir = ir(source);
+    assert!(ir.contains("return ((int)x + (int)(char)x);"), "{ir}");
+    assert!(ir.contains("return (int)(unsigned char)d;"), "{ir}");
+    assert!(ir.contains("(void)x;"), "{ir}");
+}
+
+#[test]
+fn structs_are_laid_out_with_padding() {
+    let source = r#"
+        struct pad { char c; int i; char d; };
+        struct mixed { char c; double d; short s; };
+        union u { char c[5]; int i; };
+        int a(void) { return sizeof(struct pad); }
+        int b(void) { return _Alignof(struct mixed); }
+        int c(void) { return sizeof(struct mixed); }
+        int d(void) { return sizeof(union u); }
+        int e(struct mixed *m) { return m->s; }
+    "#;
+    let ir = ir(source);
+    let returns: Vec<_> = ir
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix("return "))
+        .collect();
+    // a member starts at a multiple of its alignment, the size is one of the largest
+    assert_eq!(
+        returns,
+        [
+            "(int)12UL;",
+            "(int)8UL;",
+            "(int)24UL;",
+            "(int)8UL;",
+            "(int)m->s;"
+        ]
+    );
+    let asm = compile(source, "linux");
+    // `s` is at 16, after the 8 bytes of `d` aligned to 8
+    let e = body(&asm, "e");
+    assert_eq!(count(e, "mov x1, #16"), 1, "{asm}");
+    assert_eq!(count(e, "ldrsh x0, [x0]"), 1, "{asm}");
+}
+
+// functions taking and returning each class of struct, and a caller of all of them
+const STRUCT_PASSING: &str = r#"
+    struct small { int a; long b; };
+    struct hfa { float x; float y; float z; };
+    struct big { long a; long b; long c; };
+    long take_small(struct small s) { return s.b; }
+    float take_hfa(struct hfa h) { return h.z; }
+    long take_big(struct big b) { return b.c; }
+    struct small ret_small(void) { struct small s = {1, 2}; return s; }
+    struct hfa ret_hfa(void) { struct hfa h = {1, 2, 3}; return h; }
+    struct big ret_big(void) { struct big b = {1, 2, 3}; return b; }
+    long caller(void) {
+        struct small s = {1, 2};
+        struct hfa h = {1, 2, 3};
+        struct big b = {1, 2, 3};
+        take_small(s);
+        take_hfa(h);
+        take_big(b);
+        return ret_small().a + ret_hfa().x + ret_big().c;
+    }
+"#;
+
+#[test]
+fn small_structs_pass_in_general_purpose_registers() {
+    let asm = compile(STRUCT_PASSING, "linux");
+    let spill = "\tsub x9, fp, #16\n\tstr x0, [x9, #0]\n\tstr x1, [x9, #8]\n";
+    assert!(body(&asm, "take_small").contains(spill), "{asm}");
+    let load = "\tldr x0, [x9, #0]\n\tldr x1, [x9, #8]\n";
+    assert!(body(&asm, "caller").contains(&format!("{load}\tbl take_small\n")));
+    assert!(body(&asm, "ret_small").contains(&format!("\tmov x9, x0\n{load}")));
+    assert!(body(&asm, "caller")
+        .contains("\tbl ret_small\n\tsub x9, fp, #96\n\tstr x0, [x9, #0]\n\tstr x1, [x9, #8]\n"));
+}
+
+#[test]
+fn homogeneous_float_aggregates_pass_in_fp_registers() {
+    let asm = compile(STRUCT_PASSING, "linux");
+    let members = ["s0, [x9, #0]", "s1, [x9, #4]", "s2, [x9, #8]"];
+    for member in members {
+        let store = format!("str {member}");
+        assert_eq!(count(body(&asm, "take_hfa"), &store), 1, "{asm}");
+        let load = format!("ldr {member}");
+        assert_eq!(count(body(&asm, "caller"), &load), 1, "{asm}");
+    }
+    assert!(body(&asm, "caller").contains("\tldr s2, [x9, #8]\n\tbl take_hfa\n"));
+    let returned = "\tldr s0, [x0, #0]\n\tldr s1, [x0, #4]\n\tldr s2, [x0, #8]\n";
+    assert!(body(&asm, "ret_hfa").contains(returned), "{asm}");
+    assert!(body(&asm, "caller").contains("\tbl ret_hfa\n\tsub x9, fp, #108\n\tstr s0, [x9, #0]\n"));
+}
+
+#[test]
+fn large_structs_pass_by_reference() {
+    let asm = compile(STRUCT_PASSING, "linux");
+    // the callee copies from the address in x0, the caller passes the address of its own copy
+    let copy = "\tldr x12, [x0], #8\n\tstr x12, [x9], #8\n";
+    assert_eq!(body(&asm, "take_big").matches(copy).count(), 3, "{asm}");
+    let caller = body(&asm, "caller");
+    assert!(caller
+        .contains("\tsub x1, fp, #80\n\tstr x1, [sp, #0]\n\tldr x0, [sp, #0]\n\tbl take_big\n"));
+    // a large result goes to the memory x8 points at
+    assert!(
+        body(&asm, "ret_big").contains("\tstr x8, [fp, #-8]\n"),
+        "{asm}"
+    );
+    assert!(body(&asm, "ret_big").contains("\tldr x1, [fp, #-8]\n\tldr x12, [x0], #8\n"));
+    assert!(
+        caller.contains("\tsub x8, fp, #136\n\tbl ret_big\n\tsub x9, fp, #136\n"),
+        "{asm}"
+    );
+}
+
+#[test]
+fn sizeof_and_alignof_are_constants() {
+    let source = r#"
+        typedef struct { char c; long l; } S;
+        int calls(void);
+        long f(void) {
+            int a[3];
+            S s;
+            return sizeof a + sizeof(S) + _Alignof(S) + sizeof s.c + sizeof calls() + _Alignof(short);
+        }
+    "#;
+    let ir = ir(source);
+    assert!(
+        ir.contains("return (long)(((((12UL + 16UL) + 8UL) + 1UL) + 4UL) + 2UL);"),
+        "{ir}"
+    );
+    // the operand of `sizeof` is never evaluated
+    let asm = compile(source, "linux");
+    assert!(!asm.contains("bl calls"), "{asm}");
+}
+
+#[test]
+fn comma_operands_are_all_evaluated() {
+    let source = r#"
+        int g(void);
+        int f(int x) { ; ; if (x) ; return (g(), x = 3, 4); }
+    "#;
+    let asm = compile(source, "linux");
+    let f = body(&asm, "f");
+    assert_eq!(count(f, "bl g"), 1, "{asm}");
+    assert_eq!(count(f, "mov x0, #3"), 1, "{asm}");
+    assert_eq!(count(f, "str w0, [x1]"), 1, "{asm}");
+    // the value is that of the last operand
+    assert!(f.contains("\tmov x0, #4\n"), "{asm}");
+    let ir = ir(source);
+    assert!(ir.contains("return (((g(), x = 3), 4));"), "{ir}");
+}
@@ -0,0 +1,145 @@
+//! Programs the compiler rejects are reported with diagnostics rather than crashing it, and
+//! the programs it accepts compile without any.
+
+use compiler::{Options, Session};
+
+fn linux() -> Options {
+    Options {
+        target: "linux".parse().unwrap(),
+        ..Options::default()
+    }
+}
+
+// the codes of the errors compiling `source` reports
+fn errors(source: &str) -> Vec<&'static str> {
+    match compiler::compile(source, &linux()) {
+        Ok(_) => Vec::new(),
+        Err(diagnostics) => diagnostics
+            .iter()
+            .filter(|d| d.severity == compiler::diagnostics::Severity::Error)
+            .map(|d| d.code)
+            .collect(),
+    }
+}
+
+#[test]
+fn casts_to_structs_are_not_integer_constants() {
+    let source = r#"
+        struct s { int a; };
+        enum e { A = (struct s)1, B = (enum e)1 + 1 };
+        int main(void) {
+            switch (1) { case (struct s)1: ; case (enum e)3: ; }
+            return B;
+        }
+    "#;
+    // the invalid cast, then the constant it does not make
+    assert_eq!(errors(source), ["E0018", "E0024", "E0018", "E0024"]);
+}
+
+#[test]
+fn enumerators_outlive_the_enum_body() {
+    let source = r#"
+        typedef int T;
+        int main(void) {
+            enum { T = 3 };
+            return T * 2;
+        }
+    "#;
+    assert_eq!(errors(source), Vec::<&str>::new());
+}
+
+// the codes of everything parsing and analyzing `source` reports, with the lines they are on
+fn located(source: &str) -> Vec<(&'static str, usize)> {
+    let mut session = Session::new(linux());
+    session.preprocess("test.c", source).unwrap();
+    if let Some(mut program) = session.parse() {
+        session.analyze(&mut program);
+    }
+    let source_map = session.source_map();
+    session
+        .diagnostics()
+        .iter()
+        .map(|d| (d.code, source_map.locate(d.span.start).1))
+        .collect()
+}
+
+#[test]
+fn reports_every_error_of_a_file() {
+    let source = "int f(void) { return x; }\n\
+                  int g(int a) { a = f(1); return *a; }\n\
+                  int main(void) { return y + g(1, 2); }\n";
+    let expected = [
+        ("E0002", 1),
+        ("E0017", 2),
+        ("E0009", 2),
+        ("E0002", 3),
+        ("E0017", 3),
+    ];
+    assert_eq!(located(source), expected);
+}
+
+#[test]
+fn parsing_resumes_after_a_bad_statement_or_function() {
+    let source = "int bad(int { return 1; }\n\
+                  int main(void) { int a = 1 +; a = 2 * ; return c; }\n\
+                  int h(void) { return d; }\n";
+    // the rest of a bad function is skipped, the rest of a bad statement's function is not
+    let expected = [
+        ("E0001", 1),
+        ("E0001", 2),
+        ("E0001", 2),
+        ("E0002", 2),
+        ("E0002", 3),
+    ];
+    assert_eq!(located(source), expected);
+}
+
+#[test]
+fn initialization_is_merged_across_branches() {
+    let source = "int f(int c) {\n\
+                  int x;\n\
+                  if (c) x = 1; else x = 2;\n\
+                  int y;\n\
+                  if (c) y = 1;\n\
+                  int z;\n\
+                  if (c) return 0; else z = 3;\n\
+                  int w;\n\
+                  return x + y + z + w;\n\
+                  }\n";
+    // `x` is set on both branches and `z` on the one that goes on, `y` and `w` may not be
+    assert_eq!(located(source), [("W0001", 9), ("W0001", 9)]);
+    let warnings = compiler::compile(source, &linux()).unwrap().diagnostics;
+    let names: Vec<_> = warnings
+        .iter()
+        .map(|w| w.message.split('\'').nth(1))
+        .collect();
+    assert_eq!(names, [Some("y"), Some("w")]);
+}
+
+#[test]
+fn redeclarations_in_the_same_scope() {
+    let source = "int f(int p) {\n\
+                  int a = 1;\n\
+                  { int a = 2; int p = 3; }\n\
+                  int a = 3;\n\
+                  int p = 4;\n\
+                  return a;\n\
+                  }\n";
+    // inner blocks may shadow, the parameters share the scope of the body
+    assert_eq!(located(source), [("E0005", 4), ("E0005", 5)]);
+    let Err(diagnostics) = compiler::compile(source, &linux()) else {
+        panic!("compiled");
+    };
+    let first = diagnostics.iter().next().unwrap();
+    assert_eq!(first.notes.len(), 1, "{first:?}");
+}
+
+#[test]
+fn tags_of_a_body_end_with_it() {
+    let source = r#"
+        int f(void) { struct s { int x; }; return 0; }
+        int g(struct s *p) { return p->x; }
+    "#;
+    // `struct s` in the signature of `g` is a new, incomplete type
+    assert_eq!(errors(source), ["E0019"]);
+}
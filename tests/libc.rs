@@ -0,0 +1,177 @@
+//! Calls into the C library use the symbol names of each target, and programs without one
+//! bring a runtime making the system calls of each target. Linking a compiled program
+//! against the system C library and running it under qemu-aarch64 needs an AArch64 Linux
+//! cross compiler and qemu, so that test only runs with `cargo test -- --ignored`.
+
+use std::process::Command;
+
+use compiler::Options;
+
+const CROSS_CC: &str = "aarch64-linux-gnu-gcc";
+const QEMU: &str = "qemu-aarch64";
+
+const SOURCE: &str = r#"#include <stdio.h>
+
+int main(void) {
+    int x = 42;
+    printf("%d\n", x);
+    printf("%d %.1f %s\n", -7, 2.5f, "hello");
+    return 3;
+}
+"#;
+
+fn compile(target: &str, freestanding: bool) -> String {
+    let options = Options {
+        target: target.parse().unwrap(),
+        freestanding,
+        ..Options::default()
+    };
+    let source = match freestanding {
+        true => "int main(void) { return 3; }",
+        false => SOURCE,
+    };
+    compiler::compile(source, &options).unwrap().asm
+}
+
+fn has_line(asm: &str, line: &str) -> bool {
+    asm.lines().any(|l| l.trim() == line)
+}
+
+#[test]
+fn linux_symbols() {
+    let asm = compile("linux", false);
+    for line in [".global main", "main:", "bl printf", ".section .rodata"] {
+        assert!(has_line(&asm, line), "no '{line}' in\n{asm}");
+    }
+    assert!(asm.contains("\tadrp x0, .Lstr_0\n\tadd x0, x0, :lo12:.Lstr_0\n"));
+    assert!(!asm.contains("_printf") && !asm.contains("_main"), "{asm}");
+    assert!(has_line(&asm, "ldr x1, [sp, #0]"), "{asm}");
+}
+
+#[test]
+fn macos_symbols() {
+    let asm = compile("macos", false);
+    for line in [
+        ".global _main",
+        "_main:",
+        "bl _printf",
+        ".section __TEXT,__const",
+    ] {
+        assert!(has_line(&asm, line), "no '{line}' in\n{asm}");
+    }
+    assert!(asm.contains("\tadrp x0, Lstr_0@PAGE\n\tadd x0, x0, Lstr_0@PAGEOFF\n"));
+    assert!(!has_line(&asm, "bl printf"), "{asm}");
+    // the variadic arguments of printf go on the stack rather than in x1 and on
+    assert!(has_line(&asm, "str x9, [sp, #0]"), "{asm}");
+    assert!(!has_line(&asm, "ldr x1, [sp, #0]"), "{asm}");
+}
+
+#[test]
+fn freestanding_entry_point() {
+    for (target, main) in [("linux", "bl main"), ("macos", "bl _main")] {
+        let asm = compile(target, true);
+        assert!(has_line(&asm, ".global _start"), "{target}:\n{asm}");
+        assert!(has_line(&asm, main), "{target}:\n{asm}");
+    }
+    assert!(!has_line(&compile("linux", false), ".global _start"));
+}
+
+#[test]
+#[ignore = "needs aarch64 cross toolchain and qemu"]
+fn printf_under_qemu() {
+    let dir = std::env::temp_dir().join(format!("compiler-libc-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (source, asm, exe) = (
+        dir.join("printf.c"),
+        dir.join("printf.s"),
+        dir.join("printf"),
+    );
+    std::fs::write(&source, SOURCE).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_compiler"))
+        .args(["-S", "--target", "linux"])
+        .arg(&source)
+        .arg("-o")
+        .arg(&asm)
+        .status()
+        .unwrap();
+    assert!(status.success(), "compiling failed");
+    // linked statically, so qemu needs no sysroot to find the dynamic loader
+    let status = Command::new(CROSS_CC)
+        .arg("-static")
+        .arg(&asm)
+        .arg("-o")
+        .arg(&exe)
+        .status()
+        .unwrap();
+    assert!(status.success(), "linking failed");
+
+    let output = Command::new(QEMU).arg(&exe).output().unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "42\n-7 2.5 hello\n"
+    );
+    assert_eq!(output.status.code(), Some(3));
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+// the lines of the function `name`, up to the next global symbol or section
+fn function<'a>(asm: &'a str, name: &str) -> Vec<&'a str> {
+    asm.lines()
+        .skip_while(|l| *l != format!("{name}:"))
+        .skip(1)
+        .take_while(|l| {
+            ![".global", ".text", ".data"]
+                .iter()
+                .any(|d| l.starts_with(d))
+        })
+        .map(str::trim)
+        .collect()
+}
+
+#[test]
+fn freestanding_runtime_makes_system_calls() {
+    let asm = compile("linux", true);
+    assert_eq!(
+        function(&asm, "_start"),
+        ["bl main", "mov x8, #93", "svc #0"]
+    );
+    assert_eq!(function(&asm, "exit"), ["mov x8, #93", "svc #0"]);
+    assert_eq!(function(&asm, "write")[..2], ["mov x8, #64", "svc #0"]);
+    // malloc asks for the current break, then moves it
+    let malloc = function(&asm, "malloc");
+    assert_eq!(
+        malloc.iter().filter(|l| **l == "mov x8, #214").count(),
+        2,
+        "{asm}"
+    );
+    for name in ["putchar", "puts", "free", "memcpy", "memset"] {
+        assert!(has_line(&asm, &format!(".global {name}")), "{asm}");
+    }
+
+    let asm = compile("macos", true);
+    assert_eq!(
+        function(&asm, "_start"),
+        ["bl _main", "mov x16, #1", "svc #0x80"]
+    );
+    assert_eq!(function(&asm, "_write")[..2], ["mov x16, #4", "svc #0x80"]);
+    assert!(
+        function(&asm, "_malloc").contains(&"mov x16, #197"),
+        "{asm}"
+    );
+    assert!(!asm.contains("svc #0\n"), "{asm}");
+
+    // only the file defining `main` brings the runtime along
+    let options = Options {
+        target: "linux".parse().unwrap(),
+        freestanding: true,
+        ..Options::default()
+    };
+    let asm = compiler::compile("int f(void) { return 1; }", &options)
+        .unwrap()
+        .asm;
+    assert!(
+        !has_line(&asm, "_start:") && !has_line(&asm, "exit:"),
+        "{asm}"
+    );
+}
@@ -0,0 +1,285 @@
+//! Printing a program as C and parsing it again gives the same AST, apart from the spans.
+
+use compiler::ast::{
+    BinaryOp, Expr, ExprKind, Func, FuncParam, Initializer, Member, Program, Span, Statement,
+    StatementKind, StructKind, StructType, Type, UnaryOp,
+};
+use compiler::grammar;
+use compiler::lexer::{Lexer, TypedefNames};
+use compiler::printer;
+use proptest::prelude::*;
+
+fn parse(source: &str) -> Program {
+    let mut errors = Vec::new();
+    let names = TypedefNames::new();
+    let program = grammar::ProgramParser::new()
+        .parse(&mut errors, &names, Lexer::new(source, &names))
+        .unwrap_or_else(|e| panic!("{e:?} parsing\n{source}"));
+    assert!(errors.is_empty(), "{errors:?} parsing\n{source}");
+    program
+}
+
+// the AST's debug output, without the spans that differ with the layout
+fn shape(program: &Program) -> String {
+    let mut debug = format!("{program:?}");
+    while let Some(start) = debug.find("Span { start: ") {
+        let end = start + debug[start..].find('}').unwrap() + 1;
+        debug.replace_range(start..end, "Span");
+    }
+    debug
+}
+
+fn assert_round_trip(source: &str) {
+    let program = parse(source);
+    let printed = printer::print_program(&program);
+    let reparsed = parse(&printed);
+    assert_eq!(shape(&program), shape(&reparsed), "printed as\n{printed}");
+}
+
+#[test]
+fn prints_c() {
+    let source = r#"
+        typedef unsigned long size_t;
+        struct point { int x; int y; };
+        enum color { RED, GREEN = 4 };
+        int printf(const char *fmt, ...);
+        int helper(int, char **, size_t);
+        int f(int a, char **argv) {
+            int grid[2][3];
+            struct point p = {1, 2};
+            a = (a + 1) * -(-a) - (a - 1) - sizeof(size_t);
+            a = a ? a, 1 : (a = 2);
+            if (a) if (a > 1) return 1; else return 2;
+            if (a) { return 3; } else if (a == 1) return 4; else { a = 5; }
+            switch (a) { case 1: case 2: break; default: goto done; }
+        done:
+            return p.x + (&p)->y + argv[0][1] + (int)'\n' + printf("%d\n", a, (a, 2));
+        }
+    "#;
+    assert_round_trip(source);
+    let printed = printer::print_program(&parse(source));
+    assert!(printed.contains("int helper(int, char **, size_t);"));
+    assert!(printed.contains("a = (a + 1) * -(-a) - (a - 1) - sizeof(size_t);"));
+    assert!(printed.contains("    } else if (a == 1)\n        return 4;\n    else {\n"));
+    assert!(printed.contains("    case 1:\n    case 2:\n        break;\n"));
+}
+
+fn ty() -> impl Strategy<Value = Type> {
+    prop_oneof![
+        Just(Type::Int),
+        Just(Type::UnsignedChar),
+        Just(Type::UnsignedLong),
+        Just(Type::Double),
+        Just(Type::pointer_to(Type::Int)),
+        Just(Type::pointer_to(Type::pointer_to(Type::Char))),
+        Just(Type::Typedef("T".to_string())),
+        Just(Type::Struct(Box::new(StructType {
+            kind: StructKind::Struct,
+            tag: Some("point".to_string()),
+            members: None,
+            id: None,
+        }))),
+    ]
+}
+
+fn expr(kind: ExprKind) -> Expr {
+    Expr::new(kind, Span::default())
+}
+
+fn boxed(e: Expr) -> Box<Expr> {
+    Box::new(e)
+}
+
+fn leaf() -> impl Strategy<Value = Expr> {
+    let integer = prop_oneof![
+        Just(Type::Int),
+        Just(Type::UnsignedInt),
+        Just(Type::Long),
+        Just(Type::UnsignedLong),
+    ];
+    let float = prop_oneof![Just(Type::Float), Just(Type::Double)];
+    prop_oneof![
+        (0u64..100_000, integer).prop_map(|(value, ty)| expr(ExprKind::Const(value, ty))),
+        (
+            prop::sample::select(vec![0.5, 1.0, 2.25, 1e10, 0.001]),
+            float
+        )
+            .prop_map(|(value, ty)| expr(ExprKind::FloatConst(value, ty))),
+        any::<u8>().prop_map(|c| expr(ExprKind::Char(c))),
+        prop::collection::vec(any::<u8>(), 0..6).prop_map(|s| expr(ExprKind::String(s))),
+        prop::sample::select(vec!["a", "b", "p"]).prop_map(|v| expr(ExprKind::Var(v.into()))),
+    ]
+}
+
+fn any_expr() -> impl Strategy<Value = Expr> {
+    let unary = prop_oneof![
+        Just(UnaryOp::Negate),
+        Just(UnaryOp::BitwiseNegate),
+        Just(UnaryOp::Negative),
+    ];
+    let binary = prop::sample::select(vec![
+        BinaryOp::Add,
+        BinaryOp::Subtract,
+        BinaryOp::Multiply,
+        BinaryOp::Divide,
+        BinaryOp::Equal,
+        BinaryOp::NotEqual,
+        BinaryOp::Less,
+        BinaryOp::LessEqual,
+        BinaryOp::Greater,
+        BinaryOp::GreaterEqual,
+        BinaryOp::LogicalAnd,
+        BinaryOp::LogicalOr,
+    ]);
+    let compound = prop::sample::select(vec![
+        BinaryOp::Add,
+        BinaryOp::Subtract,
+        BinaryOp::Multiply,
+        BinaryOp::Divide,
+    ]);
+    leaf().prop_recursive(5, 48, 3, move |e| {
+        prop_oneof![
+            (unary.clone(), e.clone()).prop_map(|(op, a)| expr(ExprKind::Unary(op, boxed(a)))),
+            (binary.clone(), e.clone(), e.clone()).prop_map(|(op, a, b)| expr(ExprKind::Binary(
+                op,
+                boxed(a),
+                boxed(b)
+            ))),
+            e.clone().prop_map(|a| expr(ExprKind::Group(boxed(a)))),
+            (e.clone(), e.clone())
+                .prop_map(|(a, b)| expr(ExprKind::Assignment(boxed(a), boxed(b)))),
+            (compound.clone(), e.clone(), e.clone())
+                .prop_map(|(op, a, b)| expr(ExprKind::CompoundAssignment(op, boxed(a), boxed(b)))),
+            (e.clone(), e.clone(), e.clone()).prop_map(|(a, b, c)| expr(ExprKind::Conditional(
+                boxed(a),
+                boxed(b),
+                boxed(c)
+            ))),
+            e.clone().prop_map(|a| expr(ExprKind::AddressOf(boxed(a)))),
+            e.clone().prop_map(|a| expr(ExprKind::Deref(boxed(a)))),
+            (e.clone(), e.clone()).prop_map(|(a, b)| expr(ExprKind::Subscript(boxed(a), boxed(b)))),
+            prop::collection::vec(e.clone(), 0..3)
+                .prop_map(|args| expr(ExprKind::Call("g".into(), args))),
+            (ty(), e.clone()).prop_map(|(t, a)| expr(ExprKind::Cast(t, boxed(a)))),
+            e.clone()
+                .prop_map(|a| expr(ExprKind::Member(boxed(a), "x".into()))),
+            e.clone()
+                .prop_map(|a| expr(ExprKind::PtrMember(boxed(a), "y".into()))),
+            e.clone().prop_map(|a| expr(ExprKind::SizeOf(boxed(a)))),
+            ty().prop_map(|t| expr(ExprKind::SizeOfType(t))),
+            ty().prop_map(|t| expr(ExprKind::AlignOf(t))),
+            (e.clone(), e.clone()).prop_map(|(a, b)| expr(ExprKind::Comma(boxed(a), boxed(b)))),
+        ]
+    })
+}
+
+fn statement(kind: StatementKind) -> Statement {
+    Statement::new(kind, Span::default())
+}
+
+fn label() -> impl Strategy<Value = String> {
+    prop::sample::select(vec!["l1", "l2"]).prop_map(String::from)
+}
+
+// a statement, which unlike a block item cannot be a declaration
+fn any_statement() -> BoxedStrategy<Statement> {
+    let simple = prop_oneof![
+        any_expr().prop_map(|e| statement(StatementKind::Expr(e))),
+        any_expr().prop_map(|e| statement(StatementKind::Return(e))),
+        Just(statement(StatementKind::Break)),
+        label().prop_map(|l| statement(StatementKind::Goto(l))),
+        Just(statement(StatementKind::Empty)),
+    ];
+    simple
+        .prop_recursive(4, 24, 4, |s| {
+            let boxed = |s: Statement| Box::new(s);
+            prop_oneof![
+                (any_expr(), s.clone(), prop::option::of(s.clone())).prop_map(move |(c, t, e)| {
+                    statement(StatementKind::If(c, boxed(t), e.map(boxed)))
+                }),
+                (any_expr(), s.clone())
+                    .prop_map(move |(v, b)| statement(StatementKind::Switch(v, boxed(b)))),
+                (any_expr(), s.clone())
+                    .prop_map(move |(v, b)| statement(StatementKind::Case(v, boxed(b)))),
+                s.clone()
+                    .prop_map(move |b| statement(StatementKind::Default(boxed(b)))),
+                (label(), s.clone())
+                    .prop_map(move |(l, b)| statement(StatementKind::Labeled(l, boxed(b)))),
+                prop::collection::vec(block_item(s.clone()), 0..4)
+                    .prop_map(|items| statement(StatementKind::Compound(items))),
+            ]
+        })
+        .boxed()
+}
+
+fn block_item(
+    statement_strategy: impl Strategy<Value = Statement> + Clone,
+) -> impl Strategy<Value = Statement> {
+    let initializer = prop_oneof![
+        any_expr().prop_map(Initializer::Single),
+        prop::collection::vec(any_expr().prop_map(Initializer::Single), 0..3)
+            .prop_map(|items| Initializer::List(items, Span::default())),
+    ];
+    let name = prop::sample::select(vec!["a", "c", "point"]).prop_map(String::from);
+    prop_oneof![
+        3 => statement_strategy,
+        1 => (ty(), name, prop::option::of(initializer))
+            .prop_map(|(t, n, i)| statement(StatementKind::Declare(t, n, i))),
+    ]
+}
+
+fn any_program() -> impl Strategy<Value = Program> {
+    (
+        prop::collection::vec(block_item(any_statement()), 0..6),
+        any::<bool>(),
+    )
+        .prop_map(|(items, variadic)| {
+            let point = StructType {
+                kind: StructKind::Struct,
+                tag: Some("point".to_string()),
+                members: Some(vec![
+                    Member {
+                        member_type: Type::Int,
+                        name: "x".to_string(),
+                        span: Span::default(),
+                    },
+                    Member {
+                        member_type: Type::array_of(Type::Long, 2),
+                        name: "y".to_string(),
+                        span: Span::default(),
+                    },
+                ]),
+                id: None,
+            };
+            let param = |param_type, name: &str| FuncParam {
+                param_type,
+                param_name: name.to_string(),
+                span: Span::default(),
+            };
+            Program {
+                declarations: vec![
+                    statement(StatementKind::DeclareType(Type::Struct(Box::new(point)))),
+                    statement(StatementKind::Typedef(Type::Int, "T".to_string())),
+                ],
+                functions: vec![Func {
+                    return_type: Type::pointer_to(Type::Int),
+                    name: "f".to_string(),
+                    block_items: Some(items),
+                    params: vec![param(Type::Int, "a"), param(Type::Double, "b")],
+                    variadic,
+                    span: Span::default(),
+                }],
+            }
+        })
+}
+
+proptest! {
+    #[test]
+    fn parse_print_parse(program in any_program()) {
+        let source = printer::print_program(&program);
+        let parsed = parse(&source);
+        let printed = printer::print_program(&parsed);
+        let reparsed = parse(&printed);
+        prop_assert_eq!(shape(&parsed), shape(&reparsed), "printed as\n{}", printed);
+    }
+}
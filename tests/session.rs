@@ -0,0 +1,103 @@
+//! The library entry points: `compile` with its `Output` or `Diagnostics`, and the stages of a
+//! `Session` run one at a time.
+
+use compiler::diagnostics::Severity;
+use compiler::{Options, Session};
+
+fn linux() -> Options {
+    Options {
+        target: "linux".parse().unwrap(),
+        ..Options::default()
+    }
+}
+
+#[test]
+fn compile_returns_the_assembly_and_its_warnings() {
+    let source = "int main(void) {\n    int x;\n    return x;\n}\n";
+    let output = compiler::compile(source, &linux()).unwrap();
+    assert!(
+        output.asm.lines().any(|line| line == "main:"),
+        "{}",
+        output.asm
+    );
+    let warnings: Vec<_> = output.diagnostics.iter().collect();
+    assert_eq!(warnings.len(), 1);
+    let warning = warnings[0];
+    assert_eq!(warning.severity, Severity::Warning);
+    assert_eq!(warning.code, "W0001");
+    // the spans point into the preprocessed source the output keeps
+    let text = &output.source_map.text;
+    assert_eq!(&text[warning.span.start..warning.span.end], "x");
+    assert_eq!(output.source_map.locate(warning.span.start).1, 3);
+    assert!(output
+        .source_map
+        .render(&output.diagnostics)
+        .contains("W0001"));
+}
+
+#[test]
+fn compile_reports_every_error() {
+    let source = "int main(void) {\n    int *p = 1.5;\n    return y;\n}\n";
+    let Err(diagnostics) = compiler::compile(source, &linux()) else {
+        panic!("compiled");
+    };
+    let codes: Vec<_> = diagnostics.iter().map(|d| d.code).collect();
+    assert_eq!(codes, ["E0006", "E0002"]);
+    assert_eq!(diagnostics.error_count(), 2);
+    assert!(diagnostics.iter().all(|d| d.severity == Severity::Error));
+}
+
+#[test]
+fn preprocessor_and_syntax_errors_stop_the_pipeline() {
+    let Err(diagnostics) = compiler::compile("#error stop\nint main(void) {}", &linux()) else {
+        panic!("compiled");
+    };
+    let codes: Vec<_> = diagnostics.iter().map(|d| (d.code, d.span.start)).collect();
+    assert_eq!(codes, [("E0034", 0)]);
+
+    let Err(diagnostics) = compiler::compile("int main(void) { return 0", &linux()) else {
+        panic!("compiled");
+    };
+    assert!(diagnostics.iter().all(|d| d.code == "E0001"));
+    assert!(diagnostics.has_errors());
+}
+
+#[test]
+fn session_stages() {
+    let options = Options {
+        defines: vec![("ANSWER".to_string(), "42".to_string())],
+        ..linux()
+    };
+    let mut session = Session::new(options);
+    let text = session
+        .preprocess("answer.c", "int main(void) { return ANSWER; }")
+        .unwrap();
+    assert_eq!(
+        text.lines().last(),
+        Some("int main(void) { return 42    ; }")
+    );
+    assert_eq!(session.source_map().name, "answer.c");
+    assert_eq!(session.tokens().len(), 10);
+
+    let mut program = session.parse().unwrap();
+    assert_eq!(program.functions.len(), 1);
+    let layouts = session.analyze(&mut program);
+    assert!(!session.has_errors());
+    let asm = session.generate(&program, layouts);
+    assert!(asm.contains("mov"), "{asm}");
+    assert!(session.into_diagnostics().is_empty());
+}
+
+#[test]
+fn session_keeps_the_diagnostics_of_each_stage() {
+    let mut session = Session::new(linux());
+    session
+        .preprocess("bad.c", "int main(void) { return z; }")
+        .unwrap();
+    let mut program = session.parse().unwrap();
+    assert!(!session.has_errors());
+    session.analyze(&mut program);
+    assert!(session.has_errors());
+    let codes: Vec<_> = session.diagnostics().iter().map(|d| d.code).collect();
+    assert_eq!(codes, ["E0002"]);
+}